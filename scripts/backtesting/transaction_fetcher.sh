@@ -6,53 +6,216 @@
 
 set -eo pipefail
 
-# Default values
-OUTPUT_FORMAT="simple"
-BATCH_SIZE=10
-MAX_CONCURRENT=5
-DETAILED_BLOCKS=false
-USE_TRACE_FILTER=false
-TRACE_FILTER_BATCH_SIZE=100
-TRACE_METHOD=""
-TEMP_DIR=""
-START_TIME=""
-
-# RPC call counter files (for aggregating across subprocesses)
-RPC_COUNTER_DIR=""
-
-# Cleanup function
-cleanup() {
-    if [[ -n "$TEMP_DIR" && -d "$TEMP_DIR" ]]; then
-        rm -rf "$TEMP_DIR"
-    fi
-}
-
-# Set up cleanup on exit
-trap cleanup EXIT
-
-# Create temporary directory for parallel processing
-TEMP_DIR=$(mktemp -d)
-RPC_COUNTER_DIR="$TEMP_DIR/rpc_counters"
-mkdir -p "$RPC_COUNTER_DIR"
+# The fetching logic lives in the library; this script is the command-line front end
+source "$(dirname "${BASH_SOURCE[0]}")/transaction_fetcher_lib.sh"
 
 # Usage function
 usage() {
     cat << EOF
-Usage: $0 [OPTIONS]
+Usage: $0 [fetch] [OPTIONS]
+       $0 benchmark [OPTIONS] [-- FETCH_OPTIONS...]
 
-Fetches blockchain transactions for backtesting.
+Fetches blockchain transactions for backtesting. fetch is the default command; benchmark
+measures throughput at several concurrency settings (see $0 benchmark --help).
 
 OPTIONS:
-    --rpc-url URL                  RPC endpoint URL (required)
-    --target-contract ADDRESS      Contract address to filter transactions for (required)
-    --start-block NUMBER           Starting block number (required)
-    --end-block NUMBER             Ending block number (required)
-    --output-format FORMAT         Output format: simple or json (default: simple)
-    --batch-size SIZE              Batch size for processing (default: 10)
-    --max-concurrent COUNT         Maximum concurrent requests (default: 5)
+    --config PATH                  Read options from the TOML file PATH (see CONFIG FILE); options
+                                   on the command line override it
+    --print-config                 Print the options in effect (file and command line merged) as
+                                   TOML and exit
+    --rpc-url URL                  RPC endpoint URL (repeatable or comma-separated; required unless
+                                   CREDIBLE_RPC_URL or --input-file is set); http(s)://, ws(s)://
+                                   or ipc:///path/to/geth.ipc
+    --target-contract ADDRESS      Contract address to filter transactions for (required unless
+                                   --contracts-file, CREDIBLE_TARGET_CONTRACTS or --input-file is given;
+                                   repeatable or comma-separated to match several contracts in one pass)
+    --contracts-file PATH          Read more target addresses from PATH, one per line ("#" starts a
+                                   comment, blank lines are skipped)
+    --strict-checksum              Require every target address to use EIP-55 checksum casing
+                                   (needs OpenSSL 3.2+ or cast for keccak-256)
+    --start-block NUMBER|TAG       Starting block number or tag (latest, earliest, safe, finalized)
+                                   (required unless --start-timestamp is given)
+    --end-block NUMBER|TAG         Ending block number or tag (required unless --end-timestamp is given)
+    --start-timestamp SECONDS      Start at the first block with timestamp >= SECONDS (unix time)
+    --end-timestamp SECONDS        End at the last block with timestamp <= SECONDS (unix time)
+    --blocks-file PATH             Fetch exactly the blocks listed in PATH (one per line or
+                                   comma-separated, "#" starts a comment) instead of a range;
+                                   not with --start-block, --end-block or timestamps
+    --confirmations N              Don't fetch the N most recent blocks (the end block is clamped
+                                   to latest - N), keeping the range clear of likely reorgs
+    --max-blocks COUNT             Refuse a range of more than COUNT blocks, which is more likely a
+                                   mistyped block number than intended (default: 100000)
+    --yes                          Fetch a range larger than --max-blocks anyway
+    --verify-chain                 Check that every fetched block's parentHash is the hash of the
+                                   block before it and refetch a batch that doesn't link up (fails
+                                   after --max-retries refetches); not with --use-trace-filter
+    --follow                       After the range, keep streaming the matches of every new block
+                                   as newHeads announces it, until interrupted (needs a ws(s)://
+                                   --rpc-url and --output-format ndjson; implies --stream)
+    --input-file PATH              Re-filter the transactions of an earlier json or ndjson output
+                                   (gzip-compressed too) instead of fetching: no --rpc-url is used,
+                                   targets are optional and the range defaults to the file's blocks;
+                                   needs hex quantities (the default --numeric-format)
+    --headers-only                 Fetch only block headers (eth_getBlockByNumber without
+                                   transactions) and output one record per block: number, hash,
+                                   timestamp, base fee (null, or an empty csv field, before
+                                   London), gas used and gas limit; no targets needed, json,
+                                   ndjson or csv output
+    --output-format FORMAT         Output format: simple, json, ndjson, csv, abi or parquet
+                                   (default: simple)
+    --numeric-format FORMAT        hex or decimal strings for value, gas_price, gas_limit
+                                   (--headers-only), max_fee_per_gas, max_priority_fee_per_gas,
+                                   gas_used, effective_gas_price and gas_cost_wei in the simple,
+                                   json, ndjson and csv output and --sqlite (default: hex, as the
+                                   node returns them)
+    --omit-data                    Leave the calldata out of json, ndjson, csv and parquet output,
+                                   keeping only its size (data_len) and has_data; not with the
+                                   simple or abi formats or --sqlite
+    --checksum-addresses           Write from, to and matched_contract in EIP-55 checksum casing
+                                   instead of as the node returns them (usually lowercase); matching
+                                   ignores case either way (needs OpenSSL 3.2+ or cast for keccak-256)
+    --hashes-only                  Output only the matching transaction hashes, one per line, or as
+                                   a JSON array with --output-format json; the default simple
+                                   format becomes ndjson (one hash per line, no CSTDv1 header),
+                                   and no enrichment applies
+    --output-file PATH             Write the payload to PATH (parent dirs are created) instead of
+                                   stdout; a short summary goes to stderr
+    --split-every COUNT            Write the --output-file payload as numbered files of at most COUNT
+                                   transactions each, in order (out.ndjson becomes out-00001.ndjson,
+                                   out-00002.ndjson, ...); not with --stream
+    --stats-json PATH              Also write the run statistics (counts, timing, retries, failed
+                                   and missing blocks, per-endpoint requests) to PATH as JSON, even
+                                   when the run fails
+    --gaps-file PATH               Also write the blocks of the range missing from the output (failed,
+                                   or never fetched after an interrupt or --max-results) to PATH, one
+                                   per line for a follow-up run with --blocks-file; empty when the
+                                   output is complete, and written even when the run fails
+    --selector-stats               Also break the matches down by 4-byte selector (count and total
+                                   value per function, named with --abi) on stderr and in --stats-json
+    --manifest PATH                Also write a JSON manifest of the dataset to PATH: endpoints and
+                                   header names (no credentials), chain id, range, targets, filters, format, tool
+                                   version, transaction count and the output's SHA-256 (needs
+                                   --output-file or --sqlite)
+    --sqlite PATH                  Insert the transactions into table "transactions" of the SQLite
+                                   database PATH (one SQL transaction per batch, rows keyed by hash,
+                                   indexed by block and index) instead of writing a payload
+    --compress gzip                Gzip the --output-file payload (streamed batches are appended as
+                                   gzip members; gzip -d reads them back as one file)
+    --compress-stdout              With --compress, also gzip a stdout payload, base64-encoded
+                                   between the TRANSACTION_DATA markers (not readable by
+                                   BacktestingUtils; for other consumers)
+    --checkpoint-file PATH         Save progress after every batch and, when PATH holds a checkpoint
+                                   for the same range, targets and filters, resume from it (records
+                                   are kept in PATH.records; delete both to start over)
+    --since-last-run               Fetch from the block after the --checkpoint-file checkpoint (or
+                                   from --start-block when there is none yet) up to latest -
+                                   --confirmations, output only those blocks' transactions and
+                                   advance the checkpoint, so a recurring job (e.g. cron) can append
+                                   each run's output to a dataset
+    --resume-output                Continue an --output-file that a --stream ndjson run left partly
+                                   written (crashed or killed) instead of starting it over: the file's
+                                   --manifest must match this run's chain, range, targets, filters and
+                                   format, then the scan picks up after the last complete block and
+                                   appends (needs --manifest, which is also written before the scan)
+    --cache-dir PATH               Cache fetched blocks under PATH/<chain id>/blocks and reuse them on
+                                   later runs (only finalized blocks are cached; block scans only)
+    --chain-id ID                  Chain id for the cache path (default: asked from the node)
+    --expect-chain-id ID           Stop before scanning if the node's eth_chainId isn't ID, so a
+                                   backtest can't silently run against the wrong network
+    --no-cache                     Ignore --cache-dir for this run
+    --sample-rate RATE             Keep only this share (0-1] of the matches, picked by transaction
+                                   hash so the same sample comes back on every run (default: 1)
+    --max-results COUNT            Stop starting new block fetches once COUNT matches are kept and
+                                   return the first COUNT by block and index
+    --count-only                   Scan the range but only print the number of matching transactions
+                                   (their spread over the blocks goes to stderr) instead of the payload
+    --stream                       Flush each batch (sorted by block and index) as soon as it
+                                   completes; needs json, ndjson or csv output
+    --output-buffer BATCHES        Completed batches that may wait for the --stream or --sqlite
+                                   writer before the scan pauses for it (default: 4)
+    --max-pending-blocks COUNT     Blocks a block scan may fetch ahead of the next one to output, in
+                                   flight or finished and waiting (default: 100)
+    --batch-size SIZE              Blocks per batch, the step in which results are streamed,
+                                   checkpointed and deduplicated and the size of a jsonrpc-batch
+                                   request (default: 10)
+    --batch-timeout SECONDS        Give a block-scan batch at most SECONDS; blocks still unfinished
+                                   then are abandoned and reported as failed, so a silent
+                                   connection can't stall the run (default: 0 = no limit)
+    --max-concurrent COUNT         Maximum concurrent requests (default: 5, or 20 with
+                                   --adaptive-concurrency)
+    --adaptive-concurrency         Start at --min-concurrent and tune concurrency after every batch:
+                                   +1 while latency stays healthy, halved on 429s or timeouts
+    --min-concurrent COUNT         Lower bound for --adaptive-concurrency (default: 1)
+    --batch-mode MODE              Block fetching: parallel or jsonrpc-batch (default: parallel)
+    --selector [ADDRESS:]SELECTOR  Only keep txs whose calldata starts with this 4-byte selector
+                                   (e.g. 0xa9059cbb; repeatable or comma-separated). Prefixed with a
+                                   target (0xROUTER:0x128acb08) it applies to that target's matches
+                                   only, on top of the unprefixed ones; a target with no selectors
+                                   of either kind keeps every call
+    --exclude-from ADDRESS         Drop matches sent from this address, e.g. the target itself or
+                                   privileged accounts (repeatable or comma-separated)
+    --min-value WEI                Only keep txs sending at least this much ETH (decimal or 0x hex wei)
+    --nonzero-value                Drop txs that send no ETH (--min-value 1 unless it's higher)
+    --max-value WEI                Only keep txs sending at most this much ETH (decimal or 0x hex wei)
+    --min-gas-price WEI            Only keep txs paying at least this gas price (decimal or 0x hex wei)
+    --max-gas-price WEI            Only keep txs paying at most this gas price (decimal or 0x hex wei);
+                                   type-2 txs without a gas price are checked on their
+                                   effective_gas_price with --with-receipts, otherwise kept
+    --match-by MODE                Block scan matching: to (tx.to only), logs (also txs whose
+                                   logs were emitted by a target) or traces (also txs that call a
+                                   target internally, from trace_block or debug_traceBlockByNumber
+                                   per block, whichever the node supports) (default: to)
+    --header "NAME: VALUE"         Send this HTTP header with every RPC request, e.g. an
+                                   Authorization or API-key header (repeatable); values are never
+                                   logged, and --manifest records only the names
+    --rpc-failure-threshold COUNT  Consecutive failures before an endpoint is demoted (default: 3)
+    --rpc-cooldown-secs SECONDS    How long a demoted endpoint is skipped (default: 30)
+    --max-rps RATE                 Global cap on RPC requests per second (default: 0 = unlimited)
+    --proxy URL                    Reach http(s):// endpoints through this proxy (default: HTTPS_PROXY
+                                   or HTTP_PROXY, minus NO_PROXY hosts)
+    --ca-cert PATH                 Verify https:// endpoints and proxies with the CA certificates in
+                                   PATH (PEM) instead of the system store
+    --include-creations            Include contract-creation txs (to == null) that deploy a target
+                                   or are otherwise matched; emitted with an empty "to". Their input
+                                   is init code, so --selector doesn't filter them
+    --include-pending              Keep matched transactions the node lists without a
+                                   transactionIndex (pending, at the chain tip) instead of skipping
+                                   them with a warning and without a receipt check. In json and
+                                   ndjson they carry "pending": true and a null transaction_index
+                                   and order_key; csv has neither column, so a pending row is the
+                                   one with an empty transaction_index (json, ndjson or csv only)
+    --with-receipts                Add receipt fields (status, gas_used, effective_gas_price, logs)
+                                   and the fee paid (gas_cost_wei) to each transaction in json output
+    --with-state-diff              Add each tx's state diff (prestateTracer in diffMode, one
+                                   debug_traceTransaction per tx; needs a debug-enabled archive node)
+    --with-call-trace              Add each tx's callTracer frame (nested calls included); kept from
+                                   the block traces with --match-by traces on Geth, otherwise one
+                                   debug_traceTransaction per tx
+    --with-access-list             Add each tx's access list: the declared EIP-2930 list, or else
+                                   one derived with eth_createAccessList on the parent block
+    --skip-unavailable-state       Leave out the transactions of blocks whose state the endpoint no
+                                   longer has (a full node asked about old blocks) instead of
+                                   failing; for --with-state-diff, --with-call-trace and
+                                   --with-access-list
+    --abi PATH                     Decode calldata with this contract ABI (JSON array or a Foundry
+                                   artifact) into decoded_function (needs OpenSSL 3.2+ or cast)
     --use-trace-filter             Use traces for internal call detection (trace_filter w/ debug_trace* fallback)
     --trace-filter-batch-size SIZE Batch size for trace_filter (default: 100)
+    --quiet                        No progress output: no progress bar (it is only drawn when stdout
+                                   and stderr are terminals, never under Foundry's ffi) and only
+                                   warnings and errors unless --log-level is given
+    --json-logs                    Write each stderr line as a JSON object with time, level, message
+                                   and fields (e.g. batch, start_block) for log aggregators; no
+                                   progress bar
+    --log-level LEVEL              Most verbose messages written to stderr: error, warn, info
+                                   (batch progress and summaries) or debug (also per-block matches
+                                   and retries) (default: info, or CREDIBLE_LOG_LEVEL)
     --detailed-blocks              Enable detailed per-block summaries (default: false)
+    --max-retries COUNT            Retries per RPC request/block after the first attempt (default: 5;
+                                   0 to never retry)
+    --retry-base-delay-ms MS       Initial retry backoff, doubled per attempt (default: 2000)
+    --fail-on-error                Exit non-zero if any block fails after retries (default)
+    --no-fail-on-error             Skip failed blocks and only print a warning summary
     -h, --help                     Show this help message
 
 EXAMPLES:
@@ -62,6 +225,27 @@ EXAMPLES:
        --start-block 10000000 \\
        --end-block 10000100
 
+    # Also catch calls routed through aggregators/proxies that make the target emit events
+    $0 --rpc-url \$MAINNET_RPC_URL \\
+       --target-contract 0xBA12222222228d8Ba445958a75a0704d566BF2C8 \\
+       --start-block 23717632 \\
+       --end-block 23717700 \\
+       --match-by logs
+
+    # Also catch internal calls, tracing each block (trace_block on OpenEthereum/Nethermind/Erigon,
+    # debug_traceBlockByNumber with callTracer on Geth)
+    $0 --rpc-url \$MAINNET_RPC_URL \\
+       --target-contract 0xBA12222222228d8Ba445958a75a0704d566BF2C8 \\
+       --start-block 23717632 \\
+       --end-block 23717700 \\
+       --match-by traces
+
+    # Fetch a time window instead of a block range (resolved by binary search over block headers)
+    $0 --rpc-url \$MAINNET_RPC_URL \\
+       --target-contract 0xBA12222222228d8Ba445958a75a0704d566BF2C8 \\
+       --start-timestamp 1762300800 \\
+       --end-timestamp 1762304400
+
     # Use trace-based detection (trace_filter or debug_trace* fallback)
     $0 --rpc-url \$MAINNET_RPC_URL \\
        --target-contract 0xBA12222222228d8Ba445958a75a0704d566BF2C8 \\
@@ -70,1079 +254,1730 @@ EXAMPLES:
        --use-trace-filter
 
 PERFORMANCE:
-    --batch-size: Blocks to process in parallel (default: 10, try 10-50)
+    --batch-size: Blocks per batch (default: 10, try 10-50). Block scans keep
+        --max-concurrent requests in flight across batch boundaries, up to one
+        batch ahead, so a slow block doesn't stall the blocks after it (except
+        with --max-results, which fetches batch by batch to stop at the limit)
     --max-concurrent: Concurrent RPC requests (default: 5, try 5-20)
+    --adaptive-concurrency: For endpoints with unknown limits; the concurrency
+        is adjusted after every batch
+    --max-rps: Throttles all requests across concurrent workers to this rate,
+        useful on free tiers that answer bursts with 429s (default: unlimited)
+    --batch-mode jsonrpc-batch: Packs each batch of blocks into a single JSON-RPC
+        array request instead of one request per block (fewer HTTP round trips)
     --trace-filter-batch-size: Blocks per trace_filter call (default: 100)
-
-    Balance these based on your RPC provider's rate limits.
+    --match-by logs: The logs of the whole range are requested up front with one
+        eth_getLogs; a range the provider rejects as too large is halved until
+        it is accepted (the chunks are logged with --log-level debug)
+    --output-buffer: With --stream or --sqlite a background writer writes the
+        batches while the next ones are fetched; when it falls this many batches
+        behind, fetching pauses, so a slow sink (gzip, SQLite) bounds memory and
+        disk use instead of letting the fetch run away (default: 4)
+    --max-pending-blocks: Block scans fetch up to a batch ahead, and a block
+        that finishes before the ones ahead of it in the output waits with its
+        matches until they are done. This caps how many blocks may be in flight
+        or waiting at once: a higher cap keeps more requests busy behind a slow
+        block, a lower one bounds memory and temp space on dense blocks (a
+        mainnet DEX router with full calldata). Below --max-concurrent it also
+        caps concurrency (default: 100)
+
+    Balance these based on your RPC provider's rate limits. $0 benchmark
+    fetches a small range at each of a grid of --max-concurrent and --batch-size
+    values and recommends the fastest for your endpoint.
+
+MULTIPLE ENDPOINTS:
+    Pass --rpc-url several times (or a comma-separated list) to spread requests
+    round-robin across endpoints. An endpoint that times out, returns garbage or
+    rate-limits --rpc-failure-threshold times in a row is skipped for
+    --rpc-cooldown-secs, then tried again.
+
+FOLLOWING THE TIP:
+    --follow subscribes to newHeads on the first ws(s):// or ipc:// endpoint once the range
+    is done and scans up to each announced head (minus --confirmations), so the
+    stream becomes a live feed. A dropped subscription is reopened (up to
+    --max-retries times in a row) and the blocks missed meanwhile are scanned on
+    the next head, without repeating a transaction. SIGINT or SIGTERM closes the
+    stream after the blocks in progress.
+
+WEBSOCKET ENDPOINTS:
+    ws:// and wss:// URLs are served over one persistent WebSocket connection
+    per endpoint (through websocat), shared by all parallel requests, instead of
+    an HTTP request each. The connection is reopened if it drops.
+
+IPC ENDPOINTS:
+    ipc:// URLs name the unix socket of a local node (ipc:///data/geth/geth.ipc).
+    Requests go over one persistent connection per socket (through socat) as
+    newline-delimited JSON-RPC, like a WebSocket endpoint but without HTTP or
+    TLS overhead: the fastest transport against a co-located node.
+
+INTERRUPTING:
+    Ctrl-C (SIGINT) or SIGTERM during the scan stops it after the last completed
+    batch: the matches found so far are still written (or streamed, or inserted
+    with --sqlite) and checkpointed, the statistics cover the blocks scanned,
+    and the fetcher exits with status 130. With --checkpoint-file the same
+    command resumes where it stopped.
+
+SAMPLING:
+    With --sample-rate or --max-results the "found N transactions" figures (and
+    transactions_found in --stats-json) count the kept matches, not all matches
+    in the range, and "Processed N blocks" is the requested range even when
+    --max-results stopped early. Trace-based detection (--use-trace-filter)
+    only stops between batches.
+
+CONFIG FILE:
+    Every option can be set in the --config file as key = value, the key being the
+    option name with underscores or dashes. Strings and numbers are values, true
+    turns a flag on, false leaves it off (cache = false and fail_on_error = false
+    pick --no-cache and --no-fail-on-error), and an array repeats the option.
+    [section] headers only group keys; arrays must fit on one line:
+
+        rpc_url = "https://archive.internal:8545"
+        target_contract = ["0x...", "0x..."]
+
+        [range]
+        start_block = 19000000
+        end_block = 19010000
+
+        [filters]
+        selector = "0xa9059cbb"
+        min_value = "1000000000000000000"
+
+        [output]
+        output_format = "ndjson"
+        output_file = "data/transfers.ndjson"
+
+ENVIRONMENT:
+    CREDIBLE_RPC_URL               Endpoint(s) used when no --rpc-url is given. Prefer it for URLs
+                                   that embed an API key: arguments show up in shell history and ps
+    CREDIBLE_TARGET_CONTRACTS      Comma-separated targets used when neither --target-contract nor
+                                   --contracts-file is given
+    CREDIBLE_LOG_LEVEL             Log level used when no --log-level is given
+    HTTPS_PROXY, HTTP_PROXY        Proxy for https:// and http:// endpoints when no --proxy is given;
+                                   NO_PROXY lists hosts to reach directly
+
+RETRIES:
+    Failed requests are retried with exponential backoff plus jitter
+    (base, 2*base, 4*base, ... capped at 64s). A block is only reported as
+    failed once it has been retried --max-retries times.
 
 OUTPUT:
-    simple: count|hash|from|to|value|data|blockNumber|txIndex|gasPrice|gasLimit|maxFeePerGas|maxPriorityFeePerGas|...
-    json:   Array of transaction objects with labeled fields
+    simple: CSTDv1:count|hash|from|to|value|data|blockNumber|txIndex|gasPrice|gasLimit|maxFeePerGas|maxPriorityFeePerGas|...
+            (an empty result is CSTDv1:0; gasPrice is empty when the node
+            does not report it; gasLimit is decimal)
+            ("%" and "|" inside a field are percent-encoded as %25 and %7C)
+    json:   Array of transaction objects (an empty result is []) with labeled
+            fields, including matched_contract (the target the transaction was
+            matched against), is_creation (true for contract deployments),
+            tx_type, nonce and gas (the gas limit; decimal), block_timestamp
+            and base_fee_per_gas (decimal; null before London), data_len
+            (calldata size in bytes, decimal), has_data (false for plain
+            transfers) and order_key ("0x" + block_number and
+            transaction_index as 8-byte big-endian words, so the keys sort as
+            strings in replay order).
+            gas_price, max_fee_per_gas and max_priority_fee_per_gas are null
+            when the node does not report them (legacy transactions have no
+            EIP-1559 fees); --with-receipts adds status, gas_used,
+            effective_gas_price and logs from the receipt, and gas_cost_wei
+            (gas_used * effective_gas_price, exact); --with-state-diff adds
+            state_diff: {pre, post}, each mapping the changed accounts to their
+            balance, nonce, code and storage before and after the transaction;
+            --with-call-trace adds call_trace (the callTracer frame: type,
+            from, to, value, gas, gasUsed, input, output and nested calls);
+            --with-access-list adds access_list ([{address, storageKeys}]) and
+            access_list_source ("declared", "derived", or null when the node
+            could not derive one); --abi adds decoded_function: {name,
+            signature, args: [{name, type, value}]} (integers as decimal
+            strings), or null when the selector isn't in the ABI
+    ndjson: One JSON transaction object (same fields as json) per line, no enclosing array
+    csv:    Header row (hash,from,to,value,data,block_number,transaction_index,gas_price)
+            then one row per transaction; data is quoted and can be very long
+            (full calldata), so raise field-size limits in your CSV reader if needed;
+            --omit-data puts data_len in place of data
+    abi:    0x-prefixed ABI encoding of BacktestingTypes.TransactionData[], for
+            BacktestingUtils.decodeAbiTransactions (a single abi.decode); missing
+            fees are 0 and contract creations have a zero "to"
+    parquet: Columnar file for DuckDB/Polars, written with the duckdb CLI (0.10+)
+            and only to --output-file. Columns: hash, from_address, to_address,
+            data, value_hex, matched_contract (text); is_creation (bool);
+            value (u128); block_number, block_timestamp, base_fee_per_gas,
+            transaction_index, nonce, gas, gas_price, max_fee_per_gas,
+            max_priority_fee_per_gas, data_len (u64); has_data (bool); tx_type
+            (u8). Missing fees are null; --omit-data drops the data column
+
+    Every format lists transactions in replay order: by block_number, then
+    transaction_index (the order they executed in within the block). This is the
+    canonical order the backtest harness replays them in, so the index is never
+    guessed at: a block scan skips a transaction listed without a transactionIndex
+    as pending, with a warning (--include-pending keeps it, flagged pending in
+    json and ndjson and with an empty transaction_index in csv), and trace
+    discovery fails the run on a mined transaction the node returns without one.
 
 EOF
 }
 
-# Exponential backoff retry function
-# Usage: retry_with_backoff <max_retries> <curl_command...>
-retry_with_backoff() {
-    local max_retries="$1"
-    shift
-    local attempt=0
-    local response=""
-
-    while [[ $attempt -lt $max_retries ]]; do
-        # Execute the curl command
-        response=$("$@" 2>/dev/null || echo "")
-
-        # Check if response is empty
-        if [[ -z "$response" ]]; then
-            attempt=$((attempt + 1))
-            if [[ $attempt -lt $max_retries ]]; then
-                local wait_time
-                local jitter
-                local total_wait
-                local max_wait
-                local wait_seconds
-                wait_time=$((2 ** attempt))
-                jitter=$((RANDOM % 1000))
-                total_wait=$((wait_time * 1000 + jitter))
-                max_wait=64000
-                if [[ $total_wait -gt $max_wait ]]; then
-                    total_wait=$max_wait
+# Fetch command (the default)
+fetch_main() {
+    # Targets as given (original casing, for --strict-checksum) and the lowercased, deduplicated set
+    local target_inputs=""
+    local target_contracts=""
+    local start_block=""
+    local end_block=""
+    local start_timestamp=""
+    local end_timestamp=""
+    local blocks_file=""
+    local cache_dir=""
+    local chain_id=""
+    local expect_chain_id=""
+    local no_cache=false
+    local quiet=false
+    local log_level_given=false
+    local max_concurrent_given=false
+    local stats_json=""
+    local gaps_file=""
+    local manifest=""
+    local selector_stats=false
+    local nonzero_value=false
+    local count_only=false
+    local abi_file=""
+    local input_file=""
+    local headers_only=false
+    local confirmations=0
+    local max_blocks=100000
+    local yes=false
+    local follow=false
+
+    LOG_LEVEL="${CREDIBLE_LOG_LEVEL:-info}"
+
+    # A --config file's options go before the command line's, and an option given on the command
+    # line drops the file's entries for it (all of them for repeatable options), so flags win
+    local config_file=""
+    local print_config=false
+    local cli_args=()
+    local cli_options=()
+    while [[ $# -gt 0 ]]; do
+        case $1 in
+            --config)
+                config_file="$2"
+                shift 2
+                ;;
+            --print-config)
+                print_config=true
+                shift
+                ;;
+            --*)
+                cli_options+=("$1")
+                cli_args+=("$1")
+                shift
+                # The option's value, if it takes one, is never mistaken for an option name
+                if [[ $# -gt 0 && "$1" != --* ]]; then
+                    cli_args+=("$1")
+                    shift
                 fi
-                wait_seconds=$(awk "BEGIN {print $total_wait/1000}")
-                sleep "$wait_seconds"
+                ;;
+            *)
+                cli_args+=("$1")
+                shift
+                ;;
+        esac
+    done
+    local config_args=()
+    if [[ -n "$config_file" ]]; then
+        if [[ ! -r "$config_file" ]]; then
+            log error "Error: --config file not found: $config_file"
+            exit 1
+        fi
+        local config_options
+        config_options=$(config_file_args "$config_file") || exit 1
+        local config_entry
+        local config_value
+        local config_option
+        while IFS=$'\t' read -r config_entry config_value; do
+            config_option="${config_entry#* }"
+            if [[ -z "$config_entry" || " ${cli_options[*]} " == *" $config_option "* ||
+                  " ${cli_options[*]} " == *" --no-${config_option#--} "* ]]; then
                 continue
             fi
-        fi
+            case "${config_entry%% *}" in
+                value)
+                    config_args+=("$config_option" "$config_value")
+                    ;;
+                off)
+                    # Only some flags have a --no- form; for the rest, off is the default
+                    case "$config_option" in
+                        --cache | --fail-on-error)
+                            config_args+=("--no-${config_option#--}")
+                            ;;
+                    esac
+                    ;;
+                *)
+                    config_args+=("$config_option")
+                    ;;
+            esac
+        done <<< "$config_options"
+    fi
+    set -- "${config_args[@]}" "${cli_args[@]}"
+    if [[ "$print_config" == "true" ]]; then
+        print_config_toml "$@"
+        exit 0
+    fi
 
-        # Check for 429 error
-        local error_code
-        error_code=$(echo "$response" | jq -r '.error.code // empty' 2>/dev/null)
-        if [[ "$error_code" == "429" ]]; then
-            attempt=$((attempt + 1))
-            if [[ $attempt -lt $max_retries ]]; then
-                # Exponential backoff: 2^n seconds + random jitter (0-1000ms)
-                local wait_time
-                local jitter
-                local total_wait
-                local max_wait
-                local wait_seconds
-                wait_time=$((2 ** attempt))
-                jitter=$((RANDOM % 1000))
-                total_wait=$((wait_time * 1000 + jitter))
-
-                # Cap at maximum backoff of 64 seconds
-                max_wait=64000
-                if [[ $total_wait -gt $max_wait ]]; then
-                    total_wait=$max_wait
+    # Parse command line arguments
+    while [[ $# -gt 0 ]]; do
+        case $1 in
+            --rpc-url)
+                rpc_pool_add "$2"
+                shift 2
+                ;;
+            --header)
+                rpc_header_add "$2" || exit 1
+                shift 2
+                ;;
+            --rpc-failure-threshold)
+                RPC_FAILURE_THRESHOLD="$2"
+                shift 2
+                ;;
+            --rpc-cooldown-secs)
+                RPC_COOLDOWN_SECS="$2"
+                shift 2
+                ;;
+            --max-rps)
+                MAX_RPS="$2"
+                shift 2
+                ;;
+            --proxy)
+                RPC_PROXY="$2"
+                shift 2
+                ;;
+            --ca-cert)
+                RPC_CA_CERT="$2"
+                shift 2
+                ;;
+            --target-contract)
+                target_inputs="${target_inputs:+$target_inputs,}$2"
+                shift 2
+                ;;
+            --contracts-file)
+                local file_contracts
+                file_contracts=$(read_contracts_file "$2") || exit 1
+                target_inputs="${target_inputs:+$target_inputs,}$file_contracts"
+                shift 2
+                ;;
+            --strict-checksum)
+                STRICT_CHECKSUM=true
+                shift
+                ;;
+            --start-block)
+                start_block="$2"
+                shift 2
+                ;;
+            --end-block)
+                end_block="$2"
+                shift 2
+                ;;
+            --start-timestamp)
+                start_timestamp="$2"
+                shift 2
+                ;;
+            --end-timestamp)
+                end_timestamp="$2"
+                shift 2
+                ;;
+            --blocks-file)
+                blocks_file="$2"
+                shift 2
+                ;;
+            --output-format)
+                OUTPUT_FORMAT="$2"
+                shift 2
+                ;;
+            --numeric-format)
+                NUMERIC_FORMAT="$2"
+                shift 2
+                ;;
+            --omit-data)
+                OMIT_DATA=true
+                shift
+                ;;
+            --hashes-only)
+                HASHES_ONLY=true
+                shift
+                ;;
+            --checksum-addresses)
+                CHECKSUM_ADDRESSES=true
+                shift
+                ;;
+            --split-every)
+                SPLIT_EVERY="$2"
+                shift 2
+                ;;
+            --compress)
+                COMPRESS="$2"
+                shift 2
+                ;;
+            --compress-stdout)
+                COMPRESS_STDOUT=true
+                shift
+                ;;
+            --sqlite)
+                SQLITE_DB="$2"
+                shift 2
+                ;;
+            --output-file)
+                OUTPUT_FILE="$2"
+                shift 2
+                ;;
+            --stats-json)
+                stats_json="$2"
+                shift 2
+                ;;
+            --gaps-file)
+                gaps_file="$2"
+                shift 2
+                ;;
+            --manifest)
+                manifest="$2"
+                shift 2
+                ;;
+            --checkpoint-file)
+                CHECKPOINT_FILE="$2"
+                shift 2
+                ;;
+            --since-last-run)
+                SINCE_LAST_RUN=true
+                shift
+                ;;
+            --resume-output)
+                RESUME_OUTPUT=true
+                shift
+                ;;
+            --cache-dir)
+                cache_dir="$2"
+                shift 2
+                ;;
+            --chain-id)
+                chain_id="$2"
+                shift 2
+                ;;
+            --expect-chain-id)
+                expect_chain_id="$2"
+                shift 2
+                ;;
+            --no-cache)
+                no_cache=true
+                shift
+                ;;
+            --stream)
+                STREAM_OUTPUT=true
+                shift
+                ;;
+            --count-only)
+                count_only=true
+                shift
+                ;;
+            --follow)
+                follow=true
+                shift
+                ;;
+            --sample-rate)
+                SAMPLE_RATE="$2"
+                shift 2
+                ;;
+            --max-results)
+                MAX_RESULTS="$2"
+                shift 2
+                ;;
+            --batch-size)
+                BATCH_SIZE="$2"
+                shift 2
+                ;;
+            --batch-timeout)
+                BATCH_TIMEOUT="$2"
+                shift 2
+                ;;
+            --max-pending-blocks)
+                MAX_PENDING_BLOCKS="$2"
+                shift 2
+                ;;
+            --output-buffer)
+                OUTPUT_BUFFER="$2"
+                shift 2
+                ;;
+            --max-concurrent)
+                MAX_CONCURRENT="$2"
+                max_concurrent_given=true
+                shift 2
+                ;;
+            --min-concurrent)
+                MIN_CONCURRENT="$2"
+                shift 2
+                ;;
+            --adaptive-concurrency)
+                ADAPTIVE_CONCURRENCY=true
+                shift
+                ;;
+            --batch-mode)
+                BATCH_MODE="$2"
+                shift 2
+                ;;
+            --match-by)
+                MATCH_BY="$2"
+                shift 2
+                ;;
+            --selector)
+                local selector
+                local selectors=()
+                IFS=',' read -r -a selectors <<< "$2"
+                for selector in "${selectors[@]}"; do
+                    if ! [[ "$selector" =~ ^(0x[0-9a-fA-F]{40}:)?0x[0-9a-fA-F]{8}$ ]]; then
+                        log error "Error: --selector must be a 4-byte hex selector like 0xa9059cbb, optionally scoped to a target as 0xADDRESS:0xa9059cbb (got '$selector')"
+                        exit 1
+                    fi
+                    selector=$(echo "$selector" | tr '[:upper:]' '[:lower:]')
+                    if [[ "$selector" == *:* ]]; then
+                        CONTRACT_SELECTORS="${CONTRACT_SELECTORS:+$CONTRACT_SELECTORS,}$selector"
+                    else
+                        SELECTORS="${SELECTORS:+$SELECTORS,}$selector"
+                    fi
+                done
+                shift 2
+                ;;
+            --exclude-from)
+                local sender
+                local senders=()
+                IFS=',' read -r -a senders <<< "$2"
+                for sender in "${senders[@]}"; do
+                    if ! [[ "$sender" =~ ^0x[0-9a-fA-F]{40}$ ]]; then
+                        log error "Error: --exclude-from must be a 20-byte hex address (got '$sender')"
+                        exit 1
+                    fi
+                done
+                EXCLUDE_FROM=$(merge_target_contracts "$EXCLUDE_FROM" "$2")
+                shift 2
+                ;;
+            --min-value|--max-value|--min-gas-price|--max-gas-price)
+                local value_word
+                if ! value_word=$(wei_to_word "$2"); then
+                    log error "Error: $1 must be a wei amount in decimal or 0x hex, at most 2^256-1 (got '$2')"
+                    exit 1
                 fi
+                case "$1" in
+                    --min-value) MIN_VALUE="$value_word" ;;
+                    --max-value) MAX_VALUE="$value_word" ;;
+                    --min-gas-price) MIN_GAS_PRICE="$value_word" ;;
+                    --max-gas-price) MAX_GAS_PRICE="$value_word" ;;
+                esac
+                shift 2
+                ;;
+            --nonzero-value)
+                nonzero_value=true
+                shift
+                ;;
+            --detailed-blocks)
+                DETAILED_BLOCKS=true
+                shift
+                ;;
+            --quiet)
+                quiet=true
+                shift
+                ;;
+            --json-logs)
+                JSON_LOGS=true
+                shift
+                ;;
+            --log-level)
+                LOG_LEVEL="$2"
+                log_level_given=true
+                shift 2
+                ;;
+            --include-creations)
+                INCLUDE_CREATIONS=true
+                shift
+                ;;
+            --include-pending)
+                INCLUDE_PENDING=true
+                shift
+                ;;
+            --with-receipts)
+                WITH_RECEIPTS=true
+                shift
+                ;;
+            --with-state-diff)
+                WITH_STATE_DIFF=true
+                shift
+                ;;
+            --with-call-trace)
+                WITH_CALL_TRACE=true
+                shift
+                ;;
+            --with-access-list)
+                WITH_ACCESS_LIST=true
+                shift
+                ;;
+            --skip-unavailable-state)
+                SKIP_UNAVAILABLE_STATE=true
+                shift
+                ;;
+            --abi)
+                abi_file="$2"
+                shift 2
+                ;;
+            --input-file)
+                input_file="$2"
+                shift 2
+                ;;
+            --headers-only)
+                headers_only=true
+                shift
+                ;;
+            --confirmations)
+                confirmations="$2"
+                shift 2
+                ;;
+            --selector-stats)
+                selector_stats=true
+                shift
+                ;;
+            --max-blocks)
+                max_blocks="$2"
+                shift 2
+                ;;
+            --yes)
+                yes=true
+                shift
+                ;;
+            --verify-chain)
+                VERIFY_CHAIN=true
+                shift
+                ;;
+            --use-trace-filter)
+                USE_TRACE_FILTER=true
+                shift
+                ;;
+            --trace-filter-batch-size)
+                TRACE_FILTER_BATCH_SIZE="$2"
+                shift 2
+                ;;
+            --fail-on-error)
+                FAIL_ON_ERROR=true
+                shift
+                ;;
+            --no-fail-on-error)
+                FAIL_ON_ERROR=false
+                shift
+                ;;
+            --max-retries)
+                MAX_RETRIES="$2"
+                shift 2
+                ;;
+            --retry-base-delay-ms)
+                RETRY_BASE_DELAY_MS="$2"
+                shift 2
+                ;;
+            -h|--help)
+                usage
+                exit 0
+                ;;
+            *)
+                log error "Error: Unknown option $1"
+                usage >&2
+                exit 1
+                ;;
+        esac
+    done
 
-                wait_seconds=$(awk "BEGIN {print $total_wait/1000}")
-                echo "Rate limit hit (429), retrying after ${wait_seconds}s (attempt $attempt/$max_retries)" >&2
-                sleep "$wait_seconds"
-                continue
-            else
-                echo "Max retries reached for rate-limited request" >&2
-            fi
-        fi
+    # Endpoints and targets can come from the environment, which keeps API keys out of
+    # shell history and process listings; flags take precedence
+    if [[ ${#RPC_URLS[@]} -eq 0 ]]; then
+        rpc_pool_add "${CREDIBLE_RPC_URL:-}"
+    fi
+    if [[ -z "$target_inputs" ]]; then
+        target_inputs="${CREDIBLE_TARGET_CONTRACTS:-}"
+    fi
+    if [[ ${#RPC_URLS[@]} -eq 0 && -z "$input_file" ]]; then
+        log error "Error: No RPC endpoint (pass --rpc-url or set CREDIBLE_RPC_URL)"
+        exit 1
+    fi
+    validate_rpc_urls || exit 1
+    if [[ -z "$target_inputs" && -z "$input_file" && "$headers_only" != "true" ]]; then
+        log error "Error: No target contract (pass --target-contract, --contracts-file or set CREDIBLE_TARGET_CONTRACTS)"
+        exit 1
+    fi
+
+    validate_target_contracts "$target_inputs" || exit 1
+    target_contracts=$(merge_target_contracts "" "$target_inputs")
 
-        # Success or non-retryable error
-        echo "$response"
-        return 0
+    # A scoped selector only ever sees the matches of its own contract, so that must be a target
+    local scoped_selector
+    for scoped_selector in ${CONTRACT_SELECTORS//,/ }; do
+        if [[ -n "$target_contracts" && ",$target_contracts," != *",${scoped_selector%%:*},"* ]]; then
+            log error "Error: --selector $scoped_selector is scoped to ${scoped_selector%%:*}, which is not a target contract"
+            exit 1
+        fi
     done
 
-    # All retries exhausted
-    echo "$response"
-    return 1
-}
+    if [[ -n "$start_block" && -n "$start_timestamp" ]] || [[ -n "$end_block" && -n "$end_timestamp" ]]; then
+        log error "Error: Give each bound as a block or a timestamp, not both (--start-block/--start-timestamp, --end-block/--end-timestamp)"
+        exit 1
+    fi
 
-# Check if required tools are available
-check_dependencies() {
-    local missing_tools=()
+    # A block list stands in for the range, which runs from its first to its last block
+    if [[ -n "$blocks_file" ]]; then
+        if [[ -n "$start_block$end_block$start_timestamp$end_timestamp" || "$SINCE_LAST_RUN" == "true" ]]; then
+            log error "Error: --blocks-file replaces the range, so it can't be combined with --start-block, --end-block, timestamps or --since-last-run"
+            exit 1
+        fi
+        if [[ "$follow" == "true" || -n "$CHECKPOINT_FILE" || "$RESUME_OUTPUT" == "true" ||
+              -n "$input_file" || "$headers_only" == "true" ]]; then
+            log error "Error: --blocks-file can't be combined with --follow, --checkpoint-file, --resume-output, --input-file or --headers-only"
+            exit 1
+        fi
+        read_blocks_file "$blocks_file" "$TEMP_DIR/block_list.txt" || exit 1
+        BLOCK_LIST_FILE="$TEMP_DIR/block_list.txt"
+        start_block=$(head -n 1 "$BLOCK_LIST_FILE")
+        end_block=$(tail -n 1 "$BLOCK_LIST_FILE")
+    fi
 
-    if ! command -v curl &> /dev/null; then
-        missing_tools+=("curl")
+    # Validate required arguments (a re-filtered dump keeps its own targets and blocks, headers
+    # need no targets and --since-last-run takes its range from the checkpoint and the chain tip)
+    if [[ -z "$input_file" ]] &&
+       [[ ( -z "$target_contracts" && "$headers_only" != "true" ) ||
+          ( "$SINCE_LAST_RUN" != "true" && ( -z "$start_block$start_timestamp" || -z "$end_block$end_timestamp" ) ) ]]; then
+        log error "Error: Missing required arguments"
+        usage >&2
+        exit 1
     fi
 
-    if ! command -v jq &> /dev/null; then
-        missing_tools+=("jq")
+    # Validate numeric arguments
+    local block_tags="^(latest|earliest|safe|finalized)$"
+    if ! [[ "$start_block" =~ ^[0-9]*$ || "$start_block" =~ $block_tags ]] ||
+       ! [[ "$end_block" =~ ^[0-9]*$ || "$end_block" =~ $block_tags ]]; then
+        log error "Error: Block numbers must be positive integers or one of latest, earliest, safe, finalized"
+        exit 1
     fi
 
-    if [[ ${#missing_tools[@]} -gt 0 ]]; then
-        echo "Error: Missing required tools: ${missing_tools[*]}" >&2
-        echo "Please install the missing tools and try again." >&2
+    if ! [[ "$start_timestamp" =~ ^[0-9]*$ ]] || ! [[ "$end_timestamp" =~ ^[0-9]*$ ]]; then
+        log error "Error: Timestamps must be unix seconds"
         exit 1
     fi
-}
 
-# Convert hex to decimal
-hex_to_decimal() {
-    local hex_value="$1"
-    if [[ "$hex_value" =~ ^0x[0-9a-fA-F]+$ ]]; then
-        printf "%d" "$hex_value"
-    else
-        echo "$hex_value"
+    # curl reads HTTPS_PROXY itself but only a lowercase http_proxy, since a CGI request header
+    # can set HTTP_PROXY; a command-line tool has no such header, so take the uppercase one too
+    if [[ -z "$RPC_PROXY" && -n "${HTTP_PROXY:-}" && -z "${http_proxy:-}" ]]; then
+        export http_proxy="$HTTP_PROXY"
     fi
-}
+    if [[ -n "$RPC_CA_CERT" && ! -r "$RPC_CA_CERT" ]]; then
+        log error "Error: --ca-cert file not found: $RPC_CA_CERT"
+        exit 1
+    fi
+    if [[ -n "$RPC_PROXY$RPC_CA_CERT" ]]; then
+        local proxied_url
+        for proxied_url in "${RPC_URLS[@]}"; do
+            if is_connection_url "$proxied_url"; then
+                log error "Error: --proxy and --ca-cert apply to http(s):// endpoints; ws://, wss:// and ipc:// connections don't use them"
+                exit 1
+            fi
+        done
+    fi
+    local ipc_url
+    for ipc_url in "${RPC_URLS[@]}"; do
+        if ! is_ipc_url "$ipc_url"; then
+            continue
+        fi
+        if [[ ${#RPC_HEADERS[@]} -gt 0 ]]; then
+            log error "Error: --header applies to http(s):// and ws(s):// endpoints; an ipc:// socket has no headers"
+            exit 1
+        fi
+        if [[ ! -e "${ipc_url:6}" ]]; then
+            log error "Error: IPC socket not found: ${ipc_url:6} (is the node running with IPC enabled?)"
+            exit 1
+        fi
+    done
 
-# Count RPC calls from counter file
-count_rpc_calls() {
-    local counter_name="$1"
-    local file="$RPC_COUNTER_DIR/${counter_name}.count"
-    if [[ -f "$file" ]]; then
-        wc -l < "$file" | tr -d ' '
-    else
-        echo "0"
+    if [[ "$quiet" == "true" && "$log_level_given" != "true" ]]; then
+        LOG_LEVEL="warn"
+    fi
+    if [[ "$LOG_LEVEL" != "error" && "$LOG_LEVEL" != "warn" && "$LOG_LEVEL" != "info" && "$LOG_LEVEL" != "debug" ]]; then
+        log error "Error: --log-level must be 'error', 'warn', 'info' or 'debug'"
+        exit 1
+    fi
+
+    if [[ "$BATCH_MODE" != "parallel" && "$BATCH_MODE" != "jsonrpc-batch" ]]; then
+        log error "Error: --batch-mode must be 'parallel' or 'jsonrpc-batch'"
+        exit 1
     fi
-}
 
-# Check if RPC error indicates unsupported method
-is_method_unsupported() {
-    local response="$1"
-    local error_code
-    local error_msg
-    error_code=$(echo "$response" | jq -r '.error.code // empty' 2>/dev/null)
-    error_msg=$(echo "$response" | jq -r '.error.message // empty' 2>/dev/null | tr '[:upper:]' '[:lower:]')
-
-    if [[ "$error_code" == "-32601" ]]; then
-        return 0
-    fi
-    if [[ -n "$error_msg" ]]; then
-        if [[ "$error_msg" == *"method not found"* ]] ||
-           [[ "$error_msg" == *"does not exist"* ]] ||
-           [[ "$error_msg" == *"not available"* ]] ||
-           [[ "$error_msg" == *"unknown method"* ]] ||
-           [[ "$error_msg" == *"not supported"* ]]; then
-            return 0
+    if [[ "$OUTPUT_FORMAT" != "simple" && "$OUTPUT_FORMAT" != "json" && "$OUTPUT_FORMAT" != "ndjson" &&
+          "$OUTPUT_FORMAT" != "csv" && "$OUTPUT_FORMAT" != "abi" && "$OUTPUT_FORMAT" != "parquet" ]]; then
+        log error "Error: --output-format must be 'simple', 'json', 'ndjson', 'csv', 'abi' or 'parquet'"
+        exit 1
+    fi
+    # Hashes are written one per line (the ndjson writer) unless a json array is asked for
+    if [[ "$HASHES_ONLY" == "true" ]]; then
+        if [[ "$OUTPUT_FORMAT" == "simple" ]]; then
+            OUTPUT_FORMAT="ndjson"
+        fi
+        if [[ "$OUTPUT_FORMAT" != "json" && "$OUTPUT_FORMAT" != "ndjson" ]]; then
+            log error "Error: --hashes-only writes one hash per line, or a JSON array with --output-format json"
+            exit 1
+        fi
+        if [[ -n "$SQLITE_DB" || "$count_only" == "true" || "$WITH_RECEIPTS" == "true" || "$WITH_STATE_DIFF" == "true" ||
+              "$WITH_CALL_TRACE" == "true" || "$WITH_ACCESS_LIST" == "true" || -n "$abi_file" ||
+              "$OMIT_DATA" == "true" || "$CHECKSUM_ADDRESSES" == "true" || "$RESUME_OUTPUT" == "true" ]]; then
+            log error "Error: --hashes-only writes nothing but hashes, so it can't be combined with --sqlite, --count-only, --with-receipts, --with-state-diff, --with-call-trace, --with-access-list, --abi, --omit-data, --checksum-addresses or --resume-output"
+            exit 1
         fi
     fi
-    return 1
-}
+    if [[ "$NUMERIC_FORMAT" != "hex" && "$NUMERIC_FORMAT" != "decimal" ]]; then
+        log error "Error: --numeric-format must be 'hex' or 'decimal'"
+        exit 1
+    fi
 
-# Fetch transaction details and receipts for a list of tx hashes
-emit_transactions_from_hashes() {
-    local rpc_url="$1"
-    local tx_hashes="$2"
-    local output_file="$3"
+    # Following streams ndjson for as long as it runs, so there is no final payload to build
+    if [[ "$follow" == "true" ]]; then
+        if [[ "$OUTPUT_FORMAT" != "ndjson" || "$count_only" == "true" || -n "$SQLITE_DB" ||
+              $MAX_RESULTS -gt 0 || -n "$CHECKPOINT_FILE" ]]; then
+            log error "Error: --follow streams --output-format ndjson and can't be combined with --count-only, --sqlite, --max-results or --checkpoint-file"
+            exit 1
+        fi
+        local has_connection_url=false
+        local url
+        for url in "${RPC_URLS[@]}"; do
+            if is_connection_url "$url"; then
+                has_connection_url=true
+            fi
+        done
+        if [[ "$has_connection_url" != "true" ]]; then
+            log error "Error: --follow subscribes to newHeads, which needs a ws://, wss:// or ipc:// --rpc-url"
+            exit 1
+        fi
+        STREAM_OUTPUT=true
+    fi
 
-    local tx_count=0
-    local tx_processed=0
+    # A dump is re-filtered offline, so anything that needs the node is out
+    if [[ -n "$input_file" ]]; then
+        if [[ -n "$start_timestamp$end_timestamp" || "$start_block" =~ $block_tags || "$end_block" =~ $block_tags ||
+              "$follow" == "true" || -n "$CHECKPOINT_FILE" || "$USE_TRACE_FILTER" == "true" || "$MATCH_BY" != "to" ||
+              "$VERIFY_CHAIN" == "true" || $confirmations -gt 0 || -n "$expect_chain_id" ||
+              "$WITH_RECEIPTS" == "true" || "$WITH_STATE_DIFF" == "true" || "$WITH_CALL_TRACE" == "true" ||
+              "$WITH_ACCESS_LIST" == "true" || -n "$gaps_file" ]]; then
+            log error "Error: --input-file re-filters without a node, so it can't be combined with timestamps, block tags, --follow, --checkpoint-file, --use-trace-filter, --match-by, --verify-chain, --confirmations, --expect-chain-id, --gaps-file or the --with-* enrichments"
+            exit 1
+        fi
+        if [[ ! -r "$input_file" ]]; then
+            log error "Error: --input-file not found: $input_file"
+            exit 1
+        fi
+    fi
 
-    if [[ -n "$tx_hashes" ]]; then
-        while IFS= read -r tx_hash; do
-            [[ -z "$tx_hash" ]] && continue
+    if [[ "$OUTPUT_FORMAT" == "parquet" && ( -z "$OUTPUT_FILE" || "$STREAM_OUTPUT" == "true" || -n "$COMPRESS" ) ]]; then
+        log error "Error: --output-format parquet needs --output-file and can't be combined with --stream or --compress (Parquet is compressed already)"
+        exit 1
+    fi
 
-            # Add small delay every 5 transactions to avoid rate limiting
-            if [[ $((tx_processed % 5)) -eq 0 && $tx_processed -gt 0 ]]; then
-                sleep 0.1
-            fi
-            ((tx_processed++))
-
-            # Fetch actual transaction data using eth_getTransactionByHash
-            local tx_request
-            tx_request=$(jq -n \
-                --arg tx_hash "$tx_hash" \
-                '{
-                    "jsonrpc": "2.0",
-                    "method": "eth_getTransactionByHash",
-                    "params": [$tx_hash],
-                    "id": 1
-                }')
-
-            echo "1" >> "$RPC_COUNTER_DIR/tx_fetch.count"
-            local tx_response
-            tx_response=$(retry_with_backoff 5 curl -s -X POST \
-                -H "Content-Type: application/json" \
-                -d "$tx_request" \
-                --max-time 30 \
-                "$rpc_url")
-
-            local tx_data
-            tx_data=$(echo "$tx_response" | jq -r '.result')
-
-            if [[ -n "$tx_data" && "$tx_data" != "null" ]]; then
-                local tx_from
-                local tx_to
-                local tx_value
-                local tx_input
-                local tx_gas_price
-                local tx_gas_limit
-                local tx_max_fee_per_gas
-                local tx_max_priority_fee_per_gas
-                local tx_block_num_hex
-                local tx_index_hex
-
-                tx_from=$(echo "$tx_data" | jq -r '.from')
-                tx_to=$(echo "$tx_data" | jq -r '.to // empty')
-                tx_value=$(echo "$tx_data" | jq -r '.value')
-                tx_input=$(echo "$tx_data" | jq -r '.input')
-                tx_gas_price=$(echo "$tx_data" | jq -r '.gasPrice // "0x0"')
-                tx_gas_limit=$(echo "$tx_data" | jq -r '.gas // "0x0"')
-                tx_max_fee_per_gas=$(echo "$tx_data" | jq -r '.maxFeePerGas // "0x0"')
-                tx_max_priority_fee_per_gas=$(echo "$tx_data" | jq -r '.maxPriorityFeePerGas // "0x0"')
-                tx_block_num_hex=$(echo "$tx_data" | jq -r '.blockNumber // empty')
-                tx_index_hex=$(echo "$tx_data" | jq -r '.transactionIndex // empty')
-
-                local block_num
-                local tx_index
-                block_num=$(hex_to_decimal "$tx_block_num_hex")
-                tx_index=$(hex_to_decimal "$tx_index_hex")
-
-                # Check if transaction succeeded on-chain
-                local receipt_request
-                receipt_request=$(jq -n \
-                    --arg tx_hash "$tx_hash" \
-                    '{
-                        "jsonrpc": "2.0",
-                        "method": "eth_getTransactionReceipt",
-                        "params": [$tx_hash],
-                        "id": 1
-                    }')
-
-                echo "1" >> "$RPC_COUNTER_DIR/receipt_fetch.count"
-                local receipt_response
-                receipt_response=$(retry_with_backoff 5 curl -s -X POST \
-                    -H "Content-Type: application/json" \
-                    -d "$receipt_request" \
-                    --max-time 30 \
-                    "$rpc_url")
-
-                local tx_status
-                tx_status=$(echo "$receipt_response" | jq -r '.result.status // empty')
-
-                # Only output transaction if it succeeded (status == "0x1")
-                if [[ "$tx_status" == "0x1" ]]; then
-                    echo "$tx_hash|$tx_from|$tx_to|$tx_value|$tx_input|$block_num|$tx_index|$tx_gas_price|$tx_gas_limit|$tx_max_fee_per_gas|$tx_max_priority_fee_per_gas" >> "$output_file"
-                    ((tx_count++))
-                fi
-            fi
-        done <<< "$tx_hashes"
+    if [[ "$count_only" == "true" && ( "$STREAM_OUTPUT" == "true" || -n "$OUTPUT_FILE" ||
+          "$WITH_RECEIPTS" == "true" || "$WITH_STATE_DIFF" == "true" || "$WITH_CALL_TRACE" == "true" ||
+          "$WITH_ACCESS_LIST" == "true" || -n "$abi_file" ) ]]; then
+        log error "Error: --count-only writes no transactions, so it can't be combined with --stream, --output-file, --with-receipts, --with-state-diff, --with-call-trace, --with-access-list or --abi"
+        exit 1
     fi
 
-    echo "$tx_count"
-}
+    if [[ "$STREAM_OUTPUT" == "true" && ( "$OUTPUT_FORMAT" == "simple" || "$OUTPUT_FORMAT" == "abi" ) ]]; then
+        log error "Error: --stream needs --output-format json, ndjson or csv (simple and abi start with the total count)"
+        exit 1
+    fi
 
-# Fetch transactions using trace_filter (much faster for internal calls)
-fetch_transactions_trace_filter() {
-    local rpc_url="$1"
-    local start_block="$2"
-    local end_block="$3"
-    local target_contract="$4"
-    local output_file="$5"
-
-    # Convert block numbers to hex
-    local start_hex
-    local end_hex
-    start_hex=$(printf "0x%x" "$start_block")
-    end_hex=$(printf "0x%x" "$end_block")
-
-    echo "[TRACE] Using trace_filter API (fastest method for internal call detection)" >&2
-    echo "[TRACE] Fetching blocks $start_block to $end_block..." >&2
-
-    # Prepare trace_filter request
-    local trace_request
-    trace_request=$(jq -n \
-        --arg start_hex "$start_hex" \
-        --arg end_hex "$end_hex" \
-        --arg target "$target_contract" \
-        '{
-            "jsonrpc": "2.0",
-            "method": "trace_filter",
-            "params": [{
-                "fromBlock": $start_hex,
-                "toBlock": $end_hex,
-                "toAddress": [$target]
-            }],
-            "id": 1
-        }')
-
-    # Make RPC call with retry logic
-    echo "1" >> "$RPC_COUNTER_DIR/trace_filter.count"
-    local trace_response
-    trace_response=$(retry_with_backoff 5 curl -s -X POST \
-        -H "Content-Type: application/json" \
-        -d "$trace_request" \
-        --max-time 60 \
-        "$rpc_url")
-
-    # Check for errors
-    if [[ -z "$trace_response" ]] || echo "$trace_response" | jq -e '.error' > /dev/null 2>&1; then
-        local error_msg
-        error_msg=$(echo "$trace_response" | jq -r '.error.message // "Unknown error"')
-        echo "Error: trace_filter failed: $error_msg" >&2
-        if is_method_unsupported "$trace_response"; then
-            echo "[TRACE] trace_filter not supported by this RPC endpoint" >&2
-            echo "0"
-            return 2
+    if [[ -n "$SQLITE_DB" && ( -n "$OUTPUT_FILE" || "$STREAM_OUTPUT" == "true" || "$count_only" == "true" || -n "$COMPRESS" ) ]]; then
+        log error "Error: --sqlite is an output of its own and can't be combined with --output-file, --stream, --count-only or --compress"
+        exit 1
+    fi
+    if [[ "$OMIT_DATA" == "true" && ( "$OUTPUT_FORMAT" == "simple" || "$OUTPUT_FORMAT" == "abi" || -n "$SQLITE_DB" ) ]]; then
+        log error "Error: --omit-data needs --output-format json, ndjson, csv or parquet (the simple and abi payloads and --sqlite always carry calldata)"
+        exit 1
+    fi
+    if [[ "$INCLUDE_PENDING" == "true" &&
+          ( ( "$OUTPUT_FORMAT" != "json" && "$OUTPUT_FORMAT" != "ndjson" && "$OUTPUT_FORMAT" != "csv" ) || -n "$SQLITE_DB" ) ]]; then
+        log error "Error: --include-pending needs --output-format json, ndjson or csv (the other outputs need every transaction's index)"
+        exit 1
+    fi
+    if ! [[ "$SPLIT_EVERY" =~ ^[0-9]+$ ]]; then
+        log error "Error: --split-every must be a whole number of transactions"
+        exit 1
+    fi
+    if [[ $SPLIT_EVERY -gt 0 && ( -z "$OUTPUT_FILE" || "$STREAM_OUTPUT" == "true" ) ]]; then
+        log error "Error: --split-every needs --output-file and can't be combined with --stream"
+        exit 1
+    fi
+    # Headers carry no transactions, so nothing that selects, enriches or counts them applies
+    if [[ "$headers_only" == "true" ]]; then
+        if [[ -n "$input_file" || "$follow" == "true" || -n "$CHECKPOINT_FILE" || "$USE_TRACE_FILTER" == "true" ||
+              "$MATCH_BY" != "to" || "$VERIFY_CHAIN" == "true" || "$count_only" == "true" || "$STREAM_OUTPUT" == "true" ||
+              -n "$SQLITE_DB" || $SPLIT_EVERY -gt 0 || -n "$manifest" || "$selector_stats" == "true" ||
+              "$DETAILED_BLOCKS" == "true" || -n "$abi_file$SELECTORS$CONTRACT_SELECTORS$EXCLUDE_FROM$MIN_VALUE$MAX_VALUE$MIN_GAS_PRICE$MAX_GAS_PRICE" ||
+              "$nonzero_value" == "true" || "$INCLUDE_CREATIONS" == "true" || "$INCLUDE_PENDING" == "true" || "$SAMPLE_RATE" != "1" ||
+              "$MAX_RESULTS" != "0" || "$OMIT_DATA" == "true" || "$CHECKSUM_ADDRESSES" == "true" || "$HASHES_ONLY" == "true" ||
+              "$WITH_RECEIPTS" == "true" || "$WITH_STATE_DIFF" == "true" || "$WITH_CALL_TRACE" == "true" ||
+              "$WITH_ACCESS_LIST" == "true" ]]; then
+            log error "Error: --headers-only outputs blocks, not transactions, so it only combines with the range, output file, compression, numeric format and connection options"
+            exit 1
+        fi
+        if [[ "$OUTPUT_FORMAT" != "json" && "$OUTPUT_FORMAT" != "ndjson" && "$OUTPUT_FORMAT" != "csv" ]]; then
+            log error "Error: --headers-only needs --output-format json, ndjson or csv"
+            exit 1
         fi
-        echo "0"
-        return 1
+    fi
+    if [[ "$SKIP_UNAVAILABLE_STATE" == "true" && "$WITH_STATE_DIFF" != "true" && "$WITH_CALL_TRACE" != "true" &&
+          "$WITH_ACCESS_LIST" != "true" ]]; then
+        log error "Error: --skip-unavailable-state needs --with-state-diff, --with-call-trace or --with-access-list"
+        exit 1
+    fi
+    if [[ -n "$SQLITE_DB" ]] && ! mkdir -p "$(dirname "$SQLITE_DB")"; then
+        log error "Error: Cannot create directory for --sqlite $SQLITE_DB"
+        exit 1
     fi
 
-    # Extract unique transaction hashes that involve the target contract
-    local tx_hashes
-    tx_hashes=$(echo "$trace_response" | jq -r '.result | map(.transactionHash) | unique | .[]?')
-    tx_hashes=$(echo "$tx_hashes" | awk 'NF' | awk '!seen[$0]++')
+    if [[ -n "$COMPRESS" && "$COMPRESS" != "gzip" ]]; then
+        log error "Error: --compress must be 'gzip'"
+        exit 1
+    fi
+    if [[ "$COMPRESS_STDOUT" == "true" && ( "$COMPRESS" != "gzip" || -n "$OUTPUT_FILE" || "$STREAM_OUTPUT" == "true" ) ]]; then
+        log error "Error: --compress-stdout needs --compress gzip and a stdout payload (no --output-file or --stream)"
+        exit 1
+    fi
+    if [[ -n "$COMPRESS" && -z "$OUTPUT_FILE" && "$COMPRESS_STDOUT" != "true" ]]; then
+        log warn "Note: --compress only applies to --output-file; stdout stays uncompressed without --compress-stdout"
+    fi
 
-    local tx_count
-    tx_count=$(emit_transactions_from_hashes "$rpc_url" "$tx_hashes" "$output_file")
+    if [[ -n "$OUTPUT_FILE" ]] && ! mkdir -p "$(dirname "$OUTPUT_FILE")"; then
+        log error "Error: Cannot create directory for --output-file $OUTPUT_FILE"
+        exit 1
+    fi
 
-    echo "  Found $tx_count transactions in blocks $start_block-$end_block" >&2
-    echo "$tx_count"
-}
+    if ! [[ "$chain_id" =~ ^[0-9]*$ ]]; then
+        log error "Error: --chain-id must be a decimal chain id"
+        exit 1
+    fi
+    if ! [[ "$expect_chain_id" =~ ^[0-9]*$ ]]; then
+        log error "Error: --expect-chain-id must be a decimal chain id"
+        exit 1
+    fi
+    if [[ -n "$chain_id" && -n "$expect_chain_id" && "$chain_id" != "$expect_chain_id" ]]; then
+        log error "Error: --chain-id $chain_id contradicts --expect-chain-id $expect_chain_id"
+        exit 1
+    fi
 
-# Fetch transactions using debug_traceBlockByNumber with callTracer
-fetch_transactions_debug_trace_block() {
-    local rpc_url="$1"
-    local start_block="$2"
-    local end_block="$3"
-    local target_contract="$4"
-    local output_file="$5"
-
-    local target_contract_lower
-    target_contract_lower=$(echo "$target_contract" | tr '[:upper:]' '[:lower:]')
-
-    local tx_hashes=""
-
-    for ((block=start_block; block<=end_block; block++)); do
-        local block_hex
-        block_hex=$(printf "0x%x" "$block")
-
-        echo "[TRACE] Tracing block $block with debug_traceBlockByNumber..." >&2
-
-        local trace_request
-        trace_request=$(jq -n \
-            --arg block_hex "$block_hex" \
-            '{
-                "jsonrpc": "2.0",
-                "method": "debug_traceBlockByNumber",
-                "params": [$block_hex, {"tracer":"callTracer"}],
-                "id": 1
-            }')
-
-        echo "1" >> "$RPC_COUNTER_DIR/debug_trace_block.count"
-        local trace_response
-        trace_response=$(retry_with_backoff 5 curl -s -X POST \
-            -H "Content-Type: application/json" \
-            -d "$trace_request" \
-            --max-time 60 \
-            "$rpc_url")
-
-        if [[ -z "$trace_response" ]] || echo "$trace_response" | jq -e '.error' > /dev/null 2>&1; then
-            local error_msg
-            error_msg=$(echo "$trace_response" | jq -r '.error.message // "Unknown error"')
-            echo "Error: debug_traceBlockByNumber failed: $error_msg" >&2
-            if is_method_unsupported "$trace_response"; then
-                echo "[TRACE] debug_traceBlockByNumber not supported by this RPC endpoint" >&2
-                echo "0"
-                return 2
-            fi
-            echo "0"
-            return 1
+    # An incremental run outputs exactly the blocks its checkpoint covers, which a stream can't take back
+    if [[ "$SINCE_LAST_RUN" == "true" ]]; then
+        if [[ -z "$CHECKPOINT_FILE" ]]; then
+            log error "Error: --since-last-run needs --checkpoint-file to remember where the last run stopped"
+            exit 1
         fi
-
-        local block_tx_hashes
-        block_tx_hashes=$(echo "$trace_response" | jq -r --arg target "$target_contract_lower" '
-            def hasTarget(node):
-                if (node | type) == "object" then
-                    ((node.to? // "" | ascii_downcase) == $target) or
-                    ([node.calls[]? | hasTarget(.)] | any)
-                else
-                    false
-                end;
-            .result[]?
-            | (.result? // .) as $r
-            | select(hasTarget($r))
-            | (.txHash // .transactionHash // .hash // empty)
-        ')
-
-        if [[ -n "$block_tx_hashes" ]]; then
-            tx_hashes+="$block_tx_hashes"$'\n'
+        if [[ -n "$end_block$start_timestamp$end_timestamp" || "$STREAM_OUTPUT" == "true" ]]; then
+            log error "Error: --since-last-run ends at latest - --confirmations, so it can't be combined with --end-block, timestamps or --stream"
+            exit 1
         fi
-    done
+    fi
 
-    tx_hashes=$(echo "$tx_hashes" | awk 'NF' | awk '!seen[$0]++')
-    local tx_count
-    tx_count=$(emit_transactions_from_hashes "$rpc_url" "$tx_hashes" "$output_file")
+    if [[ -n "$stats_json" ]] && ! mkdir -p "$(dirname "$stats_json")"; then
+        log error "Error: Cannot create directory for --stats-json $stats_json"
+        exit 1
+    fi
+    if [[ -n "$gaps_file" ]] && ! mkdir -p "$(dirname "$gaps_file")"; then
+        log error "Error: Cannot create directory for --gaps-file $gaps_file"
+        exit 1
+    fi
 
-    echo "  Found $tx_count transactions in blocks $start_block-$end_block" >&2
-    echo "$tx_count"
-}
+    # The manifest describes (and hashes) a dataset on disk, not a payload for the caller
+    if [[ -n "$manifest" && -z "$OUTPUT_FILE" && -z "$SQLITE_DB" ]]; then
+        log error "Error: --manifest needs --output-file or --sqlite"
+        exit 1
+    fi
+    if [[ -n "$manifest" ]] && ! mkdir -p "$(dirname "$manifest")"; then
+        log error "Error: Cannot create directory for --manifest $manifest"
+        exit 1
+    fi
 
-# Fetch transactions using debug_traceTransaction with callTracer (slow fallback)
-fetch_transactions_debug_trace_tx() {
-    local rpc_url="$1"
-    local start_block="$2"
-    local end_block="$3"
-    local target_contract="$4"
-    local output_file="$5"
-
-    local target_contract_lower
-    target_contract_lower=$(echo "$target_contract" | tr '[:upper:]' '[:lower:]')
-
-    local tx_hashes=""
-
-    for ((block=start_block; block<=end_block; block++)); do
-        local block_hex
-        block_hex=$(printf "0x%x" "$block")
-
-        local block_request
-        block_request=$(jq -n \
-            --arg block_hex "$block_hex" \
-            '{
-                "jsonrpc": "2.0",
-                "method": "eth_getBlockByNumber",
-                "params": [$block_hex, false],
-                "id": 1
-            }')
-
-        echo "1" >> "$RPC_COUNTER_DIR/block_fetch.count"
-        local block_response
-        block_response=$(retry_with_backoff 5 curl -s -X POST \
-            -H "Content-Type: application/json" \
-            -d "$block_request" \
-            --max-time 30 \
-            "$rpc_url")
-
-        if [[ -z "$block_response" ]] || echo "$block_response" | jq -e '.error' > /dev/null 2>&1; then
-            local error_msg
-            error_msg=$(echo "$block_response" | jq -r '.error.message // "Unknown error"')
-            echo "Error: eth_getBlockByNumber failed: $error_msg" >&2
-            echo "0"
-            return 1
+    # Only whole ndjson lines can be checked and cut back to a block boundary, and a resumed run
+    # must cover the same fixed range as the one it continues
+    if [[ "$RESUME_OUTPUT" == "true" ]]; then
+        if [[ "$STREAM_OUTPUT" != "true" || -z "$OUTPUT_FILE" || "$OUTPUT_FORMAT" != "ndjson" || -z "$manifest" ]]; then
+            log error "Error: --resume-output needs --stream, --output-file, --output-format ndjson and --manifest"
+            exit 1
         fi
+        if [[ -n "$COMPRESS" || -n "$input_file" || "$headers_only" == "true" || "$follow" == "true" ||
+              -n "$CHECKPOINT_FILE" || $MAX_RESULTS -gt 0 ]]; then
+            log error "Error: --resume-output can't be combined with --compress, --input-file, --headers-only, --follow, --checkpoint-file or --max-results"
+            exit 1
+        fi
+    fi
 
-        local tx_list
-        tx_list=$(echo "$block_response" | jq -r '.result.transactions[]? // empty')
-
-        while IFS= read -r tx_hash; do
-            [[ -z "$tx_hash" ]] && continue
-
-            local trace_request
-            trace_request=$(jq -n \
-                --arg tx_hash "$tx_hash" \
-                '{
-                    "jsonrpc": "2.0",
-                    "method": "debug_traceTransaction",
-                    "params": [$tx_hash, {"tracer":"callTracer"}],
-                    "id": 1
-                }')
-
-            echo "1" >> "$RPC_COUNTER_DIR/debug_trace_tx.count"
-            local trace_response
-            trace_response=$(retry_with_backoff 5 curl -s -X POST \
-                -H "Content-Type: application/json" \
-                -d "$trace_request" \
-                --max-time 60 \
-                "$rpc_url")
-
-            if [[ -z "$trace_response" ]] || echo "$trace_response" | jq -e '.error' > /dev/null 2>&1; then
-                local error_msg
-                error_msg=$(echo "$trace_response" | jq -r '.error.message // "Unknown error"')
-                echo "Error: debug_traceTransaction failed: $error_msg" >&2
-                if is_method_unsupported "$trace_response"; then
-                    echo "[TRACE] debug_traceTransaction not supported by this RPC endpoint" >&2
-                    echo "0"
-                    return 2
-                fi
-                continue
-            fi
+    if [[ -n "$CHECKPOINT_FILE" ]] && ! mkdir -p "$(dirname "$CHECKPOINT_FILE")"; then
+        log error "Error: Cannot create directory for --checkpoint-file $CHECKPOINT_FILE"
+        exit 1
+    fi
 
-            if echo "$trace_response" | jq -e --arg target "$target_contract_lower" '
-                def hasTarget(node):
-                    if (node | type) == "object" then
-                        ((node.to? // "" | ascii_downcase) == $target) or
-                        ([node.calls[]? | hasTarget(.)] | any)
-                    else
-                        false
-                    end;
-                (.result? // empty) as $r
-                | $r != null and hasTarget($r)
-            ' > /dev/null; then
-                tx_hashes+="$tx_hash"$'\n'
-            fi
-        done <<< "$tx_list"
-    done
+    if [[ "$MATCH_BY" != "to" && "$MATCH_BY" != "logs" && "$MATCH_BY" != "traces" ]]; then
+        log error "Error: --match-by must be 'to', 'logs' or 'traces'"
+        exit 1
+    fi
+    if [[ "$MATCH_BY" == "traces" && "$USE_TRACE_FILTER" == "true" ]]; then
+        log error "Error: --match-by traces and --use-trace-filter are two ways of detecting internal calls; pick one"
+        exit 1
+    fi
 
-    tx_hashes=$(echo "$tx_hashes" | awk 'NF' | awk '!seen[$0]++')
-    local tx_count
-    tx_count=$(emit_transactions_from_hashes "$rpc_url" "$tx_hashes" "$output_file")
+    if ! [[ "$RPC_FAILURE_THRESHOLD" =~ ^[1-9][0-9]*$ ]] || ! [[ "$RPC_COOLDOWN_SECS" =~ ^[0-9]+$ ]]; then
+        log error "Error: --rpc-failure-threshold must be a positive integer and --rpc-cooldown-secs a non-negative integer"
+        exit 1
+    fi
 
-    echo "  Found $tx_count transactions in blocks $start_block-$end_block" >&2
-    echo "$tx_count"
-}
+    if ! [[ "$SAMPLE_RATE" =~ ^[0-9]*([.][0-9]+)?$ && -n "$SAMPLE_RATE" ]] ||
+        ! awk "BEGIN {exit !($SAMPLE_RATE > 0 && $SAMPLE_RATE <= 1)}"; then
+        log error "Error: --sample-rate must be a number in (0, 1]"
+        exit 1
+    fi
+    if awk "BEGIN {exit !($SAMPLE_RATE == 1)}"; then
+        SAMPLE_RATE=1
+    fi
+
+    if ! [[ "$MAX_RESULTS" =~ ^[0-9]+$ ]]; then
+        log error "Error: --max-results must be a positive integer"
+        exit 1
+    fi
+
+    if ! [[ "$MAX_RPS" =~ ^[0-9]+([.][0-9]+)?$ ]]; then
+        log error "Error: --max-rps must be a non-negative number"
+        exit 1
+    fi
+    if awk "BEGIN {exit !($MAX_RPS == 0)}"; then
+        MAX_RPS=0
+    fi
+
+    if ! [[ "$MAX_RETRIES" =~ ^[0-9]+$ ]]; then
+        log error "Error: --max-retries must be a non-negative integer"
+        exit 1
+    fi
+
+    if ! [[ "$RETRY_BASE_DELAY_MS" =~ ^[0-9]+$ ]]; then
+        log error "Error: --retry-base-delay-ms must be a non-negative integer"
+        exit 1
+    fi
+    if ! [[ "$BATCH_TIMEOUT" =~ ^[0-9]+$ ]]; then
+        log error "Error: --batch-timeout must be a non-negative number of seconds"
+        exit 1
+    fi
+    if ! [[ "$OUTPUT_BUFFER" =~ ^[1-9][0-9]*$ ]]; then
+        log error "Error: --output-buffer must be a positive number of batches"
+        exit 1
+    fi
+    if ! [[ "$MAX_PENDING_BLOCKS" =~ ^[1-9][0-9]*$ ]]; then
+        log error "Error: --max-pending-blocks must be a positive number of blocks"
+        exit 1
+    fi
+
+    # Adaptive mode may ramp well past the fixed default unless capped explicitly
+    if [[ "$ADAPTIVE_CONCURRENCY" == "true" && "$max_concurrent_given" == "false" ]]; then
+        MAX_CONCURRENT=20
+    fi
+    if ! [[ "$MAX_CONCURRENT" =~ ^[1-9][0-9]*$ && "$MIN_CONCURRENT" =~ ^[1-9][0-9]*$ ]] ||
+        [[ $MIN_CONCURRENT -gt $MAX_CONCURRENT ]]; then
+        log error "Error: --min-concurrent and --max-concurrent must be positive integers with min <= max"
+        exit 1
+    fi
+
+    if ! [[ "$confirmations" =~ ^[0-9]+$ ]]; then
+        log error "Error: --confirmations must be a non-negative integer"
+        exit 1
+    fi
+    if ! [[ "$max_blocks" =~ ^[1-9][0-9]*$ ]]; then
+        log error "Error: --max-blocks must be a positive integer"
+        exit 1
+    fi
+    if [[ "$VERIFY_CHAIN" == "true" && "$USE_TRACE_FILTER" == "true" ]]; then
+        log error "Error: --verify-chain checks the blocks of a block scan, so it can't be combined with --use-trace-filter"
+        exit 1
+    fi
 
-# Fetch transactions from a single block
-fetch_block_transactions() {
-    local rpc_url="$1"
-    local block_number="$2"
-    local target_contract="$3"
-    local output_file="$4"
-    local rpc_counter_dir="$5"
-
-    # Convert block number to hex
-    local block_hex
-    block_hex=$(printf "0x%x" "$block_number")
-
-    # Prepare RPC request
-    local rpc_request
-    rpc_request=$(jq -n \
-        --arg method "eth_getBlockByNumber" \
-        --arg block_hex "$block_hex" \
-        '{
-            "jsonrpc": "2.0",
-            "method": $method,
-            "params": [$block_hex, true],
-            "id": 1
-        }')
-
-    # Make the request with retry logic (max 5 retries)
-    local response
-    echo "1" >> "$rpc_counter_dir/block_fetch.count"
-    response=$(retry_with_backoff 5 curl -s -X POST \
-        -H "Content-Type: application/json" \
-        -d "$rpc_request" \
-        --max-time 30 \
-        "$rpc_url")
-
-    if [[ -z "$response" ]]; then
-        echo "Error: Failed to fetch block $block_number after retries" >&2
-        return 1
-    fi
-
-    # Check for RPC errors
-    local error
-    if ! error=$(echo "$response" | jq -r '.error // empty' 2>/dev/null); then
-        echo "Error: Invalid JSON response for block $block_number" >&2
-        return 1
-    fi
-
-    if [[ -n "$error" && "$error" != "null" ]]; then
-        echo "Error: RPC error for block $block_number: $error" >&2
-        return 1
-    fi
-
-    # Extract block data
-    local block_data
-    if ! block_data=$(echo "$response" | jq -r '.result // empty' 2>/dev/null); then
-        echo "Error: Invalid JSON response for block $block_number" >&2
-        return 1
-    fi
-
-    if [[ -z "$block_data" || "$block_data" == "null" || "$block_data" == "empty" ]]; then
-        echo "Warning: No block data for block $block_number" >&2
-        return 1
-    fi
-
-    # Get block number and transactions
-    local block_num_hex
-    if ! block_num_hex=$(echo "$block_data" | jq -r '.number' 2>/dev/null); then
-        echo "Error: Failed to parse block number for block $block_number" >&2
-        return 1
-    fi
-
-    local transactions
-    if ! transactions=$(echo "$block_data" | jq -c '.transactions[]? // empty' 2>/dev/null); then
-        echo "Error: Failed to parse transactions for block $block_number" >&2
-        return 1
-    fi
-
-    # Convert block number to decimal
-    local block_num_decimal
-    block_num_decimal=$(hex_to_decimal "$block_num_hex")
-
-    # Filter transactions that interact with the target contract
-    local target_contract_lower
-    target_contract_lower=$(echo "$target_contract" | tr '[:upper:]' '[:lower:]')
-
-    # Process each transaction - only check direct calls (tx.to == target)
-    while IFS= read -r tx; do
-        [[ -z "$tx" ]] && continue
-
-        local tx_hash
-        local tx_to
-        tx_hash=$(echo "$tx" | jq -r '.hash')
-        tx_to=$(echo "$tx" | jq -r '.to // empty')
-
-        # Check if this is a direct call to target contract
-        if [[ -n "$tx_to" ]]; then
-            local tx_to_lower
-            tx_to_lower=$(echo "$tx_to" | tr '[:upper:]' '[:lower:]')
-            if [[ "$tx_to_lower" == "$target_contract_lower" ]]; then
-                # Direct call found - check if transaction succeeded on-chain
-                local receipt_request
-                receipt_request=$(jq -n \
-                    --arg tx_hash "$tx_hash" \
-                    '{
-                        "jsonrpc": "2.0",
-                        "method": "eth_getTransactionReceipt",
-                        "params": [$tx_hash],
-                        "id": 1
-                    }')
-
-                echo "1" >> "$rpc_counter_dir/receipt_fetch.count"
-                local receipt_response
-                receipt_response=$(retry_with_backoff 5 curl -s -X POST \
-                    -H "Content-Type: application/json" \
-                    -d "$receipt_request" \
-                    --max-time 30 \
-                    "$rpc_url")
-
-                local tx_status
-                tx_status=$(echo "$receipt_response" | jq -r '.result.status // empty')
-
-                # Only output transaction if it succeeded (status == "0x1")
-                if [[ "$tx_status" == "0x1" ]]; then
-                    local tx_from
-                    local tx_value
-                    local tx_input
-                    local tx_index_hex
-                    local tx_gas_price
-                    local tx_gas_limit
-                    local tx_max_fee_per_gas
-                    local tx_max_priority_fee_per_gas
-                    tx_from=$(echo "$tx" | jq -r '.from')
-                    tx_value=$(echo "$tx" | jq -r '.value')
-                    tx_input=$(echo "$tx" | jq -r '.input')
-                    tx_index_hex=$(echo "$tx" | jq -r '.transactionIndex')
-                    tx_gas_price=$(echo "$tx" | jq -r '.gasPrice // "0x0"')
-                    tx_gas_limit=$(echo "$tx" | jq -r '.gas // "0x0"')
-                    tx_max_fee_per_gas=$(echo "$tx" | jq -r '.maxFeePerGas // "0x0"')
-                    tx_max_priority_fee_per_gas=$(echo "$tx" | jq -r '.maxPriorityFeePerGas // "0x0"')
-
-                    # Convert transaction index to decimal
-                    local tx_index_decimal
-                    tx_index_decimal=$(hex_to_decimal "$tx_index_hex")
-
-                    # Output transaction in the format: hash|from|to|value|data|blockNumber|txIndex|gasPrice|gasLimit|maxFeePerGas|maxPriorityFeePerGas
-                    echo "$tx_hash|$tx_from|$tx_to|$tx_value|$tx_input|$block_num_decimal|$tx_index_decimal|$tx_gas_price|$tx_gas_limit|$tx_max_fee_per_gas|$tx_max_priority_fee_per_gas" >> "$output_file"
-                fi
-            fi
+    # A zero value is the only one below 1 wei, so --nonzero-value is a lower bound like any other
+    if [[ "$nonzero_value" == "true" ]]; then
+        local one_wei
+        one_wei=$(wei_to_word 1)
+        if [[ -z "$MIN_VALUE" || "$MIN_VALUE" < "$one_wei" ]]; then
+            MIN_VALUE="$one_wei"
         fi
-    done <<< "$transactions"
-}
+    fi
 
-# Process a batch of blocks
-process_batch() {
-    local rpc_url="$1"
-    local start_block="$2"
-    local end_block="$3"
-    local target_contract="$4"
-    local batch_id="$5"
-    local max_concurrent="$6"
-
-    local batch_output="$TEMP_DIR/batch_$batch_id.txt"
-    touch "$batch_output"
-
-    # Create array of block numbers in this batch
-    local blocks=()
-    for ((block=start_block; block<=end_block; block++)); do
-        blocks+=("$block")
-    done
+    if [[ -n "$MIN_VALUE" && -n "$MAX_VALUE" && "$MIN_VALUE" > "$MAX_VALUE" ]]; then
+        log error "Error: --min-value must not be greater than --max-value"
+        exit 1
+    fi
+    if [[ -n "$MIN_GAS_PRICE" && -n "$MAX_GAS_PRICE" && "$MIN_GAS_PRICE" > "$MAX_GAS_PRICE" ]]; then
+        log error "Error: --min-gas-price must not be greater than --max-gas-price"
+        exit 1
+    fi
 
-    echo "Processing batch $batch_id: blocks $start_block to $end_block" >&2
+    # Check dependencies
+    check_dependencies
 
-    # Process blocks with concurrency limit
-    local pids=()
-    local block_index=0
+    # Find out now rather than at the first batch that checksums can't be computed here
+    if [[ "$CHECKSUM_ADDRESSES" == "true" ]] && ! keccak256 "" > /dev/null; then
+        log error "Error: --checksum-addresses needs keccak-256 from OpenSSL 3.2+ or cast (Foundry)"
+        exit 1
+    fi
 
-    while [[ $block_index -lt ${#blocks[@]} ]]; do
-        # Start new jobs up to the concurrency limit
-        while [[ ${#pids[@]} -lt $max_concurrent && $block_index -lt ${#blocks[@]} ]]; do
-            local block_num="${blocks[$block_index]}"
-            fetch_block_transactions "$rpc_url" "$block_num" "$target_contract" "$batch_output" "$RPC_COUNTER_DIR" &
-            pids+=($!)
-            ((block_index++))
-        done
+    # Refuse to scan the wrong network before any block is fetched; without an expectation the
+    # chain id is only reported, so a node that can't tell it doesn't stop the run
+    if [[ -n "$expect_chain_id" ]]; then
+        chain_id=$(get_chain_id) || exit 1
+        if [[ "$chain_id" != "$expect_chain_id" ]]; then
+            log error "Error: RPC endpoint is on chain $chain_id, but --expect-chain-id is $expect_chain_id"
+            exit 1
+        fi
+    elif [[ -z "$chain_id" && -z "$input_file" ]]; then
+        chain_id=$(get_chain_id 2>/dev/null) || chain_id=""
+    fi
 
-        # Wait for at least one job to complete
-        if [[ ${#pids[@]} -gt 0 ]]; then
-            wait "${pids[0]}"
-            pids=("${pids[@]:1}")  # Remove first element
+    if [[ -n "$abi_file" ]]; then
+        if [[ ! -r "$abi_file" ]]; then
+            log error "Error: --abi file not found: $abi_file"
+            exit 1
         fi
-    done
+        abi_load "$abi_file" || exit 1
+    fi
 
-    # Wait for all remaining jobs
-    if [[ ${#pids[@]} -gt 0 ]]; then
-        wait "${pids[@]}"
+    if [[ -n "$input_file" && ( -z "$start_block" || -z "$end_block" ) ]]; then
+        local dump_range
+        dump_range=$(dump_block_range "$input_file") || exit 1
+        local dump_start
+        local dump_end
+        read -r dump_start dump_end <<< "$dump_range"
+        start_block=${start_block:-$dump_start}
+        end_block=${end_block:-$dump_end}
     fi
 
-    # Count transactions found in this batch
-    local tx_count=0
-    if [[ -f "$batch_output" ]]; then
-        tx_count=$(wc -l < "$batch_output" 2>/dev/null || echo "0")
+    if [[ "$start_block" =~ $block_tags ]]; then
+        local start_tag="$start_block"
+        start_block=$(resolve_block_tag "$start_tag") || exit 1
+        log info "Resolved --start-block $start_tag to block $start_block"
+    fi
+    if [[ "$end_block" =~ $block_tags ]]; then
+        local end_tag="$end_block"
+        end_block=$(resolve_block_tag "$end_tag") || exit 1
+        log info "Resolved --end-block $end_tag to block $end_block"
     fi
 
-    if [[ $tx_count -gt 0 ]]; then
-        echo "  Batch $batch_id: found $tx_count transactions" >&2
+    # An incremental run picks up after the checkpoint and goes as far as the node has confirmed
+    if [[ "$SINCE_LAST_RUN" == "true" ]]; then
+        TARGET_CONTRACTS="$target_contracts"
+        local last_run_block
+        last_run_block=$(checkpoint_last_block) || exit 1
+        if [[ -n "$last_run_block" ]]; then
+            start_block=$((last_run_block + 1))
+            log info "Continuing from checkpoint $CHECKPOINT_FILE at block $start_block"
+        elif [[ -z "$start_block" ]]; then
+            log error "Error: $CHECKPOINT_FILE holds no checkpoint yet; pass --start-block for the first --since-last-run"
+            exit 1
+        fi
+        end_block=$(resolve_block_tag "latest") || exit 1
+        end_block=$((end_block - confirmations))
+        if [[ $start_block -gt $end_block ]]; then
+            log info "No confirmed blocks after block $((start_block - 1)) yet; nothing to fetch"
+        fi
     fi
 
-    # Always return a number (default to 0 if empty)
-    echo "${tx_count:-0}"
-}
+    if [[ -n "$start_timestamp" || -n "$end_timestamp" ]]; then
+        local resolved_range
+        if ! resolved_range=$(resolve_timestamp_range "$start_timestamp" "$end_timestamp"); then
+            exit 1
+        fi
+        local resolved_start
+        local resolved_end
+        read -r resolved_start resolved_end <<< "$resolved_range"
+        if [[ -n "$start_timestamp" ]]; then
+            start_block=$resolved_start
+        fi
+        if [[ -n "$end_timestamp" ]]; then
+            end_block=$resolved_end
+        fi
+        log info "Resolved timestamps ${start_timestamp:--}..${end_timestamp:--} to blocks $start_block..$end_block"
+    fi
 
-# Format transactions for output
-format_transactions() {
-    local output_format="$1"
-    local all_transactions_file="$2"
-
-    if [[ ! -f "$all_transactions_file" ]] || [[ ! -s "$all_transactions_file" ]]; then
-        echo "0"
-        return
-    fi
-
-    # Count transactions (one per line)
-    local tx_count
-    tx_count=$(wc -l < "$all_transactions_file" | tr -d ' ')
-
-    case "$output_format" in
-        "json")
-            # Convert to JSON format
-            echo "["
-            local first=true
-            while IFS='|' read -r hash from to value data block_number tx_index gas_price gas_limit max_fee_per_gas max_priority_fee_per_gas; do
-                if [[ "$first" == "true" ]]; then
-                    first=false
-                else
-                    echo ","
-                fi
-                jq -n \
-                    --arg hash "$hash" \
-                    --arg from "$from" \
-                    --arg to "$to" \
-                    --arg value "$value" \
-                    --arg data "$data" \
-                    --arg block_number "$block_number" \
-                    --arg tx_index "$tx_index" \
-                    --arg gas_price "$gas_price" \
-                    --arg gas_limit "$gas_limit" \
-                    --arg max_fee_per_gas "$max_fee_per_gas" \
-                    --arg max_priority_fee_per_gas "$max_priority_fee_per_gas" \
-                    '{
-                        hash: $hash,
-                        from: $from,
-                        to: $to,
-                        value: $value,
-                        data: $data,
-                        block_number: $block_number,
-                        transaction_index: $tx_index,
-                        gas_price: $gas_price,
-                        gas_limit: $gas_limit,
-                        max_fee_per_gas: $max_fee_per_gas,
-                        max_priority_fee_per_gas: $max_priority_fee_per_gas
-                    }' | tr -d '\n'
-            done < "$all_transactions_file"
-            echo
-            echo "]"
-            ;;
-        *)
-            # Simple format: count|hash|from|to|value|data|blockNumber|txIndex|gasPrice|gasLimit|maxFeePerGas|maxPriorityFeePerGas|...
-            echo -n "$tx_count"
-            while IFS='|' read -r hash from to value data block_number tx_index gas_price gas_limit max_fee_per_gas max_priority_fee_per_gas; do
-                echo -n "|$hash|$from|$to|$value|$data|$block_number|$tx_index|$gas_price|$gas_limit|$max_fee_per_gas|$max_priority_fee_per_gas"
-            done < "$all_transactions_file"
-            ;;
-    esac
-}
+    if [[ $start_block -gt $end_block && "$SINCE_LAST_RUN" != "true" ]]; then
+        if [[ -n "$start_timestamp" || -n "$end_timestamp" ]]; then
+            log error "Error: No blocks fall within the requested timestamp window"
+        else
+            log error "Error: Start block $start_block is after end block $end_block (reversed range?)"
+        fi
+        exit 1
+    fi
 
-# Main function
-main() {
-    local rpc_url=""
-    local target_contract=""
-    local start_block=""
-    local end_block=""
+    # A range past the chain tip would only produce failed blocks, so clamp it to what exists
+    local latest_block
+    if [[ -z "$input_file" ]]; then
+        latest_block=$(resolve_block_tag "latest") || exit 1
+    else
+        latest_block=$end_block
+    fi
+    if [[ $start_block -gt $latest_block && "$SINCE_LAST_RUN" != "true" ]]; then
+        log error "Error: Start block $start_block is beyond the chain tip (latest block is $latest_block)"
+        exit 1
+    fi
+    if [[ $end_block -gt $latest_block ]]; then
+        log warn "Warning: End block $end_block is beyond the chain tip; clamping to latest block $latest_block"
+        end_block=$latest_block
+    fi
 
-    # Parse command line arguments
-    while [[ $# -gt 0 ]]; do
-        case $1 in
-            --rpc-url)
-                rpc_url="$2"
-                shift 2
-                ;;
-            --target-contract)
-                target_contract="$2"
-                shift 2
-                ;;
-            --start-block)
-                start_block="$2"
-                shift 2
-                ;;
-            --end-block)
-                end_block="$2"
-                shift 2
-                ;;
-            --output-format)
-                OUTPUT_FORMAT="$2"
-                shift 2
-                ;;
-            --batch-size)
-                BATCH_SIZE="$2"
-                shift 2
-                ;;
-            --max-concurrent)
-                MAX_CONCURRENT="$2"
-                shift 2
-                ;;
-            --detailed-blocks)
-                DETAILED_BLOCKS=true
-                shift
-                ;;
-            --use-trace-filter)
-                USE_TRACE_FILTER=true
-                shift
-                ;;
-            --trace-filter-batch-size)
-                TRACE_FILTER_BATCH_SIZE="$2"
-                shift 2
-                ;;
-            -h|--help)
-                usage
-                exit 0
-                ;;
-            *)
-                echo "Error: Unknown option $1" >&2
-                usage
-                exit 1
-                ;;
-        esac
-    done
+    # Likewise keep --confirmations blocks between the range and the tip
+    if [[ $confirmations -gt 0 ]]; then
+        local confirmed_block=$((latest_block - confirmations))
+        if [[ $start_block -gt $confirmed_block && "$SINCE_LAST_RUN" != "true" ]]; then
+            log error "Error: Start block $start_block has fewer than $confirmations confirmations (latest block is $latest_block)"
+            exit 1
+        fi
+        if [[ $end_block -gt $confirmed_block ]]; then
+            log warn "Warning: End block $end_block has fewer than $confirmations confirmations; clamping to block $confirmed_block"
+            end_block=$confirmed_block
+        fi
+    fi
 
-    # Validate required arguments
-    if [[ -z "$rpc_url" || -z "$target_contract" || -z "$start_block" || -z "$end_block" ]]; then
-        echo "Error: Missing required arguments" >&2
-        usage
-        exit 1
+    # Listed blocks past the clamped end don't exist yet (or lack confirmations)
+    if [[ -n "$BLOCK_LIST_FILE" ]]; then
+        local listed_blocks
+        listed_blocks=$(wc -l < "$BLOCK_LIST_FILE")
+        awk -v end="$end_block" '$1 <= end' "$BLOCK_LIST_FILE" > "$BLOCK_LIST_FILE.kept"
+        mv "$BLOCK_LIST_FILE.kept" "$BLOCK_LIST_FILE"
+        local dropped_blocks=$((listed_blocks - $(wc -l < "$BLOCK_LIST_FILE")))
+        if [[ $dropped_blocks -gt 0 ]]; then
+            log warn "Warning: Skipping $dropped_blocks listed blocks after block $end_block"
+        fi
+        end_block=$(tail -n 1 "$BLOCK_LIST_FILE")
     fi
 
-    # Validate numeric arguments
-    if ! [[ "$start_block" =~ ^[0-9]+$ ]] || ! [[ "$end_block" =~ ^[0-9]+$ ]]; then
-        echo "Error: Block numbers must be positive integers" >&2
+    # A block number off by a few digits would hammer the endpoint for hours, so ask first
+    local range_blocks=$((end_block - start_block + 1))
+    if [[ -n "$BLOCK_LIST_FILE" ]]; then
+        range_blocks=$(wc -l < "$BLOCK_LIST_FILE" | tr -d ' ')
+    fi
+    if [[ $range_blocks -gt $max_blocks && "$yes" != "true" && -z "$input_file" ]]; then
+        log error "Error: Blocks $start_block..$end_block are $range_blocks blocks, more than --max-blocks $max_blocks; check the range, or pass --yes (or a higher --max-blocks) to fetch it anyway"
         exit 1
     fi
 
-    if [[ $start_block -gt $end_block ]]; then
-        echo "Error: Start block must be less than or equal to end block" >&2
+    # State diffs are fetched after the scan, so find out now rather than after a long run
+    if [[ "$WITH_STATE_DIFF" == "true" ]] && ! state_diff_supported; then
+        log error "Error: --with-state-diff needs debug_traceTransaction, which this RPC endpoint does not support (use a debug-enabled archive node)"
+        exit 1
+    fi
+    if [[ "$WITH_CALL_TRACE" == "true" ]] && ! call_trace_supported; then
+        log error "Error: --with-call-trace needs debug_traceTransaction with the callTracer, which this RPC endpoint does not support"
         exit 1
     fi
 
-    # Check dependencies
-    check_dependencies
+    if [[ -n "$cache_dir" && "$no_cache" != "true" && -z "$input_file" ]]; then
+        if [[ -z "$chain_id" ]]; then
+            chain_id=$(get_chain_id) || exit 1
+        fi
+        BLOCK_CACHE_DIR="$cache_dir/$chain_id/blocks"
+        if ! mkdir -p "$BLOCK_CACHE_DIR"; then
+            log error "Error: Cannot create cache directory $BLOCK_CACHE_DIR"
+            exit 1
+        fi
+        # Finalized blocks can't be reorged away; without the tag, stay well behind the tip
+        if ! BLOCK_CACHE_MAX_BLOCK=$(resolve_block_tag "finalized" 2>/dev/null); then
+            BLOCK_CACHE_MAX_BLOCK=$((latest_block - 64))
+            log warn "Warning: Node has no finalized block; caching only blocks up to $BLOCK_CACHE_MAX_BLOCK"
+        fi
+    fi
+
+    # The bar redraws in place, so it would only corrupt captured output or JSON log lines
+    if [[ "$quiet" != "true" && "$JSON_LOGS" != "true" && -t 1 && -t 2 ]]; then
+        SHOW_PROGRESS=true
+    fi
 
     # Start timing
     START_TIME=$(date +%s)
+    local start_ms
+    start_ms=$(now_ms)
+
+    TARGET_CONTRACTS="$target_contracts"
+    START_BLOCK="$start_block"
+    END_BLOCK="$end_block"
+
+    # Describe the run before it scans anything, so a crash leaves a manifest that the next
+    # --resume-output can check the partial file against
+    if [[ "$RESUME_OUTPUT" == "true" ]]; then
+        if [[ -z "$chain_id" ]]; then
+            chain_id=$(get_chain_id) || exit 1
+        fi
+        resume_output_prepare "$manifest" "$chain_id" || exit 1
+        SCANNED_TO_BLOCK=$((${RESUME_FROM_BLOCK:-$start_block} - 1))
+        : >> "$OUTPUT_FILE"
+        write_manifest "$manifest" "$OUTPUT_FILE" "$chain_id" "$(wc -l < "$OUTPUT_FILE" | tr -d ' ')"
+    fi
 
-    # Process blocks in batches
     local all_transactions_file="$TEMP_DIR/all_transactions.txt"
-    touch "$all_transactions_file"
-
-    local total_blocks_processed=0
-    local total_transactions_found=0
-    local batch_id=0
-
-    # Choose processing method and batch size
-    local batch_size
-    if [[ "$USE_TRACE_FILTER" == "true" ]]; then
-        TRACE_METHOD="trace_filter"
-        echo "" >&2
-        echo "=== TRANSACTION DISCOVERY ===" >&2
-        echo "Target: $target_contract" >&2
-        echo "Blocks: $start_block to $end_block" >&2
-        echo "" >&2
-        echo "[INFO] Detecting both direct calls AND internal/nested calls to target" >&2
-        echo "[INFO] Trying trace APIs with automatic fallback..." >&2
-        echo "" >&2
-        batch_size=$TRACE_FILTER_BATCH_SIZE
+    if [[ -n "$input_file" ]]; then
+        refilter_transactions "$input_file" "$all_transactions_file" || exit 1
+    elif [[ $start_block -gt $end_block ]]; then
+        # Only --since-last-run gets here, when no block has been confirmed since the last run
+        : > "$all_transactions_file"
+    elif [[ "$headers_only" == "true" ]]; then
+        fetch_block_header_records "$all_transactions_file"
     else
-        echo "" >&2
-        echo "=== TRANSACTION DISCOVERY ===" >&2
-        echo "Target: $target_contract" >&2
-        echo "Blocks: $start_block to $end_block" >&2
-        echo "" >&2
-        echo "[INFO] Direct calls only mode (no internal call detection)" >&2
-        echo "" >&2
-        batch_size=$BATCH_SIZE
-    fi
-
-    # Unified batch processing loop
-    for ((batch_start=start_block; batch_start<=end_block; batch_start+=batch_size)); do
-        local batch_end=$((batch_start + batch_size - 1))
-        if [[ $batch_end -gt $end_block ]]; then
-            batch_end=$end_block
-        fi
+        fetch_transactions "$all_transactions_file"
+    fi
 
-        local batch_file="$TEMP_DIR/batch_$batch_id.txt"
-        touch "$batch_file"
-
-        # Call appropriate processing function
-        # Note: We use "|| true" to prevent set -e from exiting on non-zero return codes
-        # The return code is captured in status for fallback handling
-        local tx_count
-        if [[ "$USE_TRACE_FILTER" == "true" ]]; then
-            local status=0
-            while true; do
-                if [[ "$TRACE_METHOD" == "trace_filter" ]]; then
-                    tx_count=$(fetch_transactions_trace_filter "$rpc_url" "$batch_start" "$batch_end" "$target_contract" "$batch_file") || status=$?
-                    if [[ $status -eq 2 ]]; then
-                        TRACE_METHOD="debug_trace_block"
-                        echo "[TRACE] Falling back to debug_traceBlockByNumber (slower but widely supported)" >&2
-                        status=0
-                        continue
-                    fi
-                elif [[ "$TRACE_METHOD" == "debug_trace_block" ]]; then
-                    tx_count=$(fetch_transactions_debug_trace_block "$rpc_url" "$batch_start" "$batch_end" "$target_contract" "$batch_file") || status=$?
-                    if [[ $status -eq 2 ]]; then
-                        TRACE_METHOD="debug_trace_tx"
-                        echo "[TRACE] Falling back to debug_traceTransaction (slowest, per-transaction tracing)" >&2
-                        status=0
-                        continue
-                    fi
-                else
-                    tx_count=$(fetch_transactions_debug_trace_tx "$rpc_url" "$batch_start" "$batch_end" "$target_contract" "$batch_file") || status=$?
-                    if [[ $status -eq 2 ]]; then
-                        echo "[WARN] No trace APIs supported by this RPC - falling back to direct calls only" >&2
-                        echo "[WARN] Internal/nested calls to target contract will NOT be detected" >&2
-                        echo "[WARN] Consider using an RPC with debug/trace API support for complete coverage" >&2
-                        TRACE_METHOD=""
-                        tx_count=$(process_batch "$rpc_url" "$batch_start" "$batch_end" "$target_contract" "$batch_id" "$MAX_CONCURRENT")
-                    fi
-                fi
-                break
-            done
-        else
-            tx_count=$(process_batch "$rpc_url" "$batch_start" "$batch_end" "$target_contract" "$batch_id" "$MAX_CONCURRENT")
+    # Blocks past a failed one are fetched again by the next incremental run, so they aren't output twice
+    if [[ "$SINCE_LAST_RUN" == "true" ]]; then
+        local covered_block
+        covered_block=$(jq -r '.last_block' "$CHECKPOINT_FILE" 2>/dev/null || echo $((start_block - 1)))
+        local output_end_block=$end_block
+        if [[ "$INTERRUPTED" == "true" ]]; then
+            output_end_block=$SCANNED_TO_BLOCK
         fi
-        tx_count=${tx_count:-0}
-
-        # Collect results from this batch
-        if [[ -f "$batch_file" && -s "$batch_file" ]]; then
-            cat "$batch_file" >> "$all_transactions_file"
-            total_transactions_found=$((total_transactions_found + tx_count))
+        if [[ $covered_block -lt $output_end_block ]]; then
+            log warn "Warning: Blocks $((covered_block + 1))..$output_end_block are left for the next --since-last-run"
+            jq -c --argjson last_block "$covered_block" 'select(.block_number <= $last_block)' \
+                "$all_transactions_file" > "$all_transactions_file.covered"
+            mv "$all_transactions_file.covered" "$all_transactions_file"
         fi
+    fi
 
-        total_blocks_processed=$((total_blocks_processed + batch_end - batch_start + 1))
-        batch_id=$((batch_id + 1))
-    done
+    # An interrupted run reports (and outputs) only the blocks it got through
+    local scanned_end_block=$end_block
+    if [[ "$INTERRUPTED" == "true" ]]; then
+        scanned_end_block=$SCANNED_TO_BLOCK
+    fi
+    local total_blocks_processed=$((scanned_end_block - start_block + 1))
+    if [[ -n "$BLOCK_LIST_FILE" ]]; then
+        total_blocks_processed=$(awk -v end="$scanned_end_block" '$1 <= end' "$BLOCK_LIST_FILE" | wc -l | tr -d ' ')
+    fi
+    local total_transactions_found
+    total_transactions_found=$(wc -l < "$all_transactions_file" | tr -d ' ')
+
+    # Blocks the output has nothing for, including those left for the next --since-last-run;
+    # a re-filtered dump has no range to check
+    local gaps="$TEMP_DIR/gaps.txt"
+    local missing_blocks="null"
+    : > "$gaps"
+    if [[ -z "$input_file" ]]; then
+        if [[ $start_block -le $end_block ]]; then
+            local last_covered_block=$SCANNED_TO_BLOCK
+            if [[ "$SINCE_LAST_RUN" == "true" && $covered_block -lt $last_covered_block ]]; then
+                last_covered_block=$covered_block
+            fi
+            range_gaps "$start_block" "$end_block" "$last_covered_block" > "$gaps"
+        fi
+        missing_blocks=$(jq -s -c '.' "$gaps")
+        report_range_gaps "$gaps"
+    fi
+    if [[ -n "$gaps_file" ]]; then
+        cp "$gaps" "$gaps_file"
+        if [[ -s "$gaps" ]]; then
+            log info "Wrote the $(wc -l < "$gaps" | tr -d ' ') missing blocks to $gaps_file; fetch them with --blocks-file $gaps_file"
+        fi
+    fi
 
     # Calculate timing
     local end_time
     end_time=$(date +%s)
     local duration=$((end_time - START_TIME))
+    local elapsed_ms=$(($(now_ms) - start_ms))
 
-    echo "" >&2
-    echo "=== DISCOVERY COMPLETE ===" >&2
+    log info ""
+    log info "=== DISCOVERY COMPLETE ==="
 
     # Report which trace method was used
-    if [[ "$USE_TRACE_FILTER" == "true" ]]; then
+    if [[ -n "$input_file" ]]; then
+        log info "[INFO] Detection method: re-filtered from $input_file"
+    elif [[ "$headers_only" == "true" ]]; then
+        log info "[INFO] Detection method: block headers only"
+    elif [[ "$USE_TRACE_FILTER" == "true" ]]; then
         if [[ -n "$TRACE_METHOD" ]]; then
             case "$TRACE_METHOD" in
                 "trace_filter")
-                    echo "[INFO] Detection method: trace_filter (fastest)" >&2
+                    log info "[INFO] Detection method: trace_filter (fastest)"
                     ;;
                 "debug_trace_block")
-                    echo "[INFO] Detection method: debug_traceBlockByNumber" >&2
+                    log info "[INFO] Detection method: debug_traceBlockByNumber"
                     ;;
                 "debug_trace_tx")
-                    echo "[INFO] Detection method: debug_traceTransaction (slowest)" >&2
+                    log info "[INFO] Detection method: debug_traceTransaction (slowest)"
                     ;;
             esac
-            echo "[INFO] Internal calls: ENABLED" >&2
+            log info "[INFO] Internal calls: ENABLED"
         else
-            echo "[WARN] Detection method: direct calls only (no trace API available)" >&2
-            echo "[WARN] Internal calls: DISABLED" >&2
+            log warn "[WARN] Detection method: direct calls only (no trace API available)"
+            log warn "[WARN] Internal calls: DISABLED"
         fi
+    elif [[ "$BLOCK_TRACE_METHOD" == "trace_block" ]]; then
+        log info "[INFO] Detection method: trace_block per block"
+    elif [[ "$BLOCK_TRACE_METHOD" == "debug_trace_block" ]]; then
+        log info "[INFO] Detection method: debug_traceBlockByNumber per block"
+    elif [[ "$MATCH_BY" == "logs" ]]; then
+        log info "[INFO] Detection method: direct calls and target event logs"
     else
-        echo "[INFO] Detection method: direct calls only" >&2
+        log info "[INFO] Detection method: direct calls only"
     fi
 
-    echo "" >&2
-    echo "Completed in ${duration}s" >&2
-    echo "Processed $total_blocks_processed blocks, found $total_transactions_found transactions" >&2
+    log info ""
+    log info "Completed in ${duration}s"
+    log info "Chain id: ${chain_id:-unknown}"
+    if [[ "$headers_only" == "true" ]]; then
+        log info "Processed $total_blocks_processed blocks, fetched $total_transactions_found headers"
+    else
+        log info "Processed $total_blocks_processed blocks, found $total_transactions_found transactions" \
+            "blocks=$total_blocks_processed" "transactions=$total_transactions_found"
+    fi
+    if [[ "$INTERRUPTED" == "true" ]]; then
+        if [[ -n "$CHECKPOINT_FILE" ]]; then
+            log warn "Interrupted after block $scanned_end_block of $end_block; run again with the same --checkpoint-file to resume"
+        else
+            log warn "Interrupted after block $scanned_end_block of $end_block; the output covers blocks $start_block to $scanned_end_block only"
+        fi
+    fi
 
     if [[ $duration -gt 0 ]]; then
         local blocks_per_sec=$((total_blocks_processed / duration))
         local tx_per_sec=$((total_transactions_found / duration))
-        echo "Average: $blocks_per_sec blocks/sec, $tx_per_sec transactions/sec" >&2
+        log info "Average: $blocks_per_sec blocks/sec, $tx_per_sec transactions/sec"
     fi
 
     # Display RPC call statistics
     local block_fetch_count
+    local block_batch_count
     local detailed_block_count
     local trace_filter_count
     local debug_trace_block_count
+    local trace_block_count
     local debug_trace_tx_count
     local tx_fetch_count
     local receipt_fetch_count
+    local log_fetch_count
+    local receipt_enrich_count
+    local state_diff_count
+    local call_trace_count
+    local access_list_count
+    local block_receipts_count
+    local block_header_count
+    local chain_id_count
+    local block_cache_hit_count
+    local rate_limit_retry_count
+    local request_retry_count
+    local block_retry_count
     block_fetch_count=$(count_rpc_calls "block_fetch")
+    block_batch_count=$(count_rpc_calls "block_batch")
     detailed_block_count=$(count_rpc_calls "detailed_block")
     trace_filter_count=$(count_rpc_calls "trace_filter")
     debug_trace_block_count=$(count_rpc_calls "debug_trace_block")
+    trace_block_count=$(count_rpc_calls "trace_block")
     debug_trace_tx_count=$(count_rpc_calls "debug_trace_tx")
     tx_fetch_count=$(count_rpc_calls "tx_fetch")
     receipt_fetch_count=$(count_rpc_calls "receipt_fetch")
-
-    local total_rpc_calls=$((block_fetch_count + detailed_block_count + trace_filter_count + debug_trace_block_count + debug_trace_tx_count + tx_fetch_count + receipt_fetch_count))
-
-    echo "" >&2
-    echo "=== RPC CALL STATISTICS ===" >&2
-    echo "Total RPC calls: $total_rpc_calls" >&2
-    echo "  - Block fetches: $block_fetch_count" >&2
+    log_fetch_count=$(count_rpc_calls "log_fetch")
+    receipt_enrich_count=$(count_rpc_calls "receipt_enrich")
+    state_diff_count=$(count_rpc_calls "state_diff")
+    call_trace_count=$(count_rpc_calls "call_trace")
+    access_list_count=$(count_rpc_calls "access_list")
+    block_receipts_count=$(count_rpc_calls "block_receipts")
+    block_header_count=$(count_rpc_calls "block_header")
+    chain_id_count=$(count_rpc_calls "chain_id")
+    block_cache_hit_count=$(count_rpc_calls "block_cache_hit")
+    rate_limit_retry_count=$(count_rpc_calls "rate_limit_retry")
+    request_retry_count=$(count_rpc_calls "request_retry")
+    block_retry_count=$(count_rpc_calls "block_retry")
+
+    local total_rpc_calls=$((block_fetch_count + block_batch_count + detailed_block_count + trace_filter_count + trace_block_count + debug_trace_block_count + debug_trace_tx_count + tx_fetch_count + receipt_fetch_count + log_fetch_count + receipt_enrich_count + state_diff_count + call_trace_count + access_list_count + block_receipts_count + block_header_count + chain_id_count))
+
+    log info ""
+    log info "=== RPC CALL STATISTICS ==="
+    log info "Total RPC calls: $total_rpc_calls"
+    log info "  - Block fetches: $block_fetch_count"
+    if [[ $block_batch_count -gt 0 ]]; then
+        log info "  - Batched block fetches (JSON-RPC batch): $block_batch_count"
+    fi
     if [[ $trace_filter_count -gt 0 ]]; then
-        echo "  - trace_filter calls: $trace_filter_count" >&2
+        log info "  - trace_filter calls: $trace_filter_count"
+    fi
+    if [[ $trace_block_count -gt 0 ]]; then
+        log info "  - trace_block calls: $trace_block_count"
     fi
     if [[ $debug_trace_block_count -gt 0 ]]; then
-        echo "  - debug_traceBlockByNumber calls: $debug_trace_block_count" >&2
+        log info "  - debug_traceBlockByNumber calls: $debug_trace_block_count"
     fi
     if [[ $debug_trace_tx_count -gt 0 ]]; then
-        echo "  - debug_traceTransaction calls: $debug_trace_tx_count" >&2
+        log info "  - debug_traceTransaction calls: $debug_trace_tx_count"
     fi
     if [[ $tx_fetch_count -gt 0 ]]; then
-        echo "  - Transaction fetches (eth_getTransactionByHash): $tx_fetch_count" >&2
+        log info "  - Transaction fetches (eth_getTransactionByHash): $tx_fetch_count"
     fi
     if [[ $receipt_fetch_count -gt 0 ]]; then
-        echo "  - Receipt fetches (status checks): $receipt_fetch_count" >&2
+        log info "  - Receipt fetches (status checks): $receipt_fetch_count"
+    fi
+    if [[ $block_header_count -gt 0 ]]; then
+        log info "  - Block header fetches: $block_header_count"
+    fi
+    if [[ $log_fetch_count -gt 0 ]]; then
+        log info "  - Log fetches (eth_getLogs): $log_fetch_count"
+    fi
+    if [[ $receipt_enrich_count -gt 0 ]]; then
+        log info "  - Receipt fetches (--with-receipts): $receipt_enrich_count"
+    fi
+    if [[ $state_diff_count -gt 0 ]]; then
+        log info "  - State diff traces (--with-state-diff): $state_diff_count"
+    fi
+    if [[ $call_trace_count -gt 0 ]]; then
+        log info "  - Call traces (--with-call-trace): $call_trace_count"
+    fi
+    if [[ $access_list_count -gt 0 ]]; then
+        log info "  - Access list derivations (eth_createAccessList): $access_list_count"
+    fi
+    if [[ $block_receipts_count -gt 0 ]]; then
+        log info "  - Block receipt fetches (eth_getBlockReceipts): $block_receipts_count"
     fi
     if [[ $detailed_block_count -gt 0 ]]; then
-        echo "  - Detailed block fetches: $detailed_block_count" >&2
+        log info "  - Detailed block fetches: $detailed_block_count"
+    fi
+    if [[ $block_cache_hit_count -gt 0 ]]; then
+        log info "Blocks read from cache (no RPC call): $block_cache_hit_count"
     fi
     if [[ $duration -gt 0 && $total_rpc_calls -gt 0 ]]; then
         local rpc_per_sec=$((total_rpc_calls / duration))
-        echo "Average: $rpc_per_sec RPC calls/sec" >&2
+        log info "Average: $rpc_per_sec RPC calls/sec"
+    fi
+    if [[ $((rate_limit_retry_count + request_retry_count + block_retry_count)) -gt 0 ]]; then
+        log info "Retries: $rate_limit_retry_count rate-limited requests, $request_retry_count empty responses, $block_retry_count blocks"
+    fi
+    if [[ "$ADAPTIVE_CONCURRENCY" == "true" ]]; then
+        log info "Adaptive concurrency: ended at $CONCURRENCY, peak $ADAPTIVE_PEAK (bounds $MIN_CONCURRENT-$MAX_CONCURRENT)"
+    fi
+    if [[ ${#RPC_URLS[@]} -gt 1 ]]; then
+        local index
+        for index in "${!RPC_URLS[@]}"; do
+            log info "  - Endpoint $(endpoint_label "${RPC_URLS[$index]}"): $(count_rpc_calls_in "$RPC_POOL_DIR" "$index.requests") requests, $(count_rpc_calls_in "$RPC_POOL_DIR" "$index.errors") failures"
+        done
+    fi
+    log info "==========================="
+
+    # A fetch that matched nothing at all is almost always pointed at the wrong contract or chain
+    if [[ $total_transactions_found -eq 0 && "$headers_only" != "true" && -z "$input_file" &&
+          "$INTERRUPTED" != "true" && $total_blocks_processed -gt 0 ]]; then
+        log warn "WARNING: No transactions matched ${TARGET_CONTRACTS//,/, } in blocks $start_block to $scanned_end_block on chain id ${chain_id:-unknown}"
+        log warn "  - Is the address right, and deployed on this chain? --expect-chain-id guards the network"
+        log warn "  - Does the range cover the contract's activity (after its deployment block)?"
+        if [[ "$MATCH_BY" == "to" && "$USE_TRACE_FILTER" != "true" ]]; then
+            log warn "  - Only direct calls were matched; calls through proxies or multicalls need --match-by logs or traces"
+        fi
+        if [[ -n "$SELECTORS$CONTRACT_SELECTORS$EXCLUDE_FROM$MIN_VALUE$MAX_VALUE$MIN_GAS_PRICE$MAX_GAS_PRICE" || "$SAMPLE_RATE" != "1" ]]; then
+            log warn "  - The --selector, --exclude-from, value, gas price or --sample-rate filters may have dropped every match"
+        fi
+    fi
+
+    # What the matches add up to, when the summary or --stats-json shows it; a re-filtered dump
+    # knows nothing of what reverted
+    local aggregates="null"
+    if [[ "$headers_only" != "true" ]] && { [[ -n "$stats_json" ]] || log_enabled info; }; then
+        local reverted_count="null"
+        if [[ -z "$input_file" ]]; then
+            reverted_count=0
+            if [[ -f "$RPC_COUNTER_DIR/reverted.hashes" ]]; then
+                reverted_count=$(sort -u "$RPC_COUNTER_DIR/reverted.hashes" | wc -l | tr -d ' ')
+            fi
+        fi
+        aggregates=$(transaction_aggregates "$all_transactions_file" "$reverted_count")
+        report_transaction_aggregates "$aggregates"
+    fi
+
+    # Which functions the matches call (--selector-stats)
+    local selectors="null"
+    if [[ "$selector_stats" == "true" ]]; then
+        selectors=$(selector_breakdown "$all_transactions_file")
+        report_selector_breakdown "$selectors"
+    fi
+
+    # Machine-readable summary, written before a failed run exits so CI can still track it
+    if [[ -n "$stats_json" ]]; then
+        local endpoints="[]"
+        local index
+        for index in "${!RPC_URLS[@]}"; do
+            endpoints=$(echo "$endpoints" | jq -c \
+                --arg endpoint "$(endpoint_label "${RPC_URLS[$index]}")" \
+                --argjson requests "$(count_rpc_calls_in "$RPC_POOL_DIR" "$index.requests")" \
+                --argjson failures "$(count_rpc_calls_in "$RPC_POOL_DIR" "$index.errors")" \
+                '. + [{endpoint: $endpoint, requests: $requests, failures: $failures}]')
+        done
+        jq -n \
+            --arg chain_id_value "$chain_id" \
+            --argjson start_block "$start_block" \
+            --argjson end_block "$end_block" \
+            --argjson blocks "$total_blocks_processed" \
+            --argjson transactions "$total_transactions_found" \
+            --argjson failed_blocks "$(sort -n -u "$FAILED_BLOCKS_FILE" | jq -s -c '.')" \
+            --argjson missing_blocks "$missing_blocks" \
+            --argjson elapsed_ms "$elapsed_ms" \
+            --argjson total_rpc_calls "$total_rpc_calls" \
+            --argjson block_fetch "$block_fetch_count" \
+            --argjson block_batch "$block_batch_count" \
+            --argjson trace_filter "$trace_filter_count" \
+            --argjson trace_block "$trace_block_count" \
+            --argjson debug_trace_block "$debug_trace_block_count" \
+            --argjson debug_trace_tx "$debug_trace_tx_count" \
+            --argjson tx_fetch "$tx_fetch_count" \
+            --argjson receipt_fetch "$receipt_fetch_count" \
+            --argjson block_header "$block_header_count" \
+            --argjson log_fetch "$log_fetch_count" \
+            --argjson receipt_enrich "$receipt_enrich_count" \
+            --argjson state_diff "$state_diff_count" \
+            --argjson call_trace "$call_trace_count" \
+            --argjson access_list "$access_list_count" \
+            --argjson block_receipts "$block_receipts_count" \
+            --argjson detailed_block "$detailed_block_count" \
+            --argjson chain_id "$chain_id_count" \
+            --argjson block_cache_hits "$block_cache_hit_count" \
+            --argjson rate_limited "$rate_limit_retry_count" \
+            --argjson empty_response "$request_retry_count" \
+            --argjson block_retries "$block_retry_count" \
+            --argjson endpoints "$endpoints" \
+            --arg detection_method "$(detection_method)" \
+            --argjson interrupted "$INTERRUPTED" \
+            --argjson selectors "$selectors" \
+            --argjson aggregates "$aggregates" \
+            '($elapsed_ms / 1000) as $elapsed
+            | {
+                chain_id: (if $chain_id_value == "" then null else ($chain_id_value | tonumber) end),
+                start_block: $start_block,
+                end_block: $end_block,
+                blocks_processed: $blocks,
+                transactions_found: $transactions,
+                failed_blocks: $failed_blocks,
+                missing_blocks: $missing_blocks,
+                elapsed_seconds: $elapsed,
+                blocks_per_sec: (if $elapsed > 0 then $blocks / $elapsed else null end),
+                transactions_per_sec: (if $elapsed > 0 then $transactions / $elapsed else null end),
+                detection_method: $detection_method,
+                interrupted: $interrupted,
+                selectors: $selectors,
+                aggregates: $aggregates,
+                rpc_calls: {
+                    total: $total_rpc_calls,
+                    block_fetch: $block_fetch,
+                    block_batch: $block_batch,
+                    trace_filter: $trace_filter,
+                    trace_block: $trace_block,
+                    debug_trace_block: $debug_trace_block,
+                    debug_trace_tx: $debug_trace_tx,
+                    tx_fetch: $tx_fetch,
+                    receipt_fetch: $receipt_fetch,
+                    block_header: $block_header,
+                    log_fetch: $log_fetch,
+                    receipt_enrich: $receipt_enrich,
+                    state_diff: $state_diff,
+                    call_trace: $call_trace,
+                    access_list: $access_list,
+                    block_receipts: $block_receipts,
+                    detailed_block: $detailed_block,
+                    chain_id: $chain_id
+                },
+                block_cache_hits: $block_cache_hits,
+                retries: {
+                    total: ($rate_limited + $empty_response + $block_retries),
+                    rate_limited: $rate_limited,
+                    empty_response: $empty_response,
+                    block: $block_retries
+                },
+                endpoints: $endpoints
+            }' > "$stats_json"
+    fi
+
+    # Report blocks that failed after all retries
+    if [[ -s "$FAILED_BLOCKS_FILE" ]]; then
+        local failed_blocks
+        local failed_count
+        failed_blocks=$(sort -n -u "$FAILED_BLOCKS_FILE" | paste -sd, - | sed 's/,/, /g')
+        failed_count=$(sort -n -u "$FAILED_BLOCKS_FILE" | wc -l | tr -d ' ')
+        if [[ "$FAIL_ON_ERROR" == "true" ]]; then
+            log error "Error: $failed_count blocks failed: $failed_blocks"
+            log error "Re-run to retry them, or pass --no-fail-on-error to accept incomplete results"
+            exit 1
+        fi
+        log warn "WARNING: $failed_count blocks failed: $failed_blocks"
+    fi
+
+    # Output results (for --count-only just the total, with the distribution in the log)
+    local follow_failed=false
+    local format_records=format_transactions
+    local record_kind="transactions"
+    if [[ "$headers_only" == "true" ]]; then
+        format_records=format_block_headers
+        record_kind="block headers"
+    fi
+    if [[ "$count_only" == "true" ]]; then
+        report_match_distribution "$all_transactions_file" "$start_block" "$scanned_end_block"
+        echo "$total_transactions_found"
+    elif [[ -n "$SQLITE_DB" ]]; then
+        log info "Wrote $total_transactions_found transactions to table transactions of $SQLITE_DB"
+    elif [[ "$STREAM_OUTPUT" == "true" ]]; then
+        # The statistics above cover the requested range; following keeps the stream open
+        if [[ "$follow" == "true" && "$INTERRUPTED" != "true" ]]; then
+            FOLLOW_CONFIRMATIONS=$confirmations
+            follow_chain || follow_failed=true
+        fi
+        stream_end
+    elif [[ $SPLIT_EVERY -gt 0 ]]; then
+        write_split_output "$all_transactions_file"
+        log info "Wrote $total_transactions_found transactions ($OUTPUT_FORMAT${COMPRESS:+, $COMPRESS}) to $(wc -l < "$SPLIT_CHUNKS_FILE" | tr -d ' ') files like $(split_chunk_path 1)"
+    elif [[ "$OUTPUT_FORMAT" == "parquet" ]]; then
+        write_parquet "$all_transactions_file" "$OUTPUT_FILE"
+        log info "Wrote $total_transactions_found transactions (parquet) to $OUTPUT_FILE"
+    elif [[ -n "$OUTPUT_FILE" ]]; then
+        "$format_records" "$OUTPUT_FORMAT" "$all_transactions_file" | compress_output > "$OUTPUT_FILE"
+        log info "Wrote $(wc -l < "$all_transactions_file" | tr -d ' ') $record_kind ($OUTPUT_FORMAT${COMPRESS:+, $COMPRESS}) to $OUTPUT_FILE"
+    else
+        echo "TRANSACTION_DATA:START"
+        echo -n "TRANSACTION_DATA:"
+        "$format_records" "$OUTPUT_FORMAT" "$all_transactions_file" | compress_stdout
+        echo -n "TRANSACTION_DATA:END"
+    fi
+
+    # The next incremental run outputs only what it fetches itself
+    if [[ "$SINCE_LAST_RUN" == "true" ]]; then
+        : > "$CHECKPOINT_FILE.records"
     fi
-    echo "===========================" >&2
 
-    # Output results
-    echo "TRANSACTION_DATA:START"
-    echo -n "TRANSACTION_DATA:"
-    format_transactions "$OUTPUT_FORMAT" "$all_transactions_file"
-    echo -n "TRANSACTION_DATA:END"
+    # A stream that --follow had to give up on is still a complete file up to where it stopped
+    if [[ -n "$manifest" ]]; then
+        local manifest_transactions=$total_transactions_found
+        if [[ "$STREAM_OUTPUT" == "true" ]]; then
+            manifest_transactions=$STREAMED_TX_COUNT
+        fi
+        write_manifest "$manifest" "${SQLITE_DB:-$OUTPUT_FILE}" "$chain_id" "$manifest_transactions"
+        log info "Wrote manifest to $manifest"
+    fi
+    if [[ "$follow_failed" == "true" ]]; then
+        exit 1
+    fi
 
-    # Output formatted block summaries if detailed blocks is enabled
+    # Block summaries are for people reading the log, so they go to stderr with the rest of it
     if [[ "$DETAILED_BLOCKS" = true ]]; then
-        echo "BLOCK_SUMMARY_FORMATTED:START"
+        log info ""
+        log info "=== BLOCK SUMMARY ==="
 
         # Count triggered transactions per block by parsing the transactions file
         declare -A triggered_per_block
 
         if [[ -f "$all_transactions_file" && -s "$all_transactions_file" ]]; then
-            while IFS='|' read -r hash from to value data block_num rest; do
-                # Extract block number from transaction line (format: hash|from|to|value|data|block_number|...)
+            while read -r block_num; do
                 if [[ -n "$block_num" ]]; then
                     triggered_per_block[$block_num]=$((${triggered_per_block[$block_num]:-0} + 1))
                 fi
-            done < "$all_transactions_file"
+            done < <(jq -r '.block_number' "$all_transactions_file")
         fi
 
         # Get total tx count for each block and format output
-        for ((block = start_block; block <= end_block; block++)); do
+        for ((block = start_block; block <= scanned_end_block; block++)); do
             local block_hex
             block_hex=$(printf "0x%x" "$block")
             echo "1" >> "$RPC_COUNTER_DIR/detailed_block.count"
             local total_tx_count
-            total_tx_count=$(curl -s -X POST "$rpc_url" \
-                -H "Content-Type: application/json" \
-                -d "{\"jsonrpc\":\"2.0\",\"method\":\"eth_getBlockByNumber\",\"params\":[\"$block_hex\", false],\"id\":1}" \
+            total_tx_count=$(rpc_post "{\"jsonrpc\":\"2.0\",\"method\":\"eth_getBlockByNumber\",\"params\":[\"$block_hex\", false],\"id\":1}" \
                 | jq -r '.result.transactions | length')
 
             if [[ -z "$total_tx_count" || "$total_tx_count" == "null" ]]; then
@@ -1154,15 +1989,217 @@ main() {
 
             # Format output line
             if [[ $triggered_count -gt 0 ]]; then
-                echo "=== BLOCK $block SUMMARY | Triggered: $triggered_count | Not Triggered: $not_triggered | Total: $total_tx_count ==="
+                log info "=== BLOCK $block SUMMARY | Triggered: $triggered_count | Not Triggered: $not_triggered | Total: $total_tx_count ==="
             else
-                echo "=== BLOCK $block | Total TXs: $total_tx_count ==="
+                log info "=== BLOCK $block | Total TXs: $total_tx_count ==="
+            fi
+        done
+    fi
+
+    # The partial results are complete as far as they go, but the range isn't
+    if [[ "$INTERRUPTED" == "true" ]]; then
+        exit 130
+    fi
+}
+
+benchmark_usage() {
+    cat << EOF
+Usage: $0 benchmark [OPTIONS] [-- FETCH_OPTIONS...]
+
+Fetches the same small block range once per setting of a --max-concurrent and --batch-size
+grid, reports the throughput of each and recommends the fastest setting that lost no blocks.
+Optimal settings vary widely by provider, so measure before a long fetch.
+
+OPTIONS:
+    --rpc-url URL                  RPC endpoint URL (repeatable or comma-separated; required unless
+                                   CREDIBLE_RPC_URL is set)
+    --target-contract ADDRESS      Contract to match while benchmarking (default: the zero address;
+                                   every block is fetched and scanned either way)
+    --start-block BLOCK            First block of the range (default: --blocks blocks ending 64
+                                   blocks behind the chain tip)
+    --blocks COUNT                 Blocks fetched per setting (default: 20)
+    --concurrency LIST             Comma-separated --max-concurrent values to try (default: 1,2,5,10,20)
+    --batch-sizes LIST             Comma-separated --batch-size values to try (default: 10)
+    -h, --help                     Show this help message
+
+    Options after -- are passed to every fetch (e.g. -- --batch-mode jsonrpc-batch --header "...").
+    The table goes to stdout; each fetch runs with --count-only and --no-cache.
+
+EXAMPLES:
+    $0 benchmark --rpc-url \$MAINNET_RPC_URL --blocks 50 --concurrency 5,10,20,40 --batch-sizes 10,50
+EOF
+}
+
+# Fetch one range at every --max-concurrent x --batch-size setting and recommend the fastest
+# Usage: benchmark_main [options] [-- fetch options]
+benchmark_main() {
+    local target_contract="0x0000000000000000000000000000000000000000"
+    local start_block=""
+    local blocks=20
+    local concurrency_list="1,2,5,10,20"
+    local batch_size_list="10"
+    local fetch_options=()
+
+    while [[ $# -gt 0 ]]; do
+        case "$1" in
+            --rpc-url)
+                rpc_pool_add "$2"
+                shift 2
+                ;;
+            --target-contract)
+                target_contract="$2"
+                shift 2
+                ;;
+            --start-block)
+                start_block="$2"
+                shift 2
+                ;;
+            --blocks)
+                blocks="$2"
+                shift 2
+                ;;
+            --concurrency)
+                concurrency_list="$2"
+                shift 2
+                ;;
+            --batch-sizes)
+                batch_size_list="$2"
+                shift 2
+                ;;
+            --)
+                shift
+                fetch_options=("$@")
+                break
+                ;;
+            -h|--help)
+                benchmark_usage
+                exit 0
+                ;;
+            *)
+                log error "Error: Unknown benchmark option $1"
+                benchmark_usage >&2
+                exit 1
+                ;;
+        esac
+    done
+
+    if [[ ${#RPC_URLS[@]} -eq 0 ]]; then
+        rpc_pool_add "${CREDIBLE_RPC_URL:-}"
+    fi
+    if [[ ${#RPC_URLS[@]} -eq 0 ]]; then
+        log error "Error: No RPC endpoint (pass --rpc-url or set CREDIBLE_RPC_URL)"
+        exit 1
+    fi
+    validate_rpc_urls || exit 1
+    validate_target_contracts "$target_contract" || exit 1
+    if ! [[ "$blocks" =~ ^[1-9][0-9]*$ ]]; then
+        log error "Error: --blocks must be a positive number of blocks"
+        exit 1
+    fi
+    if [[ -n "$start_block" ]] && ! [[ "$start_block" =~ ^[0-9]+$ ]]; then
+        log error "Error: --start-block must be a block number"
+        exit 1
+    fi
+    local settings=()
+    local concurrency
+    local batch_size
+    local concurrencies=()
+    local batch_sizes=()
+    IFS=',' read -r -a concurrencies <<< "$concurrency_list"
+    IFS=',' read -r -a batch_sizes <<< "$batch_size_list"
+    for batch_size in "${batch_sizes[@]}"; do
+        if ! [[ "$batch_size" =~ ^[1-9][0-9]*$ ]]; then
+            log error "Error: --batch-sizes must list positive numbers (got '$batch_size')"
+            exit 1
+        fi
+        for concurrency in "${concurrencies[@]}"; do
+            if ! [[ "$concurrency" =~ ^[1-9][0-9]*$ ]]; then
+                log error "Error: --concurrency must list positive numbers (got '$concurrency')"
+                exit 1
             fi
+            settings+=("$concurrency $batch_size")
         done
+    done
+
+    check_dependencies
+
+    # Stay behind the tip, where blocks are settled and the same range can be fetched every time
+    if [[ -z "$start_block" ]]; then
+        local latest_block
+        latest_block=$(resolve_block_tag "latest") || exit 1
+        start_block=$((latest_block - 64 - blocks + 1))
+        if [[ $start_block -lt 0 ]]; then
+            start_block=0
+        fi
+    fi
+    local end_block=$((start_block + blocks - 1))
+
+    local endpoints=()
+    local url
+    for url in "${RPC_URLS[@]}"; do
+        endpoints+=("$(endpoint_label "$url")")
+    done
+    log info "Benchmarking blocks $start_block to $end_block on ${endpoints[*]}: ${#settings[@]} settings"
+
+    local rpc_url_options=()
+    for url in "${RPC_URLS[@]}"; do
+        rpc_url_options+=(--rpc-url "$url")
+    done
+
+    local results="$TEMP_DIR/benchmark.tsv"
+    : > "$results"
+    local setting
+    for setting in "${settings[@]}"; do
+        read -r concurrency batch_size <<< "$setting"
+        local stats="$TEMP_DIR/benchmark_stats.json"
+        rm -f "$stats"
+        bash "${BASH_SOURCE[0]}" fetch "${rpc_url_options[@]}" --target-contract "$target_contract" \
+            --start-block "$start_block" --end-block "$end_block" \
+            --max-concurrent "$concurrency" --batch-size "$batch_size" \
+            --count-only --no-cache --quiet --stats-json "$stats" "${fetch_options[@]}" > /dev/null 2>&1 || true
+        if [[ ! -s "$stats" ]]; then
+            log warn "Warning: --max-concurrent $concurrency --batch-size $batch_size did not finish"
+            printf '%s\t%s\t-\t-\t-\t-\t-\n' "$concurrency" "$batch_size" >> "$results"
+            continue
+        fi
+        jq -r --arg concurrency "$concurrency" --arg batch_size "$batch_size" '
+            [$concurrency, $batch_size, (.elapsed_seconds * 100 | round / 100),
+             (.blocks_per_sec // 0 | . * 100 | round / 100),
+             (if .elapsed_seconds > 0 then .rpc_calls.total / .elapsed_seconds * 100 | round / 100 else 0 end),
+             (.failed_blocks | length), .retries.rate_limited] | @tsv' "$stats" >> "$results"
+        log info "  --max-concurrent $concurrency --batch-size $batch_size: $(cut -f4 <<< "$(tail -1 "$results")") blocks/s"
+    done
 
-        echo "BLOCK_SUMMARY_FORMATTED:END"
+    echo "=== BENCHMARK ==="
+    echo "Blocks $start_block to $end_block ($blocks blocks) on ${endpoints[*]}"
+    awk -F'\t' 'BEGIN { printf "%12s %10s %10s %10s %8s %8s %12s\n", "concurrency", "batch_size", "seconds", "blocks/s", "rpc/s", "failed", "rate_limited" }
+        { printf "%12s %10s %10s %10s %8s %8s %12s\n", $1, $2, $3, $4, $5, $6, $7 }' "$results"
+
+    # Settings that lost blocks or didn't finish can't be recommended, however fast they were
+    local best
+    best=$(awk -F'\t' '$6 == "0" && $4 > best { best = $4; line = $1 "\t" $2 "\t" $4 } END { print line }' "$results")
+    if [[ -z "$best" ]]; then
+        echo "No setting fetched every block; check the endpoint or try lower concurrency"
+        exit 1
     fi
+    local best_rate
+    IFS=$'\t' read -r concurrency batch_size best_rate <<< "$best"
+    echo "Recommended: --max-concurrent $concurrency --batch-size $batch_size ($best_rate blocks/s)"
 }
 
-# Run main function
-main "$@"
+# Run the command (fetch when the first argument isn't one)
+trap fetcher_cleanup EXIT
+fetcher_init
+case "${1:-}" in
+    fetch)
+        shift
+        fetch_main "$@"
+        ;;
+    benchmark)
+        shift
+        benchmark_main "$@"
+        ;;
+    *)
+        fetch_main "$@"
+        ;;
+esac
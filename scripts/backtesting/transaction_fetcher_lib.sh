@@ -0,0 +1,5663 @@
+#!/bin/bash
+
+# Transaction Fetcher Library
+# The fetching logic behind transaction_fetcher.sh, for tools that want to embed it.
+#
+# Usage:
+#   set -eo pipefail
+#   source scripts/backtesting/transaction_fetcher_lib.sh
+#   RPC_URLS=("$RPC_URL")
+#   TARGET_CONTRACTS="0xabc...,0xdef..."
+#   START_BLOCK=23717632
+#   END_BLOCK=23717700
+#   fetcher_init
+#   fetch_transactions "$records_file"
+#   fetcher_cleanup
+#
+# All settings are the globals below, so new options never change the function
+# signatures. fetch_transactions writes one JSON record per line (the ndjson fields),
+# sorted by block number and transaction index; format_transactions renders them as
+# simple, json, ndjson or csv.
+
+# Recorded in --manifest (the release this fetcher shipped with)
+FETCHER_VERSION="0.4.0"
+
+# Fetch range and targets (required by fetch_transactions)
+TARGET_CONTRACTS=""  # Lowercase, comma-separated addresses (see merge_target_contracts)
+START_BLOCK=""
+END_BLOCK=""
+BLOCK_LIST_FILE=""  # Only these blocks of the range, one per line in order (--blocks-file)
+
+# Default values
+OUTPUT_FORMAT="simple"
+NUMERIC_FORMAT="hex"        # Quantities in the output: hex as the node sends them, or decimal (see NUMERIC_FIELDS)
+OUTPUT_FILE=""
+COMPRESS=""                 # Payload compression: empty or gzip (applies to OUTPUT_FILE)
+COMPRESS_STDOUT=false       # Also compress a stdout payload (base64 between the markers)
+SQLITE_DB=""                # Insert records into this SQLite database as batches complete
+STREAM_OUTPUT=false
+STREAMED_TX_COUNT=0
+BATCH_SIZE=10
+BATCH_TIMEOUT=0             # Seconds a block-scan batch may take before its unfinished blocks fail (0 = no limit)
+MAX_CONCURRENT=5
+ADAPTIVE_CONCURRENCY=false  # Tune concurrency per batch between MIN_CONCURRENT and MAX_CONCURRENT
+MIN_CONCURRENT=1
+CONCURRENCY=""              # Concurrency used for the next batch (set by fetch_transactions)
+ADAPTIVE_PEAK=0
+ADAPTIVE_BASELINE_MS=""     # Best average request latency of a batch so far
+DETAILED_BLOCKS=false
+LOG_LEVEL="info"            # Most verbose messages written to stderr: error, warn, info or debug (--log-level)
+USE_TRACE_FILTER=false
+TRACE_FILTER_BATCH_SIZE=100
+TRACE_METHOD=""
+MAX_RETRIES=5
+RETRY_BASE_DELAY_MS=2000
+FAIL_ON_ERROR=true
+BATCH_MODE="parallel"
+MATCH_BY="to"
+SELECTORS=""
+CONTRACT_SELECTORS=""       # Selectors scoped to one target, as comma-separated "address:selector" pairs (--selector ADDRESS:SELECTOR)
+EXCLUDE_FROM=""             # Lowercase, comma-separated senders whose txs are dropped (--exclude-from)
+MIN_VALUE=""                # Value bounds in wei as 64-digit hex words (see wei_to_word); empty = unbounded
+MAX_VALUE=""
+MIN_GAS_PRICE=""            # Gas price bounds in wei, same encoding as MIN_VALUE/MAX_VALUE
+MAX_GAS_PRICE=""
+INCLUDE_CREATIONS=false
+INCLUDE_PENDING=false       # Keep transactions without a transactionIndex, flagged pending (--include-pending)
+WITH_RECEIPTS=false
+WITH_STATE_DIFF=false       # Attach prestateTracer diffs (--with-state-diff); needs a debug-enabled archive node
+WITH_CALL_TRACE=false       # Attach each tx's callTracer frame (--with-call-trace)
+OMIT_DATA=false             # Leave calldata out of the payload, keeping data_len and has_data (--omit-data)
+CHECKSUM_ADDRESSES=false    # Write from, to and matched_contract in EIP-55 casing (--checksum-addresses)
+HASHES_ONLY=false           # Write only the transaction hashes: lines (ndjson) or a json array (--hashes-only)
+SPLIT_EVERY=0               # Records per numbered OUTPUT_FILE chunk (--split-every; 0 writes one file)
+WITH_ACCESS_LIST=false      # Carry or derive (eth_createAccessList) each tx's access list (--with-access-list)
+SKIP_UNAVAILABLE_STATE=false # Leave out txs whose block state the node has pruned (--skip-unavailable-state)
+ABI_SELECTORS_FILE=""       # Functions of the --abi contract by selector, written by abi_load
+BLOCK_TRACE_METHOD=""       # trace_block or debug_trace_block for --match-by traces (see detect_block_trace_method)
+VERIFY_CHAIN=false          # Check that fetched blocks link up by parentHash, refetching on a reorg (--verify-chain)
+FOLLOW_CONFIRMATIONS=0      # Blocks follow_chain stays behind each announced head (--follow with --confirmations)
+SAMPLE_RATE=1               # Share of matches kept, chosen by transaction hash (1 = all)
+MAX_RESULTS=0               # Stop fetching new blocks once this many matches are kept (0 = no limit)
+RESULTS_COLLECTED=0         # Matches kept by finished batches (read by process_batch)
+INTERRUPTED=false           # Set by SIGINT/SIGTERM during the range scan (see fetch_transactions)
+SCANNED_TO_BLOCK=""         # Last block of the last batch the range scan completed
+STRICT_CHECKSUM=false
+CHECKPOINT_FILE=""
+SINCE_LAST_RUN=false        # Continue from CHECKPOINT_FILE up to the confirmed tip (--since-last-run)
+RESUME_OUTPUT=false         # Continue a partly written --stream OUTPUT_FILE (--resume-output)
+RESUME_FROM_BLOCK=""        # Block the resumed OUTPUT_FILE continues at (see resume_output_prepare)
+SHOW_PROGRESS=false         # Draw a progress bar on stderr (only when it is a terminal)
+JSON_LOGS=false             # Write each log line as a JSON object (--json-logs)
+BLOCK_CACHE_DIR=""          # Per-chain directory for cached blocks (empty = no block cache)
+BLOCK_CACHE_MAX_BLOCK=-1    # Only blocks at or below this height are final enough to cache
+RPC_FAILURE_THRESHOLD=3
+RPC_COOLDOWN_SECS=30
+MAX_RPS=0
+RPC_PROXY=""                # Proxy for http(s):// endpoints (--proxy); without it curl reads HTTPS_PROXY/http_proxy
+RPC_CA_CERT=""              # PEM bundle that verifies https:// endpoints and proxies (--ca-cert)
+TEMP_DIR=""
+START_TIME=""
+
+# RPC call counter files (for aggregating across subprocesses)
+RPC_COUNTER_DIR=""
+
+# RPC endpoint pool (URLs plus per-endpoint health files shared across subprocesses)
+RPC_URLS=()
+RPC_POOL_DIR=""
+RPC_HEADERS=()              # Extra "Name: Value" headers for every request (--header; see rpc_header_add)
+RPC_HEADERS_FILE=""
+RPC_REQUEST_ID_FILE=""      # Last JSON-RPC request id handed out (see reserve_request_ids)
+
+# Block headers fetched for hash-based (trace) discovery, shared across subprocesses
+BLOCK_HEADER_CACHE_DIR=""
+
+# Blocks that could not be fetched (appended to by subprocesses)
+FAILED_BLOCKS_FILE=""
+
+# One entry per block already reported as needing an archive node (see report_unavailable_state)
+UNAVAILABLE_STATE_DIR=""
+
+# Request latencies and congestion events of the current batch (--adaptive-concurrency)
+ADAPTIVE_SAMPLES_FILE=""
+
+# "number hash parentHash" of every scanned block (--verify-chain; appended to by subprocesses)
+CHAIN_LINKS_FILE=""
+
+# Target logs fetched ahead of the scan, one file per block (see prefetch_logs)
+LOGS_DIR=""
+
+# Hashes of the transactions collected so far, one per line (see drop_duplicate_records)
+SEEN_HASHES_FILE=""
+
+# Call frames of matched transactions kept from the block traces, as <tx_hash>.json
+# (--with-call-trace with --match-by traces; see match_call_frames)
+CALL_TRACES_DIR=""
+
+# Receipts fetched for the status check of kept transactions, as <tx_hash>.json, so
+# --with-receipts doesn't fetch them again (see fetch_receipts_by_hash)
+SCAN_RECEIPTS_DIR=""
+
+# "path transactions" of every chunk written by write_split_output
+SPLIT_CHUNKS_FILE=""
+
+# One directory per ws://, wss:// or ipc:// endpoint holding its persistent connection (see ws_post)
+WS_DIR=""
+
+# Background fetcher of the block scan in progress (see block_pool_start)
+BLOCK_POOL_DIR=""
+BLOCK_POOL_PID=""
+# Blocks the pool may have started that the scan hasn't collected yet, in flight or finished and
+# waiting for the blocks before them (--max-pending-blocks)
+MAX_PENDING_BLOCKS=100
+
+# Batches handed to the output writer that it hasn't written yet, at most OUTPUT_BUFFER
+# (--output-buffer); see output_writer_start
+OUTPUT_BUFFER=4
+OUTPUT_WRITER_DIR=""
+OUTPUT_WRITER_PID=""
+OUTPUT_WRITER_SUBMITTED=0
+
+# Succeeds when LOG_LEVEL lets messages of <level> through (error < warn < info < debug)
+# Usage: log_enabled <error|warn|info|debug>
+log_enabled() {
+    case "$LOG_LEVEL" in
+        debug) return 0 ;;
+        info) [[ "$1" != "debug" ]] ;;
+        warn) [[ "$1" == "error" || "$1" == "warn" ]] ;;
+        *) [[ "$1" == "error" ]] ;;
+    esac
+}
+
+# Write a message to stderr unless LOG_LEVEL filters out its level; stdout is left to the payload
+# With --json-logs the message is one JSON object per line: time, level, message and the given
+# fields (strings; blank spacer lines are dropped)
+# Usage: log <error|warn|info|debug> <message> [key=value...]
+log() {
+    local level="$1"
+    local message="$2"
+    if ! log_enabled "$level"; then
+        return 0
+    fi
+    if [[ "$JSON_LOGS" != "true" ]]; then
+        echo "$message" >&2
+    elif [[ -n "$message" ]]; then
+        shift 2
+        jq -cn --arg time "$(date -u +%Y-%m-%dT%H:%M:%SZ)" --arg level "$level" --arg message "$message" '
+            {time: $time, level: $level, message: $message,
+             fields: ($ARGS.positional | map(index("=") as $at | {key: .[:$at], value: .[$at + 1:]}) | from_entries)}' \
+            --args "$@" >&2
+    fi
+}
+
+# Log every line read from stdin at one level, for multi-line reports such as tables
+# Usage: <command> | log_lines <error|warn|info|debug>
+log_lines() {
+    local line
+    while IFS= read -r line; do
+        log "$1" "$line"
+    done
+}
+
+# Close connections and remove the temporary directory; the CLI runs it on exit, embedders
+# call it (or trap it) themselves, as the library leaves the caller's traps alone
+# Usage: fetcher_cleanup
+fetcher_cleanup() {
+    ws_disconnect_all
+    if [[ -n "$TEMP_DIR" && -d "$TEMP_DIR" ]]; then
+        rm -rf "$TEMP_DIR"
+    fi
+}
+
+# Put back the INT and TERM handlers saved with `trap -p INT TERM` before the library installed its own
+# Usage: restore_signal_traps <saved_traps>
+restore_signal_traps() {
+    trap - INT TERM
+    eval "$1"
+}
+
+# Create the temporary directory shared with subprocesses; removed again by fetcher_cleanup
+# Usage: fetcher_init   (call once before any other function)
+fetcher_init() {
+    TEMP_DIR=$(mktemp -d)
+    RPC_COUNTER_DIR="$TEMP_DIR/rpc_counters"
+    mkdir -p "$RPC_COUNTER_DIR"
+    FAILED_BLOCKS_FILE="$TEMP_DIR/failed_blocks.txt"
+    touch "$FAILED_BLOCKS_FILE"
+    UNAVAILABLE_STATE_DIR="$TEMP_DIR/unavailable_state"
+    mkdir -p "$UNAVAILABLE_STATE_DIR"
+    RPC_POOL_DIR="$TEMP_DIR/rpc_pool"
+    mkdir -p "$RPC_POOL_DIR"
+    RPC_HEADERS_FILE="$TEMP_DIR/rpc_headers.txt"
+    : > "$RPC_HEADERS_FILE"
+    RPC_REQUEST_ID_FILE="$TEMP_DIR/rpc_request_id"
+    echo "0" > "$RPC_REQUEST_ID_FILE"
+    BLOCK_HEADER_CACHE_DIR="$TEMP_DIR/block_headers"
+    mkdir -p "$BLOCK_HEADER_CACHE_DIR"
+    ADAPTIVE_SAMPLES_FILE="$TEMP_DIR/adaptive_samples.txt"
+    touch "$ADAPTIVE_SAMPLES_FILE"
+    CHAIN_LINKS_FILE="$TEMP_DIR/chain_links.txt"
+    touch "$CHAIN_LINKS_FILE"
+    SEEN_HASHES_FILE="$TEMP_DIR/seen_hashes.txt"
+    touch "$SEEN_HASHES_FILE"
+    LOGS_DIR="$TEMP_DIR/logs"
+    mkdir -p "$LOGS_DIR"
+    CALL_TRACES_DIR="$TEMP_DIR/call_traces"
+    mkdir -p "$CALL_TRACES_DIR"
+    SCAN_RECEIPTS_DIR="$TEMP_DIR/scan_receipts"
+    mkdir -p "$SCAN_RECEIPTS_DIR"
+    SPLIT_CHUNKS_FILE="$TEMP_DIR/split_chunks.txt"
+    touch "$SPLIT_CHUNKS_FILE"
+    WS_DIR="$TEMP_DIR/ws"
+    mkdir -p "$WS_DIR"
+}
+
+# Sleep for the backoff delay of a given retry attempt
+# Usage: backoff_sleep <attempt>
+backoff_sleep() {
+    local attempt="$1"
+    local wait_time
+    local jitter=0
+    local total_wait
+    local max_wait
+
+    # Cap at maximum backoff of 64 seconds
+    max_wait=64000
+
+    # Exponential backoff: base * 2^(n-1) milliseconds + random jitter (0-base ms). Doubling
+    # stops at the cap, so a high attempt count can't overflow into a short or negative wait
+    wait_time=$RETRY_BASE_DELAY_MS
+    local doublings=1
+    while [[ $doublings -lt $attempt && $wait_time -gt 0 && $wait_time -lt $max_wait ]]; do
+        wait_time=$((wait_time * 2))
+        doublings=$((doublings + 1))
+    done
+    if [[ $RETRY_BASE_DELAY_MS -gt 0 ]]; then
+        jitter=$((RANDOM % RETRY_BASE_DELAY_MS))
+    fi
+    total_wait=$((wait_time + jitter))
+
+    if [[ $total_wait -gt $max_wait ]]; then
+        total_wait=$max_wait
+    fi
+
+    sleep "$(awk "BEGIN {print $total_wait/1000}")"
+}
+
+# Exponential backoff retry function: the command runs once, plus up to <max_retries> retries
+# Usage: retry_with_backoff <max_retries> <command...>
+retry_with_backoff() {
+    local max_retries="$1"
+    shift
+    local retries=0
+    local response=""
+
+    while true; do
+        # Execute the request command
+        response=$("$@" 2>/dev/null || echo "")
+
+        # Retry an empty response (rate limits are already retried by rpc_post)
+        if [[ -z "$response" && $retries -lt $max_retries ]]; then
+            retries=$((retries + 1))
+            echo "1" >> "$RPC_COUNTER_DIR/request_retry.count"
+            backoff_sleep "$retries"
+            continue
+        fi
+
+        # Success, non-retryable error or retries exhausted
+        echo "$response"
+        return 0
+    done
+}
+
+# Add endpoints to the pool from a comma-separated list (empty entries are skipped)
+# Usage: rpc_pool_add <url[,url...]>
+rpc_pool_add() {
+    local url
+    local urls=()
+    IFS=',' read -r -a urls <<< "$1"
+    for url in "${urls[@]}"; do
+        if [[ -n "$url" ]]; then
+            RPC_URLS+=("$url")
+        fi
+    done
+}
+
+# Send a header ("Name: Value") with every RPC request, for endpoints that authenticate with
+# one (Authorization, an API-key header); curl reads them from RPC_HEADERS_FILE, so values stay
+# out of process listings, and only the names are ever logged (see rpc_header_names)
+# Usage: rpc_header_add <header>
+rpc_header_add() {
+    local header="$1"
+    if [[ "$header" == *$'\n'* || "$header" == *$'\r'* ]] || ! [[ "$header" =~ ^[A-Za-z0-9_.-]+:\ ?[^\ ] ]]; then
+        log error "Error: --header must look like 'Name: Value'"
+        return 1
+    fi
+    RPC_HEADERS+=("$header")
+    echo "$header" >> "$RPC_HEADERS_FILE"
+}
+
+# Print the names of the RPC headers as a JSON array, values left out
+# Usage: rpc_header_names
+rpc_header_names() {
+    local header
+    for header in "${RPC_HEADERS[@]}"; do
+        echo "${header%%:*}"
+    done | jq -R . | jq -sc '.'
+}
+
+# Read a TOML config file (--config) as fetcher options, printed one per line as
+# "flag --name", "off --name" or "value --name<TAB>value". Keys are option names with dashes or
+# underscores (rpc_url is --rpc-url); a string or number is the option's value, true turns a flag
+# on, false leaves it off (the caller maps that to a --no- form where one exists) and an array
+# repeats the option. [section] headers only group keys. Values can't hold tabs or line breaks
+# Usage: config_file_args <config_file>
+config_file_args() {
+    local config_file="$1"
+
+    awk -v file="$config_file" '
+        function fail(message) {
+            printf "Error: %s:%d: %s\n", file, NR, message > "/dev/stderr"
+            failed = 1
+            exit 1
+        }
+        function skip_space() {
+            while (pos <= length(line) && substr(line, pos, 1) ~ /[ \t]/) pos++
+        }
+        # Parse the value at pos into value and kind (string, bare), leaving pos after it
+        function parse_scalar(    c, quote, escaped) {
+            c = substr(line, pos, 1)
+            value = ""
+            if (c == "\"" || c == "\047") {
+                quote = c
+                pos++
+                while (1) {
+                    if (pos > length(line)) fail("unterminated string")
+                    c = substr(line, pos, 1)
+                    pos++
+                    if (c == quote) break
+                    if (c == "\\" && quote == "\"") {
+                        c = substr(line, pos, 1)
+                        pos++
+                        if (c == "\"" || c == "\\") value = value c
+                        else fail("unsupported escape \\" c)
+                        continue
+                    }
+                    if (c == "\t") fail("values cannot contain tabs")
+                    value = value c
+                }
+                kind = "string"
+                return
+            }
+            while (pos <= length(line) && substr(line, pos, 1) !~ /[ \t,\]#]/) {
+                value = value substr(line, pos, 1)
+                pos++
+            }
+            if (value == "true" || value == "false") kind = value
+            else if (value ~ /^[+-]?[0-9][0-9_]*(\.[0-9]+)?$/) { gsub(/_/, "", value); kind = "number" }
+            else fail("expected a string, number, boolean or array")
+        }
+        function emit(name) {
+            if (kind == "true") print "flag --" name
+            else if (kind == "false") print "off --" name
+            else print "value --" name "\t" value
+        }
+        {
+            line = $0
+            pos = 1
+            skip_space()
+            if (pos > length(line) || substr(line, pos, 1) == "#") next
+            if (substr(line, pos) ~ /^\[[A-Za-z0-9_.-]+\][ \t]*(#.*)?$/) next
+            if (!match(substr(line, pos), /^[A-Za-z0-9_-]+[ \t]*=/)) fail("expected key = value")
+            name = substr(line, pos, RLENGTH)
+            sub(/[ \t]*=$/, "", name)
+            gsub(/_/, "-", name)
+            pos += RLENGTH
+            skip_space()
+            if (substr(line, pos, 1) == "[") {
+                pos++
+                while (1) {
+                    skip_space()
+                    if (pos > length(line)) fail("unterminated array (arrays must fit on one line)")
+                    if (substr(line, pos, 1) == "]") { pos++; break }
+                    parse_scalar()
+                    emit(name)
+                    skip_space()
+                    c = substr(line, pos, 1)
+                    if (c == ",") pos++
+                    else if (c != "]") fail("expected , or ] in array (arrays must fit on one line)")
+                }
+            } else {
+                parse_scalar()
+                emit(name)
+            }
+            skip_space()
+            if (pos <= length(line) && substr(line, pos, 1) != "#") fail("unexpected text after the value")
+        }
+        END { if (failed) exit 1 }
+    ' "$config_file"
+}
+
+# Print options as the TOML config file that sets them (--print-config): repeated options become
+# arrays, bare flags true (or false for a --no- form), and numbers too long for a double stay
+# strings. Header values and endpoint credentials are left out, as everywhere they are shown
+# Usage: print_config_toml <option> [value] ...
+print_config_toml() {
+    local name
+    local value
+    while [[ $# -gt 0 ]]; do
+        name="${1#--}"
+        if [[ $# -gt 1 && "$2" != --* ]]; then
+            value="$2"
+            shift 2
+            case "$name" in
+                header) value="${value%%:*}: <redacted>" ;;
+                rpc-url) value=$(endpoint_label "$value") ;;
+            esac
+            printf '%s\t%s\n' "$name" "$value"
+        else
+            shift
+            printf '%s\n' "$name"
+        fi
+    done | jq -Rrn '
+        [inputs | split("\t") | {name: .[0], value: .[1]}]
+        | reduce .[] as $entry ({order: [], values: {}};
+            (if $entry.value == null then
+                if ($entry.name | startswith("no-")) then {name: $entry.name[3:], value: false}
+                else {name: $entry.name, value: true} end
+             elif ($entry.value | test("^[0-9]{1,15}$")) then {name: $entry.name, value: ($entry.value | tonumber)}
+             else $entry end) as $option
+            | ($option.name | gsub("-"; "_")) as $key
+            | (if .values[$key] == null then .order += [$key] else . end)
+            | .values[$key] += [$option.value])
+        | .values as $values
+        | .order[]
+        | "\(.) = \($values[.] | if length == 1 then .[0] | tojson else "[" + (map(tojson) | join(", ")) + "]" end)"'
+}
+
+# Strip credentials, path and query from an RPC URL so it is safe to log
+endpoint_label() {
+    local url="$1"
+    echo "$url" | sed -E 's#^([a-zA-Z0-9+.-]+://)([^@/]*@)?([^/?]+).*#\1\3#'
+}
+
+# Check that every endpoint is a URL the fetcher can talk to: http(s)://host, ws(s)://host or
+# ipc:///path. A bare host or a mistyped scheme would otherwise only fail at the first request,
+# with whatever curl makes of it; errors name the endpoint by its label, keeping keys out of logs
+# Usage: validate_rpc_urls
+validate_rpc_urls() {
+    local supported="use http://, https://, ws://, wss:// or ipc:///path/to/node.ipc"
+    local authority='^[^:]+://([^/?#@]*@)?(\[[0-9a-fA-F:.]+\]|[^][/?#@:]+)(:[0-9]*)?([/?#].*)?$'
+    local url
+    for url in "${RPC_URLS[@]}"; do
+        if [[ "$url" =~ [[:space:]] ]]; then
+            log error "Error: RPC endpoint '$(endpoint_label "$url")' contains whitespace"
+            return 1
+        fi
+        if [[ "$url" != *://* ]]; then
+            local host="${url%%/*}"
+            host="${host##*@}"
+            log error "Error: RPC endpoint '$host' has no scheme; $supported (e.g. https://$host)"
+            return 1
+        fi
+        local scheme
+        scheme=$(echo "${url%%://*}" | tr '[:upper:]' '[:lower:]')
+        case "$scheme" in
+            http|https|ws|wss)
+                if ! [[ "$url" =~ $authority ]]; then
+                    log error "Error: Cannot find a host in the $scheme:// RPC endpoint"
+                    return 1
+                fi
+                ;;
+            ipc)
+                if [[ -z "${url#*://}" ]]; then
+                    log error "Error: RPC endpoint '$url' has no socket path; use ipc:///path/to/node.ipc"
+                    return 1
+                fi
+                ;;
+            *)
+                log error "Error: RPC endpoint scheme '$scheme://' is not supported; $supported"
+                return 1
+                ;;
+        esac
+    done
+}
+
+# Pick the next endpoint index round-robin, skipping endpoints in cooldown
+# Falls back to the endpoint whose cooldown ends first when all are demoted
+rpc_pool_select() {
+    local count=${#RPC_URLS[@]}
+    if [[ $count -le 1 ]]; then
+        echo "0"
+        return
+    fi
+
+    local next
+    next=$(
+        {
+            flock 9
+            current=$(cat "$RPC_POOL_DIR/next" 2>/dev/null || echo "0")
+            echo $(((current + 1) % count)) > "$RPC_POOL_DIR/next"
+            echo "$current"
+        } 9> "$RPC_POOL_DIR/lock"
+    )
+
+    local now
+    now=$(date +%s)
+    local fallback=$next
+    local fallback_until=""
+    local offset
+    for ((offset=0; offset<count; offset++)); do
+        local index=$(((next + offset) % count))
+        local demoted_until
+        demoted_until=$(cat "$RPC_POOL_DIR/$index.demoted_until" 2>/dev/null || echo "0")
+        if [[ $demoted_until -le $now ]]; then
+            echo "$index"
+            return
+        fi
+        if [[ -z "$fallback_until" || $demoted_until -lt $fallback_until ]]; then
+            fallback=$index
+            fallback_until=$demoted_until
+        fi
+    done
+    echo "$fallback"
+}
+
+# Update an endpoint's health after a request
+# Usage: rpc_pool_record <index> <ok|fail>
+rpc_pool_record() {
+    local index="$1"
+    local outcome="$2"
+    (
+        flock 9
+        if [[ "$outcome" == "ok" ]]; then
+            rm -f "$RPC_POOL_DIR/$index.failures" "$RPC_POOL_DIR/$index.demoted_until"
+        else
+            echo "1" >> "$RPC_POOL_DIR/$index.errors"
+            local failures
+            failures=$(($(cat "$RPC_POOL_DIR/$index.failures" 2>/dev/null || echo "0") + 1))
+            echo "$failures" > "$RPC_POOL_DIR/$index.failures"
+            if [[ ${#RPC_URLS[@]} -gt 1 && $failures -ge $RPC_FAILURE_THRESHOLD ]]; then
+                echo $(($(date +%s) + RPC_COOLDOWN_SECS)) > "$RPC_POOL_DIR/$index.demoted_until"
+                echo "0" > "$RPC_POOL_DIR/$index.failures"
+                log warn "[RPC] Demoting endpoint $(endpoint_label "${RPC_URLS[$index]}") for ${RPC_COOLDOWN_SECS}s after $failures consecutive failures"
+            fi
+        fi
+    ) 9> "$RPC_POOL_DIR/lock"
+}
+
+# Current wall clock time in microseconds
+now_us() {
+    if [[ -n "${EPOCHREALTIME:-}" ]]; then
+        echo "${EPOCHREALTIME/[.,]/}"
+    else
+        echo $(($(date +%s) * 1000000))
+    fi
+}
+
+# Current wall clock time in milliseconds
+now_ms() {
+    echo $(($(now_us) / 1000))
+}
+
+# Sleep between polls for something another process finishes: 10ms at first, doubling up to
+# 160ms, so short waits stay responsive and long ones don't spin
+# Usage: poll_sleep <round>   (0 for the first poll of a wait)
+poll_sleep() {
+    local round="$1"
+    if [[ $round -gt 4 ]]; then
+        round=4
+    fi
+    sleep "0.$(printf '%03d' $((10 << round)))"
+}
+
+# Block until the global request rate allows another request (--max-rps)
+# Request slots are handed out from a shared file so the limit holds across all workers; slots
+# are spaced in microseconds, so rates above 1000/s still get a non-zero interval
+rate_limit_acquire() {
+    if [[ "$MAX_RPS" == "0" ]]; then
+        return
+    fi
+
+    local wait_us
+    wait_us=$(
+        {
+            flock 9
+            interval_us=$(awk "BEGIN {i = int(1000000 / $MAX_RPS); printf \"%d\", (i < 1 ? 1 : i)}")
+            now=$(now_us)
+            next_slot=$(cat "$RPC_POOL_DIR/next_slot" 2>/dev/null || echo "0")
+            if [[ $next_slot -lt $now ]]; then
+                next_slot=$now
+            fi
+            echo $((next_slot + interval_us)) > "$RPC_POOL_DIR/next_slot"
+            echo $((next_slot - now))
+        } 9> "$RPC_POOL_DIR/rate.lock"
+    )
+
+    if [[ $wait_us -gt 0 ]]; then
+        sleep "$(awk "BEGIN {printf \"%.6f\", $wait_us / 1000000}")"
+    fi
+}
+
+# Sleep before retrying a rate-limited request
+# Honors a Retry-After header (delta-seconds or HTTP-date), otherwise uses exponential backoff
+# Usage: rate_limit_sleep <retry_after> <attempt>
+rate_limit_sleep() {
+    local retry_after="$1"
+    local attempt="$2"
+    local seconds=""
+
+    if [[ "$retry_after" =~ ^[0-9]+$ ]]; then
+        seconds=$retry_after
+    elif [[ -n "$retry_after" ]]; then
+        local retry_at
+        if retry_at=$(date -d "$retry_after" +%s 2>/dev/null); then
+            seconds=$((retry_at - $(date +%s)))
+            if [[ $seconds -lt 0 ]]; then
+                seconds=0
+            fi
+        fi
+    fi
+
+    if [[ -z "$seconds" ]]; then
+        log warn "Rate limited, retrying with backoff (retry $attempt/$MAX_RETRIES)"
+        backoff_sleep "$attempt"
+        return
+    fi
+
+    # Cap at maximum backoff of 64 seconds
+    if [[ $seconds -gt 64 ]]; then
+        seconds=64
+    fi
+    log warn "Rate limited, retrying after ${seconds}s per Retry-After (retry $attempt/$MAX_RETRIES)"
+    sleep "$seconds"
+}
+
+# Note the outcome of one request for --adaptive-concurrency
+# Usage: adaptive_record <latency_ms|congestion>
+adaptive_record() {
+    if [[ "$ADAPTIVE_CONCURRENCY" == "true" ]]; then
+        echo "$1" >> "$ADAPTIVE_SAMPLES_FILE"
+    fi
+}
+
+# Adjust CONCURRENCY after a batch (AIMD): halve it when any request was rate-limited or
+# timed out, add one when the batch's average latency stayed within twice the best batch
+# average (or 100ms of it, so sub-millisecond noise does not stall a fast node), otherwise hold
+adaptive_concurrency_update() {
+    if [[ "$ADAPTIVE_CONCURRENCY" != "true" || ! -s "$ADAPTIVE_SAMPLES_FILE" ]]; then
+        return
+    fi
+
+    local congestion
+    local latency
+    read -r congestion latency < <(awk '
+        $1 == "congestion" { congestion++; next }
+        { sum += $1; count++ }
+        END { printf "%d %d\n", congestion, (count ? sum / count : 0) }' "$ADAPTIVE_SAMPLES_FILE")
+    : > "$ADAPTIVE_SAMPLES_FILE"
+
+    local previous=$CONCURRENCY
+    if [[ $congestion -gt 0 ]]; then
+        CONCURRENCY=$((CONCURRENCY / 2))
+        if [[ $CONCURRENCY -lt $MIN_CONCURRENT ]]; then
+            CONCURRENCY=$MIN_CONCURRENT
+        fi
+        log info "[ADAPTIVE] $congestion rate-limited or timed-out requests, concurrency $previous -> $CONCURRENCY"
+        return
+    fi
+
+    if [[ -z "$ADAPTIVE_BASELINE_MS" || $latency -lt $ADAPTIVE_BASELINE_MS ]]; then
+        ADAPTIVE_BASELINE_MS=$latency
+    fi
+    local healthy_ms=$((ADAPTIVE_BASELINE_MS * 2))
+    if [[ $healthy_ms -lt $((ADAPTIVE_BASELINE_MS + 100)) ]]; then
+        healthy_ms=$((ADAPTIVE_BASELINE_MS + 100))
+    fi
+
+    if [[ $latency -le $healthy_ms && $CONCURRENCY -lt $MAX_CONCURRENT ]]; then
+        CONCURRENCY=$((CONCURRENCY + 1))
+        if [[ $CONCURRENCY -gt $ADAPTIVE_PEAK ]]; then
+            ADAPTIVE_PEAK=$CONCURRENCY
+        fi
+    fi
+}
+
+# Whether an endpoint URL is a WebSocket (ws:// or wss://) endpoint
+# Usage: is_ws_url <url>
+is_ws_url() {
+    [[ "$1" =~ ^[wW][sS][sS]?:// ]]
+}
+
+# Whether an endpoint URL is a node's IPC socket (ipc:///path/to/geth.ipc)
+# Usage: is_ipc_url <url>
+is_ipc_url() {
+    [[ "$1" =~ ^[iI][pP][cC]:// ]]
+}
+
+# Whether an endpoint is served over a persistent connection (WebSocket or IPC) instead of HTTP
+# Usage: is_connection_url <url>
+is_connection_url() {
+    is_ws_url "$1" || is_ipc_url "$1"
+}
+
+# Relay newline-delimited JSON between stdin/stdout and a ws://, wss:// or ipc:// endpoint until
+# the connection closes: websocat for WebSockets (messages can hold whole blocks, so its buffer is
+# raised well above the 64 KiB default), socat for the unix socket of an IPC endpoint
+# Usage: connection_client <url>
+connection_client() {
+    local url="$1"
+
+    if is_ipc_url "$url"; then
+        socat - "UNIX-CONNECT:${url:6}"
+        return
+    fi
+
+    local header_options=()
+    local header
+    for header in "${RPC_HEADERS[@]}"; do
+        header_options+=(-H "$header")
+    done
+    websocat --text --buffer-size 67108864 "${header_options[@]}" "$url"
+}
+
+# Keep one WebSocket or IPC connection to an endpoint open: requests are written to the
+# connection's input FIFO, and each response is filed under responses/<request key> for ws_post
+# to pick up. Runs until the connection closes; ws_connect starts it in the background
+# Usage: ws_connection_run <url> <connection_dir>
+ws_connection_run() {
+    local url="$1"
+    local dir="$2"
+
+    # Opening the FIFO read-write keeps it from reaching EOF between requests
+    connection_client "$url" <> "$dir/in" 2> "$dir/errors" |
+        while IFS= read -r message; do
+            local key
+            key=$(echo "$message" | jq -r 'if type == "array" then .[0].id else .id end | tostring | split(":")[0]' \
+                2>/dev/null || true)
+            if [[ -n "$key" && "$key" != "null" ]]; then
+                echo "$message" > "$dir/responses/$key.tmp"
+                mv "$dir/responses/$key.tmp" "$dir/responses/$key"
+            fi
+        done
+}
+
+# Open the connection to a WebSocket or IPC endpoint unless it is already up (or reopen it after it
+# dropped); safe to call from several subprocesses at once
+# Usage: ws_connect <endpoint_index>
+ws_connect() {
+    local index="$1"
+    local dir="$WS_DIR/$index"
+
+    {
+        flock 9
+        if [[ -s "$dir/pid" ]] && kill -0 "$(cat "$dir/pid")" 2>/dev/null; then
+            return 0
+        fi
+        mkdir -p "$dir/responses"
+        rm -f "$dir/in"
+        mkfifo "$dir/in"
+        ws_connection_run "${RPC_URLS[$index]}" "$dir" < /dev/null > /dev/null 2>&1 9>&- &
+        echo "$!" > "$dir/pid"
+    } 9> "$WS_DIR/$index.lock"
+}
+
+# Close every WebSocket and IPC connection opened by ws_connect
+ws_disconnect_all() {
+    if [[ -z "$WS_DIR" || ! -d "$WS_DIR" ]]; then
+        return 0
+    fi
+    local pid_file
+    for pid_file in "$WS_DIR"/*/pid; do
+        if [[ -s "$pid_file" ]]; then
+            kill_job_tree "$(cat "$pid_file")"
+        fi
+    done
+}
+
+# Send a JSON-RPC request (or batch) over an endpoint's persistent WebSocket or IPC connection
+# Requests from all subprocesses share the connection, so the ids are rewritten to
+# "<request key>:<position>" to find the response again, and restored before it is returned.
+# Prints 200 and writes the response to body_file, or prints 000 when none arrives in time
+# Usage: ws_post <endpoint_index> <request_json> <max_time_seconds> <body_file>
+ws_post() {
+    local index="$1"
+    local request="$2"
+    local max_time="$3"
+    local body_file="$4"
+    local dir="$WS_DIR/$index"
+
+    ws_connect "$index"
+
+    local key="$BASHPID-$(now_ms)-$RANDOM"
+    local message
+    message=$(echo "$request" | jq -c --arg key "$key" '
+        if type == "array" then to_entries | map(.value + {id: "\($key):\(.key)"})
+        else . + {id: "\($key):0"} end')
+
+    # A message longer than the pipe buffer is written in pieces, so writers take turns
+    {
+        flock 9
+        echo "$message" > "$dir/in"
+    } 9> "$dir/write.lock"
+
+    # Give up at the deadline, or at once when the connection has dropped (the next request reopens it)
+    local response_file="$dir/responses/$key"
+    local deadline_ms=$(($(now_ms) + max_time * 1000))
+    local round=0
+    while [[ ! -e "$response_file" ]]; do
+        if [[ $(now_ms) -ge $deadline_ms ]] || ! kill -0 "$(cat "$dir/pid")" 2>/dev/null; then
+            : > "$body_file"
+            echo "000"
+            return
+        fi
+        poll_sleep "$round"
+        round=$((round + 1))
+    done
+
+    jq -c --argjson request "$request" '
+        def original_id: if type == "string" and test(":[0-9]+$")
+            then ($request | if type == "array" then . else [.] end)[split(":")[-1] | tonumber].id
+            else . end;
+        if type == "array" then map(.id |= original_id) else .id |= original_id end' \
+        "$response_file" > "$body_file"
+    rm -f "$response_file"
+    echo "200"
+}
+
+# Take the next <count> JSON-RPC request ids from the counter shared with subprocesses and
+# print the first, so ids only ever increase over a run and are never reused
+# Usage: reserve_request_ids <count>
+reserve_request_ids() {
+    local count="$1"
+    {
+        flock 9
+        local last
+        last=$(cat "$RPC_REQUEST_ID_FILE")
+        echo $((last + count)) > "$RPC_REQUEST_ID_FILE"
+        echo $((last + 1))
+    } 9> "$RPC_REQUEST_ID_FILE.lock"
+}
+
+# POST a JSON-RPC request to the next healthy endpoint of the pool
+# HTTP 429/503 (or a JSON-RPC 429 error) is retried up to --max-retries times, waiting for
+# Retry-After when the endpoint sends one. Non-2xx responses without a JSON body are turned
+# into a JSON-RPC error object so callers never try to parse an HTML error page.
+# Timeouts, non-JSON bodies and rate-limit errors count against the endpoint's health;
+# other JSON-RPC errors are answers to the request itself and are returned as-is.
+# Over HTTP every request (and every element of a batch) is sent with a fresh id from
+# reserve_request_ids, which load balancers can key on; the caller's ids are put back into the
+# response, so a batch is still matched up by the ids its caller chose.
+# ws://, wss:// and ipc:// endpoints are sent the request over their persistent connection instead
+# Usage: rpc_post <request_json> [max_time_seconds]
+rpc_post() {
+    local request="$1"
+    local max_time="${2:-30}"
+    local attempt=0
+    local response=""
+
+    while true; do
+        rate_limit_acquire
+
+        local index
+        index=$(rpc_pool_select)
+        echo "1" >> "$RPC_POOL_DIR/$index.requests"
+
+        local body_file
+        local header_file
+        body_file=$(mktemp "$TEMP_DIR/rpc_body.XXXXXX")
+        header_file=$(mktemp "$TEMP_DIR/rpc_headers.XXXXXX")
+
+        local request_start_ms
+        request_start_ms=$(now_ms)
+
+        local http_code
+        if is_connection_url "${RPC_URLS[$index]}"; then
+            http_code=$(ws_post "$index" "$request" "$max_time" "$body_file")
+        else
+            local network_options=()
+            if [[ -n "$RPC_PROXY" ]]; then
+                network_options+=(--proxy "$RPC_PROXY")
+            fi
+            if [[ -n "$RPC_CA_CERT" ]]; then
+                network_options+=(--cacert "$RPC_CA_CERT" --proxy-cacert "$RPC_CA_CERT")
+            fi
+            if [[ ${#RPC_HEADERS[@]} -gt 0 ]]; then
+                network_options+=(-H "@$RPC_HEADERS_FILE")
+            fi
+            local id_count=1
+            if [[ "$request" == "["* ]]; then
+                id_count=$(echo "$request" | jq 'length')
+            fi
+            local first_id
+            first_id=$(reserve_request_ids "$id_count")
+            local numbered_request
+            numbered_request=$(echo "$request" | jq -c --argjson first "$first_id" '
+                if type == "array" then to_entries | map(.value + {id: ($first + .key)})
+                else . + {id: $first} end')
+            http_code=$(curl -s -X POST \
+                -H "Content-Type: application/json" \
+                -d "$numbered_request" \
+                --max-time "$max_time" \
+                "${network_options[@]}" \
+                -D "$header_file" \
+                -o "$body_file" \
+                -w '%{http_code}' \
+                "${RPC_URLS[$index]}" 2>/dev/null || true)
+
+            # Bodies that aren't JSON (an HTML error page) are left for the checks below
+            local restored
+            if restored=$(jq -c --argjson request "$request" --argjson first "$first_id" '
+                def original_id: if type == "number" and . >= $first
+                    then . as $id | ($request | if type == "array" then . else [.] end)[$id - $first].id // $id
+                    else . end;
+                if type == "array" then map(if type == "object" then .id |= original_id else . end)
+                elif type == "object" then .id |= original_id
+                else . end' "$body_file" 2>/dev/null) && [[ -n "$restored" ]]; then
+                echo "$restored" > "$body_file"
+            fi
+        fi
+        http_code=${http_code:-000}
+
+        local retry_after
+        response=$(cat "$body_file")
+        retry_after=$(awk -F': *' 'tolower($1) == "retry-after" {print $2}' "$header_file" | tr -d '\r' | tail -1)
+        rm -f "$body_file" "$header_file"
+
+        local error_code
+        error_code=$(echo "$response" | jq -r 'if type == "object" then (.error.code // "") else "" end' 2>/dev/null || echo "invalid")
+
+        # Infura-style providers answer an eth_getLogs over too wide a range with -32005 as well;
+        # that says nothing about the node's load (see prefetch_logs)
+        if [[ "$error_code" == "-32005" ]] && is_log_range_too_large "$response"; then
+            error_code=""
+        fi
+
+        if [[ "$http_code" == "429" || "$http_code" == "503" || "$error_code" == "429" ]]; then
+            rpc_pool_record "$index" fail
+            adaptive_record congestion
+            attempt=$((attempt + 1))
+            if [[ $attempt -le $MAX_RETRIES ]]; then
+                echo "1" >> "$RPC_COUNTER_DIR/rate_limit_retry.count"
+                rate_limit_sleep "$retry_after" "$attempt"
+                continue
+            fi
+            log error "Max retries reached for rate-limited request"
+            jq -n -c --arg status "$http_code" \
+                '{"jsonrpc": "2.0", "error": {"code": 429, "message": ("Rate limited (HTTP " + $status + ")")}}'
+            return
+        fi
+
+        if [[ -z "$response" || "$error_code" == "invalid" || "$error_code" == "-32005" || "$http_code" == 5* ]]; then
+            rpc_pool_record "$index" fail
+        else
+            rpc_pool_record "$index" ok
+        fi
+
+        # Timeouts (curl reports 000) and -32005 "limit exceeded" mean the node is saturated
+        if [[ "$http_code" == "000" || "$error_code" == "-32005" ]]; then
+            adaptive_record congestion
+        else
+            adaptive_record $(($(now_ms) - request_start_ms))
+        fi
+
+        # Surface HTTP errors without a JSON-RPC body as a JSON-RPC error
+        if [[ "$http_code" != 2* && "$http_code" != "000" && ( -z "$response" || "$error_code" == "invalid" ) ]]; then
+            jq -n -c --argjson status "$http_code" --arg endpoint "$(endpoint_label "${RPC_URLS[$index]}")" \
+                '{"jsonrpc": "2.0", "error": {"code": $status, "message": ("HTTP \($status) from \($endpoint)")}}'
+            return
+        fi
+
+        echo "$response"
+        return
+    done
+}
+
+# Check if required tools are available
+check_dependencies() {
+    local missing_tools=()
+
+    # The block pool waits for whichever fetch finishes first with wait -n (bash 4.3; macOS ships 3.2)
+    if [[ ${BASH_VERSINFO[0]} -lt 4 || ( ${BASH_VERSINFO[0]} -eq 4 && ${BASH_VERSINFO[1]} -lt 3 ) ]]; then
+        log error "Error: bash 4.3 or newer is required (this is $BASH_VERSION; on macOS: brew install bash)"
+        exit 1
+    fi
+
+    if ! command -v curl &> /dev/null; then
+        missing_tools+=("curl")
+    fi
+
+    if ! command -v jq &> /dev/null; then
+        missing_tools+=("jq")
+    fi
+
+    # Subprocesses take turns on shared files with flock (util-linux; on macOS: brew install flock)
+    if ! command -v flock &> /dev/null; then
+        missing_tools+=("flock")
+    fi
+
+    if [[ "$COMPRESS" == "gzip" ]] && ! command -v gzip &> /dev/null; then
+        missing_tools+=("gzip")
+    fi
+
+    if [[ "$COMPRESS_STDOUT" == "true" ]] && ! command -v base64 &> /dev/null; then
+        missing_tools+=("base64")
+    fi
+
+    local url
+    for url in "${RPC_URLS[@]}"; do
+        if is_ws_url "$url" && ! command -v websocat &> /dev/null; then
+            missing_tools+=("websocat")
+            break
+        fi
+    done
+    for url in "${RPC_URLS[@]}"; do
+        if is_ipc_url "$url" && ! command -v socat &> /dev/null; then
+            missing_tools+=("socat")
+            break
+        fi
+    done
+
+    if [[ -n "$SQLITE_DB" ]] && ! command -v sqlite3 &> /dev/null; then
+        missing_tools+=("sqlite3")
+    fi
+
+    if [[ "$OUTPUT_FORMAT" == "parquet" ]] && ! command -v duckdb &> /dev/null; then
+        missing_tools+=("duckdb")
+    fi
+
+    if [[ ${#missing_tools[@]} -gt 0 ]]; then
+        log error "Error: Missing required tools: ${missing_tools[*]}"
+        log error "Please install the missing tools and try again."
+        exit 1
+    fi
+}
+
+# Convert hex to decimal (decimal input is passed through)
+# Returns non-zero without output if the value is neither, so callers can report it
+hex_to_decimal() {
+    local hex_value="$1"
+    if [[ "$hex_value" =~ ^0x[0-9a-fA-F]+$ ]]; then
+        printf "%d" "$hex_value"
+    elif [[ "$hex_value" =~ ^[0-9]+$ ]]; then
+        echo "$hex_value"
+    else
+        return 1
+    fi
+}
+
+# Add comma-separated addresses to a target list, lowercased and without duplicates
+# Usage: merge_target_contracts <existing_csv> <new_csv>
+merge_target_contracts() {
+    local target_contracts="$1"
+    local target
+    local targets=()
+    IFS=',' read -r -a targets <<< "$2"
+    for target in "${targets[@]}"; do
+        target=$(echo "$target" | tr '[:upper:]' '[:lower:]')
+        if [[ -n "$target" && ",$target_contracts," != *",$target,"* ]]; then
+            target_contracts="${target_contracts:+$target_contracts,}$target"
+        fi
+    done
+    echo "$target_contracts"
+}
+
+# Keccak-256 of a string as 64 hex digits, from OpenSSL 3.2+ or cast (Foundry)
+# Usage: keccak256 <text>
+keccak256() {
+    local hash
+    if hash=$(printf '%s' "$1" | openssl dgst -keccak-256 -r 2>/dev/null); then
+        echo "${hash%% *}"
+    elif command -v cast &> /dev/null; then
+        hash=$(cast keccak "$1") || return 1
+        echo "${hash#0x}"
+    else
+        return 1
+    fi
+}
+
+# EIP-55 checksum casing of an address
+# Usage: to_checksum_address <address>
+to_checksum_address() {
+    local address
+    address=$(echo "${1#0x}" | tr '[:upper:]' '[:lower:]')
+
+    local hash
+    if ! hash=$(keccak256 "$address"); then
+        log error "Error: --strict-checksum needs keccak-256 from OpenSSL 3.2+ or cast (Foundry)"
+        return 1
+    fi
+
+    # Uppercase each letter whose matching hash nibble is >= 8
+    local checksummed="0x"
+    local i
+    local char
+    for ((i = 0; i < 40; i++)); do
+        char="${address:i:1}"
+        if [[ "$char" == [a-f] && $((16#${hash:i:1})) -ge 8 ]]; then
+            char=$(echo "$char" | tr '[:lower:]' '[:upper:]')
+        fi
+        checksummed+="$char"
+    done
+    echo "$checksummed"
+}
+
+# Rewrite the from, to and matched_contract of records in EIP-55 casing (--checksum-addresses).
+# Matching is done by then, so only the written form changes; each address is hashed once a run
+# Usage: checksum_record_addresses <records_file>
+checksum_record_addresses() {
+    local records_file="$1"
+    local cache_file="$TEMP_DIR/checksummed_addresses.txt"
+    touch "$cache_file"
+
+    local address
+    local checksummed
+    while IFS= read -r address; do
+        if ! grep -q "^$address " "$cache_file"; then
+            checksummed=$(to_checksum_address "$address") || return 1
+            echo "$address $checksummed" >> "$cache_file"
+        fi
+    done < <(jq -r '.from, .to, .matched_contract | select(. != null and . != "") | ascii_downcase' "$records_file" | sort -u)
+
+    jq -c --slurpfile cased <(jq -Rn '[inputs | split(" ") | {key: .[0], value: .[1]}] | from_entries' "$cache_file") '
+        def cased: if . == null or . == "" then . else $cased[0][ascii_downcase] // . end;
+        .from |= cased | .to |= cased | .matched_contract |= cased' "$records_file" > "$records_file.cased" || return 1
+    mv "$records_file.cased" "$records_file"
+}
+
+# Validate target addresses before any RPC work, so a typo fails fast instead of matching nothing
+# Usage: validate_target_contracts <csv>
+validate_target_contracts() {
+    local target
+    local targets=()
+    IFS=',' read -r -a targets <<< "$1"
+    for target in "${targets[@]}"; do
+        if [[ -z "$target" ]]; then
+            continue
+        fi
+        if ! [[ "$target" =~ ^0x[0-9a-fA-F]{40}$ ]]; then
+            log error "Error: Invalid target contract '$target': expected 0x followed by 40 hex characters"
+            return 1
+        fi
+        if [[ "$STRICT_CHECKSUM" == "true" ]]; then
+            local checksummed
+            checksummed=$(to_checksum_address "$target") || return 1
+            if [[ "$target" != "$checksummed" ]]; then
+                log error "Error: Target contract '$target' fails the EIP-55 checksum (expected $checksummed)"
+                return 1
+            fi
+        fi
+    done
+}
+
+# Read target addresses from a --contracts-file, printed comma-separated
+# Usage: read_contracts_file <path>
+read_contracts_file() {
+    local path="$1"
+
+    if [[ ! -r "$path" ]]; then
+        log error "Error: Cannot read --contracts-file $path"
+        return 1
+    fi
+
+    local line
+    local line_number=0
+    local addresses=""
+    while IFS= read -r line || [[ -n "$line" ]]; do
+        line_number=$((line_number + 1))
+        # Drop comments and surrounding whitespace (including CRLF line endings)
+        line="${line%%#*}"
+        line=$(echo "$line" | tr -d '[:space:]')
+        if [[ -z "$line" ]]; then
+            continue
+        fi
+        if ! [[ "$line" =~ ^0x[0-9a-fA-F]{40}$ ]]; then
+            log error "Error: $path:$line_number: '$line' is not a 20-byte hex address"
+            return 1
+        fi
+        addresses="${addresses:+$addresses,}$line"
+    done < "$path"
+
+    echo "$addresses"
+}
+
+# Read block numbers from a --blocks-file (one per line or comma-separated; "#" starts a comment)
+# into <list_file>, one per line in ascending order without duplicates
+# Usage: read_blocks_file <path> <list_file>
+read_blocks_file() {
+    local path="$1"
+    local list_file="$2"
+
+    if [[ ! -r "$path" ]]; then
+        log error "Error: Cannot read --blocks-file $path"
+        return 1
+    fi
+
+    local line
+    local line_number=0
+    local block
+    local blocks=()
+    while IFS= read -r line || [[ -n "$line" ]]; do
+        line_number=$((line_number + 1))
+        line="${line%%#*}"
+        IFS=',' read -r -a blocks <<< "$(echo "$line" | tr -d '[:space:]')"
+        for block in "${blocks[@]}"; do
+            if [[ -z "$block" ]]; then
+                continue
+            fi
+            if ! [[ "$block" =~ ^[0-9]+$ ]]; then
+                log error "Error: $path:$line_number: '$block' is not a block number"
+                return 1
+            fi
+            echo $((10#$block))
+        done
+    done < "$path" > "$list_file.read"
+    sort -n -u "$list_file.read" > "$list_file"
+    rm -f "$list_file.read"
+
+    if [[ ! -s "$list_file" ]]; then
+        log error "Error: --blocks-file $path lists no blocks"
+        return 1
+    fi
+}
+
+# Convert a wei amount (decimal or 0x hex, up to 256 bits) to a zero-padded 64-digit hex word,
+# so amounts beyond 64 bits compare correctly as strings
+# Usage: wei_to_word <amount>
+wei_to_word() {
+    local amount="$1"
+    local hex=""
+    local hex_digits="0123456789abcdef"
+
+    if [[ "$amount" =~ ^0[xX]([0-9a-fA-F]+)$ ]]; then
+        hex=$(echo "${BASH_REMATCH[1]}" | tr '[:upper:]' '[:lower:]' | sed 's/^0*//')
+    elif [[ "$amount" =~ ^[0-9]+$ ]]; then
+        # Long division by 16 on the decimal digits, collecting remainders as hex digits
+        local digits
+        digits=$(echo "$amount" | sed 's/^0*//')
+        while [[ -n "$digits" ]]; do
+            local quotient=""
+            local remainder=0
+            local i
+            for ((i = 0; i < ${#digits}; i++)); do
+                local current=$((remainder * 10 + ${digits:i:1}))
+                quotient+=$((current / 16))
+                remainder=$((current % 16))
+            done
+            hex="${hex_digits:remainder:1}$hex"
+            digits=$(echo "$quotient" | sed 's/^0*//')
+        done
+    else
+        return 1
+    fi
+
+    if [[ ${#hex} -gt 64 ]]; then
+        return 1
+    fi
+    printf '%64s' "$hex" | tr ' ' '0'
+}
+
+# Check a wei amount against inclusive bounds given as words from wei_to_word (empty = unbounded)
+# Usage: wei_in_range <amount> <min_word> <max_word>
+wei_in_range() {
+    local amount="$1"
+    local min_word="$2"
+    local max_word="$3"
+
+    if [[ -z "$min_word" && -z "$max_word" ]]; then
+        return 0
+    fi
+
+    local word
+    word=$(wei_to_word "$amount") || return 1
+    if [[ -n "$min_word" && "$word" < "$min_word" ]]; then
+        return 1
+    fi
+    if [[ -n "$max_word" && "$word" > "$max_word" ]]; then
+        return 1
+    fi
+}
+
+# Check a transaction's value (hex wei) against --min-value/--max-value
+value_in_range() {
+    wei_in_range "${1:-0x0}" "$MIN_VALUE" "$MAX_VALUE"
+}
+
+# Check a transaction's gas price (hex wei) against --min-gas-price/--max-gas-price
+# Type-2 transactions may come without a gas price; they pass here and are checked on their
+# effective gas price once receipts are in (see effective_gas_price_in_range)
+gas_price_in_range() {
+    local gas_price="$1"
+    if [[ -z "$gas_price" ]]; then
+        return 0
+    fi
+    wei_in_range "$gas_price" "$MIN_GAS_PRICE" "$MAX_GAS_PRICE"
+}
+
+# Check a transaction's calldata against the --selector lists: the global selectors plus those
+# scoped to the contract it matched. Succeeds when neither list has any; input shorter than 4
+# bytes (e.g. "0x") never matches
+# Usage: selector_matches <input> [matched_contract]
+selector_matches() {
+    local input="$1"
+    local contract="${2:-}"
+
+    local allowed="$SELECTORS"
+    if [[ -n "$CONTRACT_SELECTORS" && -n "$contract" ]]; then
+        local entry
+        for entry in ${CONTRACT_SELECTORS//,/ }; do
+            if [[ "${entry%%:*}" == "$contract" ]]; then
+                allowed="${allowed:+$allowed,}${entry#*:}"
+            fi
+        done
+    fi
+    if [[ -z "$allowed" ]]; then
+        return 0
+    fi
+
+    if [[ ${#input} -lt 10 ]]; then
+        return 1
+    fi
+
+    local selector
+    selector=$(echo "${input:0:10}" | tr '[:upper:]' '[:lower:]')
+    [[ ",$allowed," == *",$selector,"* ]]
+}
+
+# Check a transaction's sender against the --exclude-from list
+# Usage: sender_excluded <from>
+sender_excluded() {
+    local from
+    from=$(echo "$1" | tr '[:upper:]' '[:lower:]')
+    [[ -n "$EXCLUDE_FROM" && -n "$from" && ",$EXCLUDE_FROM," == *",$from,"* ]]
+}
+
+# Record a range of blocks as failed
+record_failed_blocks() {
+    local start_block="$1"
+    local end_block="$2"
+    local block
+    for ((block=start_block; block<=end_block; block++)); do
+        echo "$block" >> "$FAILED_BLOCKS_FILE"
+    done
+}
+
+# Check that every block of a range names the block before it as its parent (--verify-chain)
+# Blocks whose predecessor wasn't fetched (failed, or before a resumed checkpoint) are not checked;
+# a refetched block's latest link wins
+# Usage: verify_chain_links <start_block> <end_block>
+verify_chain_links() {
+    local start_block="$1"
+    local end_block="$2"
+
+    local mismatch
+    mismatch=$(awk -v from="$start_block" -v to="$end_block" '
+        { hash[$1] = $2; parent[$1] = $3 }
+        END {
+            for (n = from; n <= to; n++) {
+                if ((n in parent) && ((n - 1) in hash) && parent[n] != hash[n - 1]) {
+                    print n, parent[n], hash[n - 1]
+                    exit
+                }
+            }
+        }' "$CHAIN_LINKS_FILE")
+
+    if [[ -n "$mismatch" ]]; then
+        local block parent_hash previous_hash
+        read -r block parent_hash previous_hash <<< "$mismatch"
+        log warn "[REORG] Block $block has parentHash $parent_hash, but block $((block - 1)) was fetched with hash $previous_hash"
+        return 1
+    fi
+}
+
+# Count RPC calls from counter file
+count_rpc_calls() {
+    local counter_name="$1"
+    count_rpc_calls_in "$RPC_COUNTER_DIR" "${counter_name}.count"
+}
+
+# Count lines of a counter file in a directory (0 if missing)
+count_rpc_calls_in() {
+    local dir="$1"
+    local file="$dir/$2"
+    if [[ -f "$file" ]]; then
+        wc -l < "$file" | tr -d ' '
+    else
+        echo "0"
+    fi
+}
+
+# Format the JSON-RPC error of a response as "message (code N)"
+# Includes the code so e.g. a rate limit (-32005) can be told apart from a missing block
+format_rpc_error() {
+    local response="$1"
+    local formatted
+    formatted=$(echo "$response" | jq -r '
+        .error
+        | if type == "object" then
+            "\(.message // "Unknown error") (code \(.code // "unknown"))"
+          else
+            tostring
+          end' 2>/dev/null || echo "")
+    if [[ -z "$formatted" ]]; then
+        formatted="Unknown error (unparseable response)"
+    fi
+    echo "$formatted"
+}
+
+# Check if RPC error says the node no longer has the state a trace or call needs: a full
+# (non-archive) node keeps the state of recent blocks only, so tracing an older one fails this way
+is_state_unavailable() {
+    local response="$1"
+    local error_msg
+    error_msg=$(echo "$response" | jq -r '.error.message // empty' 2>/dev/null | tr '[:upper:]' '[:lower:]')
+
+    [[ "$error_msg" == *"missing trie node"* ]] ||
+        [[ "$error_msg" == *"state unavailable"* ]] ||
+        [[ "$error_msg" == *"state is not available"* ]] ||
+        [[ "$error_msg" == *"state not available"* ]] ||
+        [[ "$error_msg" == *"historical state"* ]] ||
+        [[ "$error_msg" == *"pruned"* ]]
+}
+
+# The message for a trace or call on <block_number> that failed with is_state_unavailable
+# Usage: unavailable_state_message <block_number> <response>
+unavailable_state_message() {
+    echo "Block $1 requires an archive node: the endpoint no longer has its state ($(format_rpc_error "$2"))"
+}
+
+# Check if RPC error indicates unsupported method
+is_method_unsupported() {
+    local response="$1"
+
+    # "historical state not available" is about the block, not the method
+    if is_state_unavailable "$response"; then
+        return 1
+    fi
+
+    local error_code
+    local error_msg
+    error_code=$(echo "$response" | jq -r '.error.code // empty' 2>/dev/null)
+    error_msg=$(echo "$response" | jq -r '.error.message // empty' 2>/dev/null | tr '[:upper:]' '[:lower:]')
+
+    if [[ "$error_code" == "-32601" ]]; then
+        return 0
+    fi
+    if [[ -n "$error_msg" ]]; then
+        if [[ "$error_msg" == *"method not found"* ]] ||
+           [[ "$error_msg" == *"does not exist"* ]] ||
+           [[ "$error_msg" == *"not available"* ]] ||
+           [[ "$error_msg" == *"unknown method"* ]] ||
+           [[ "$error_msg" == *"not supported"* ]]; then
+            return 0
+        fi
+    fi
+    return 1
+}
+
+# Build the internal record for a transaction: one compact JSON object per line
+# block_number and transaction_index are JSON numbers so records order and compare numerically;
+# the encoders turn them into decimal strings for the payload
+# Usage: build_transaction_record <tx_json> <block_number> <transaction_index> <matched_contract> <block_header_json>
+build_transaction_record() {
+    local tx="$1"
+    local block_number="$2"
+    local tx_index="$3"
+    local matched_contract="$4"
+    local block_header="$5"
+
+    echo "$tx" | jq -c \
+        --argjson block_number "$block_number" \
+        --argjson tx_index "$tx_index" \
+        --arg matched_contract "$matched_contract" \
+        --argjson block_header "${block_header:-null}" \
+        --argjson with_access_list "$WITH_ACCESS_LIST" \
+        "$JQ_HEX_TO_DEC"'def quantity_to_dec:
+            if type == "string" and test("^0x[0-9a-fA-F]+$") then ltrimstr("0x") | hex_to_dec
+            elif type == "string" and test("^[0-9]+$") then .
+            else null end;
+        {
+            hash: .hash,
+            from: .from,
+            to: (.to // ""),
+            is_creation: (.to == null),
+            value: .value,
+            data: .input,
+            data_len: ((.input // "0x") | ltrimstr("0x") | length / 2 | floor | tostring),
+            has_data: ((.input // "0x") != "0x"),
+            block_number: $block_number,
+            block_timestamp: ($block_header.timestamp | quantity_to_dec // ""),
+            # Pre-London blocks have no base fee; the field is null rather than an invented zero
+            base_fee_per_gas: ($block_header.baseFeePerGas | quantity_to_dec),
+            transaction_index: $tx_index,
+            # Nodes may omit "type" on pre-Berlin transactions, which are all legacy (type 0)
+            tx_type: ((.type // "0x0") | quantity_to_dec // "0"),
+            nonce: (.nonce | quantity_to_dec // ""),
+            gas: (.gas | quantity_to_dec // ""),
+            gas_price: .gasPrice,
+            max_fee_per_gas: .maxFeePerGas,
+            max_priority_fee_per_gas: .maxPriorityFeePerGas,
+            matched_contract: $matched_contract
+        } + if $with_access_list then {access_list: .accessList} else {} end'
+}
+
+# Fetch a block header (the block without its transactions) from the node
+# Usage: fetch_block_header <block_param>   (hex block number or a tag such as "latest")
+fetch_block_header() {
+    local block_param="$1"
+
+    local header_request
+    header_request=$(jq -n \
+        --arg block_param "$block_param" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "eth_getBlockByNumber",
+            "params": [$block_param, false],
+            "id": 1
+        }')
+
+    echo "1" >> "$RPC_COUNTER_DIR/block_header.count"
+    local header_response
+    header_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$header_request" 30)
+
+    local header
+    header=$(echo "$header_response" | jq -c '.result // empty | del(.transactions)' 2>/dev/null || true)
+    if [[ -z "$header" ]]; then
+        return 1
+    fi
+    echo "$header"
+}
+
+# Fetch a block header by number, cached for the whole run
+# Usage: get_block_header <block_number>
+get_block_header() {
+    local block_number="$1"
+    local cache_file="$BLOCK_HEADER_CACHE_DIR/$block_number.json"
+
+    if [[ -s "$cache_file" ]]; then
+        cat "$cache_file"
+        return 0
+    fi
+
+    local header
+    if ! header=$(fetch_block_header "$(printf "0x%x" "$block_number")"); then
+        return 1
+    fi
+
+    # Write through a temp file so concurrent readers never see a partial header
+    echo "$header" > "$cache_file.$BASHPID"
+    mv "$cache_file.$BASHPID" "$cache_file"
+    echo "$header"
+}
+
+# Read a block's timestamp (decimal unix seconds)
+# Usage: get_block_timestamp <block_number>
+get_block_timestamp() {
+    local block_number="$1"
+
+    local header
+    if ! header=$(get_block_header "$block_number"); then
+        log error "Error: Failed to fetch header of block $block_number"
+        return 1
+    fi
+    if ! hex_to_decimal "$(echo "$header" | jq -r '.timestamp // empty')"; then
+        log error "Error: Block $block_number has no valid timestamp"
+        return 1
+    fi
+}
+
+# One --headers-only record for a block: number, hash, timestamp and base fee (decimal, the base
+# fee null before London) with gas used and limit as hex quantities like the transaction records.
+# Prints nothing when the header can't be fetched
+# Usage: block_header_record <block_number>
+block_header_record() {
+    local block_number="$1"
+
+    local header
+    if ! header=$(fetch_block_header "$(printf "0x%x" "$block_number")"); then
+        log warn "Warning: Failed to fetch header of block $block_number"
+        return 0
+    fi
+
+    local block_timestamp
+    block_timestamp=$(hex_to_decimal "$(echo "$header" | jq -r '.timestamp // empty')") || block_timestamp=""
+    local base_fee_per_gas
+    base_fee_per_gas=$(hex_to_decimal "$(echo "$header" | jq -r '.baseFeePerGas // empty')") || base_fee_per_gas=""
+
+    echo "$header" | jq -c \
+        --argjson block_number "$block_number" \
+        --arg block_timestamp "$block_timestamp" \
+        --arg base_fee_per_gas "$base_fee_per_gas" \
+        '{
+            block_number: $block_number,
+            hash: .hash,
+            block_timestamp: $block_timestamp,
+            base_fee_per_gas: (if $base_fee_per_gas == "" then null else $base_fee_per_gas end),
+            gas_used: .gasUsed,
+            gas_limit: .gasLimit
+        }'
+}
+
+# Fetch the header of every block from START_BLOCK to END_BLOCK for --headers-only, MAX_CONCURRENT
+# at a time, one record per line in block order; blocks whose header can't be fetched go to
+# FAILED_BLOCKS_FILE
+# Usage: fetch_block_header_records <output_file>
+fetch_block_header_records() {
+    local output_file="$1"
+
+    local headers_dir="$TEMP_DIR/header_records"
+    rm -rf "$headers_dir"
+    mkdir -p "$headers_dir"
+
+    log info "Fetching headers of blocks $START_BLOCK to $END_BLOCK"
+
+    local pids=()
+    local block=$START_BLOCK
+    while [[ $block -le $END_BLOCK ]]; do
+        while [[ ${#pids[@]} -lt $MAX_CONCURRENT && $block -le $END_BLOCK ]]; do
+            block_header_record "$block" > "$headers_dir/$block.json" &
+            pids+=($!)
+            block=$((block + 1))
+        done
+
+        if [[ ${#pids[@]} -gt 0 ]]; then
+            wait "${pids[0]}"
+            pids=("${pids[@]:1}")
+        fi
+    done
+
+    if [[ ${#pids[@]} -gt 0 ]]; then
+        wait "${pids[@]}"
+    fi
+
+    : > "$output_file"
+    for ((block = START_BLOCK; block <= END_BLOCK; block++)); do
+        if [[ -s "$headers_dir/$block.json" ]]; then
+            cat "$headers_dir/$block.json" >> "$output_file"
+        else
+            echo "$block" >> "$FAILED_BLOCKS_FILE"
+        fi
+    done
+
+    INTERRUPTED=false
+    SCANNED_TO_BLOCK=$END_BLOCK
+}
+
+# Binary search the chain for the block bounding a timestamp
+# Usage: find_block_by_timestamp <timestamp> <first|last> <latest_block>
+#   first: lowest block with timestamp >= <timestamp>
+#   last:  highest block with timestamp <= <timestamp>
+# Prints nothing (and succeeds) when no block satisfies the bound
+find_block_by_timestamp() {
+    local timestamp="$1"
+    local bound="$2"
+    local latest_block="$3"
+    local low=0
+    local high=$latest_block
+    local mid
+    local mid_timestamp
+
+    if [[ "$bound" == "first" ]]; then
+        mid_timestamp=$(get_block_timestamp "$high") || return 1
+        if [[ $mid_timestamp -lt $timestamp ]]; then
+            return 0
+        fi
+        while [[ $low -lt $high ]]; do
+            mid=$(((low + high) / 2))
+            mid_timestamp=$(get_block_timestamp "$mid") || return 1
+            if [[ $mid_timestamp -ge $timestamp ]]; then
+                high=$mid
+            else
+                low=$((mid + 1))
+            fi
+        done
+    else
+        mid_timestamp=$(get_block_timestamp "$low") || return 1
+        if [[ $mid_timestamp -gt $timestamp ]]; then
+            return 0
+        fi
+        while [[ $low -lt $high ]]; do
+            mid=$(((low + high + 1) / 2))
+            mid_timestamp=$(get_block_timestamp "$mid") || return 1
+            if [[ $mid_timestamp -le $timestamp ]]; then
+                low=$mid
+            else
+                high=$((mid - 1))
+            fi
+        done
+    fi
+
+    echo "$low"
+}
+
+# Resolve a block tag (latest, earliest, safe, finalized) to a block number via its header
+# Usage: resolve_block_tag <tag>
+resolve_block_tag() {
+    local tag="$1"
+
+    local header
+    if ! header=$(fetch_block_header "$tag"); then
+        log error "Error: Failed to fetch the '$tag' block header (the node may not support this tag)"
+        return 1
+    fi
+    if ! hex_to_decimal "$(echo "$header" | jq -r '.number // empty')"; then
+        log error "Error: The '$tag' block header has no valid number"
+        return 1
+    fi
+}
+
+# Resolve --start-timestamp/--end-timestamp into block numbers
+# Usage: resolve_timestamp_range <start_timestamp> <end_timestamp>  (either may be empty)
+# Prints "<start_block> <end_block>", with an empty side left as "-"
+resolve_timestamp_range() {
+    local start_timestamp="$1"
+    local end_timestamp="$2"
+
+    local latest_block
+    latest_block=$(resolve_block_tag "latest") || return 1
+
+    local start_block="-"
+    local end_block="-"
+    if [[ -n "$start_timestamp" ]]; then
+        start_block=$(find_block_by_timestamp "$start_timestamp" first "$latest_block") || return 1
+        if [[ -z "$start_block" ]]; then
+            log error "Error: No block at or after --start-timestamp $start_timestamp (latest is $latest_block)"
+            return 1
+        fi
+    fi
+    if [[ -n "$end_timestamp" ]]; then
+        end_block=$(find_block_by_timestamp "$end_timestamp" last "$latest_block") || return 1
+        if [[ -z "$end_block" ]]; then
+            log error "Error: No block at or before --end-timestamp $end_timestamp"
+            return 1
+        fi
+    fi
+
+    echo "$start_block $end_block"
+}
+
+# Read the chain id (decimal) from the node
+get_chain_id() {
+    local response
+    echo "1" >> "$RPC_COUNTER_DIR/chain_id.count"
+    response=$(retry_with_backoff "$MAX_RETRIES" rpc_post '{"jsonrpc":"2.0","method":"eth_chainId","params":[],"id":1}' 30)
+
+    if ! hex_to_decimal "$(echo "$response" | jq -r '.result // empty' 2>/dev/null)"; then
+        log error "Error: Failed to read the chain id: $(format_rpc_error "$response")"
+        return 1
+    fi
+}
+
+# Print a cached eth_getBlockByNumber response for a block, failing when it isn't cached
+# Usage: block_cache_read <block_number>
+block_cache_read() {
+    local block_number="$1"
+    local cache_file="$BLOCK_CACHE_DIR/$block_number.json"
+
+    if [[ -z "$BLOCK_CACHE_DIR" || ! -s "$cache_file" ]]; then
+        return 1
+    fi
+
+    echo "1" >> "$RPC_COUNTER_DIR/block_cache_hit.count"
+    jq -c '{jsonrpc: "2.0", id: 1, result: .}' "$cache_file"
+}
+
+# Cache the block from an eth_getBlockByNumber response if it is final (at or below BLOCK_CACHE_MAX_BLOCK)
+# Usage: block_cache_write <block_number> <response>
+block_cache_write() {
+    local block_number="$1"
+    local response="$2"
+    local cache_file="$BLOCK_CACHE_DIR/$block_number.json"
+
+    if [[ -z "$BLOCK_CACHE_DIR" || $block_number -gt $BLOCK_CACHE_MAX_BLOCK || -s "$cache_file" ]]; then
+        return 0
+    fi
+
+    # Write through a temp file so concurrent runs never read a partial block
+    local temp_file="$cache_file.$BASHPID"
+    if echo "$response" | jq -c '.result // empty' > "$temp_file" 2>/dev/null && [[ -s "$temp_file" ]]; then
+        mv "$temp_file" "$cache_file"
+    else
+        rm -f "$temp_file"
+    fi
+}
+
+# Classify a transaction by its eth_getTransactionReceipt response, for the block scan and
+# trace discovery alike: prints success, reverted, or unknown for a pre-Byzantium receipt
+# (a state root instead of a status). An RPC error or missing receipt is logged and returns 1
+# Usage: receipt_outcome <receipt_response> <tx_hash> <block_number>
+receipt_outcome() {
+    local receipt_response="$1"
+    local tx_hash="$2"
+    local block_number="$3"
+
+    if echo "$receipt_response" | jq -e '.error != null' > /dev/null 2>&1; then
+        log error "Error: RPC error fetching receipt for $tx_hash in block $block_number: $(format_rpc_error "$receipt_response")"
+        return 1
+    fi
+    if ! echo "$receipt_response" | jq -e '.result | type == "object"' > /dev/null 2>&1; then
+        log error "Error: Failed to fetch receipt for $tx_hash in block $block_number"
+        return 1
+    fi
+
+    echo "$receipt_response" | jq -r '.result.status // null
+        | if . == null then "unknown" elif . == "0x1" then "success" else "reverted" end'
+}
+
+# Warn about the transactions left out because their receipt has no status (see receipt_outcome)
+# Usage: warn_unknown_status <count> <where>
+warn_unknown_status() {
+    local count="$1"
+    local where="$2"
+    if [[ $count -gt 0 ]]; then
+        log warn "Warning: Skipped $count transactions $where whose receipt has no status (pre-Byzantium), so whether they succeeded is unknown"
+    fi
+}
+
+# Fetch transaction details and receipts for a list of tx hashes
+# Each line of tx_hashes is "<hash> <matched_contract>"
+emit_transactions_from_hashes() {
+    local tx_hashes="$1"
+    local output_file="$2"
+
+    local tx_count=0
+    local tx_processed=0
+    local no_status=0
+
+    if [[ -n "$tx_hashes" ]]; then
+        while read -r tx_hash matched_contract; do
+            [[ -z "$tx_hash" ]] && continue
+
+            # Add small delay every 5 transactions to avoid rate limiting
+            if [[ $((tx_processed % 5)) -eq 0 && $tx_processed -gt 0 ]]; then
+                sleep 0.1
+            fi
+            ((tx_processed++))
+
+            # Fetch actual transaction data using eth_getTransactionByHash
+            local tx_request
+            tx_request=$(jq -n \
+                --arg tx_hash "$tx_hash" \
+                '{
+                    "jsonrpc": "2.0",
+                    "method": "eth_getTransactionByHash",
+                    "params": [$tx_hash],
+                    "id": 1
+                }')
+
+            echo "1" >> "$RPC_COUNTER_DIR/tx_fetch.count"
+            local tx_response
+            tx_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$tx_request" 30)
+
+            local tx_data
+            tx_data=$(echo "$tx_response" | jq -c '.result')
+
+            if [[ -n "$tx_data" && "$tx_data" != "null" ]]; then
+                # Contract creations are only kept with --include-creations
+                local is_creation=false
+                if echo "$tx_data" | jq -e '.to == null' > /dev/null; then
+                    [[ "$INCLUDE_CREATIONS" != "true" ]] && continue
+                    is_creation=true
+                fi
+
+                # Skip transactions that don't call one of the requested --selector functions (a
+                # creation's input is init code, so it has no selector to match)
+                if [[ "$is_creation" != "true" ]] &&
+                    ! selector_matches "$(echo "$tx_data" | jq -r '.input // ""')" "$matched_contract"; then
+                    continue
+                fi
+
+                # Skip transactions outside the --min-value/--max-value range
+                if ! value_in_range "$(echo "$tx_data" | jq -r '.value // "0x0"')"; then
+                    continue
+                fi
+
+                # Skip transactions outside the --min-gas-price/--max-gas-price range
+                if ! gas_price_in_range "$(echo "$tx_data" | jq -r '.gasPrice // ""')"; then
+                    continue
+                fi
+
+                # Skip transactions sent by an --exclude-from address
+                if sender_excluded "$(echo "$tx_data" | jq -r '.from // ""')"; then
+                    continue
+                fi
+
+                local tx_block_num_hex
+                local tx_index_hex
+                tx_block_num_hex=$(echo "$tx_data" | jq -r '.blockNumber // empty')
+                tx_index_hex=$(echo "$tx_data" | jq -r '.transactionIndex // empty')
+                if [[ -z "$tx_index_hex" ]]; then
+                    log error "Error: Node returned no transactionIndex for mined transaction $tx_hash, so its replay order is unknown"
+                    echo "$tx_count"
+                    return 1
+                fi
+
+                local block_num
+                local tx_index
+                if ! block_num=$(hex_to_decimal "$tx_block_num_hex"); then
+                    log error "Error: Invalid block number '$tx_block_num_hex' for transaction $tx_hash"
+                    echo "$tx_count"
+                    return 1
+                fi
+                if ! tx_index=$(hex_to_decimal "$tx_index_hex"); then
+                    log error "Error: Invalid transaction index '$tx_index_hex' for transaction $tx_hash in block $block_num"
+                    echo "$tx_count"
+                    return 1
+                fi
+
+                # Check if transaction succeeded on-chain
+                local receipt_request
+                receipt_request=$(jq -n \
+                    --arg tx_hash "$tx_hash" \
+                    '{
+                        "jsonrpc": "2.0",
+                        "method": "eth_getTransactionReceipt",
+                        "params": [$tx_hash],
+                        "id": 1
+                    }')
+
+                echo "1" >> "$RPC_COUNTER_DIR/receipt_fetch.count"
+                local receipt_response
+                receipt_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$receipt_request" 30)
+                local outcome
+                if ! outcome=$(receipt_outcome "$receipt_response" "$tx_hash" "$block_num"); then
+                    echo "$tx_count"
+                    return 1
+                fi
+
+                # Only output transaction if it succeeded; reverted ones are only counted
+                if [[ "$outcome" == "unknown" ]]; then
+                    no_status=$((no_status + 1))
+                elif [[ "$outcome" == "reverted" ]]; then
+                    echo "$tx_hash" >> "$RPC_COUNTER_DIR/reverted.hashes"
+                else
+                    local block_header
+                    if ! block_header=$(get_block_header "$block_num"); then
+                        log error "Error: Failed to fetch header of block $block_num for transaction $tx_hash"
+                        echo "$tx_count"
+                        return 1
+                    fi
+                    if [[ "$WITH_RECEIPTS" == "true" ]]; then
+                        echo "$receipt_response" | jq -c '.result' > "$SCAN_RECEIPTS_DIR/$tx_hash.json"
+                    fi
+                    build_transaction_record "$tx_data" "$block_num" "$tx_index" "$matched_contract" "$block_header" >> "$output_file"
+                    ((tx_count++))
+                fi
+            fi
+        done <<< "$tx_hashes"
+    fi
+    warn_unknown_status "$no_status" "found by trace discovery"
+
+    echo "$tx_count"
+}
+
+# jq defs for callTracer output: first_call_target($targets) walks a call tree (the calls in order,
+# depth first) and yields the first target it calls, or null
+JQ_FIRST_CALL_TARGET='
+    def first_call_target($targets):
+        if type == "object" then
+            ((.to? // "") | ascii_downcase) as $to
+            | if any($targets[]; . == $to) then
+                $to
+              else
+                ([.calls[]? | first_call_target($targets)] | map(select(. != null)) | .[0])
+              end
+        else
+            null
+        end;
+'
+
+# Fetch transactions using trace_filter (much faster for internal calls)
+fetch_transactions_trace_filter() {
+    local start_block="$1"
+    local end_block="$2"
+    local target_contracts="$3"
+    local output_file="$4"
+
+    # Convert block numbers to hex
+    local start_hex
+    local end_hex
+    start_hex=$(printf "0x%x" "$start_block")
+    end_hex=$(printf "0x%x" "$end_block")
+
+    log info "[TRACE] Using trace_filter API (fastest method for internal call detection)"
+    log info "[TRACE] Fetching blocks $start_block to $end_block..."
+
+    # Prepare trace_filter request
+    local trace_request
+    trace_request=$(jq -n \
+        --arg start_hex "$start_hex" \
+        --arg end_hex "$end_hex" \
+        --arg targets "$target_contracts" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "trace_filter",
+            "params": [{
+                "fromBlock": $start_hex,
+                "toBlock": $end_hex,
+                "toAddress": ($targets | split(","))
+            }],
+            "id": 1
+        }')
+
+    # Make RPC call with retry logic
+    echo "1" >> "$RPC_COUNTER_DIR/trace_filter.count"
+    local trace_response
+    trace_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$trace_request" 60)
+
+    # Check for errors
+    if [[ -z "$trace_response" ]] || echo "$trace_response" | jq -e '.error' > /dev/null 2>&1; then
+        local error_msg
+        error_msg=$(format_rpc_error "$trace_response")
+        log error "Error: trace_filter failed: $error_msg"
+        if is_method_unsupported "$trace_response"; then
+            log info "[TRACE] trace_filter not supported by this RPC endpoint"
+            echo "0"
+            return 2
+        fi
+        echo "0"
+        return 1
+    fi
+
+    # Extract unique transaction hashes that involve a target contract, with the target hit
+    local tx_hashes
+    tx_hashes=$(echo "$trace_response" | jq -r --arg targets "$target_contracts" '
+        ($targets | split(",")) as $t
+        | .result[]?
+        | ((.action.to? // "") | ascii_downcase) as $to
+        | (if any($t[]; . == $to) then $to elif ($t | length) == 1 then $t[0] else "" end) as $matched
+        | "\(.transactionHash) \($matched)"
+    ')
+    tx_hashes=$(echo "$tx_hashes" | awk 'NF' | awk '!seen[$1]++')
+
+    local tx_count
+    if ! tx_count=$(emit_transactions_from_hashes "$tx_hashes" "$output_file"); then
+        echo "0"
+        return 1
+    fi
+
+    log debug "  Found $tx_count transactions in blocks $start_block-$end_block"
+    echo "$tx_count"
+}
+
+# Fetch transactions using debug_traceBlockByNumber with callTracer
+fetch_transactions_debug_trace_block() {
+    local start_block="$1"
+    local end_block="$2"
+    local target_contracts="$3"
+    local output_file="$4"
+
+    local tx_hashes=""
+
+    for ((block=start_block; block<=end_block; block++)); do
+        local block_hex
+        block_hex=$(printf "0x%x" "$block")
+
+        log debug "[TRACE] Tracing block $block with debug_traceBlockByNumber..."
+
+        local trace_request
+        trace_request=$(jq -n \
+            --arg block_hex "$block_hex" \
+            '{
+                "jsonrpc": "2.0",
+                "method": "debug_traceBlockByNumber",
+                "params": [$block_hex, {"tracer":"callTracer"}],
+                "id": 1
+            }')
+
+        echo "1" >> "$RPC_COUNTER_DIR/debug_trace_block.count"
+        local trace_response
+        trace_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$trace_request" 60)
+
+        if [[ -z "$trace_response" ]] || echo "$trace_response" | jq -e '.error' > /dev/null 2>&1; then
+            local error_msg
+            error_msg=$(format_rpc_error "$trace_response")
+            if is_state_unavailable "$trace_response"; then
+                log error "Error: $(unavailable_state_message "$block" "$trace_response")"
+                echo "0"
+                return 1
+            fi
+            log error "Error: debug_traceBlockByNumber failed: $error_msg"
+            if is_method_unsupported "$trace_response"; then
+                log info "[TRACE] debug_traceBlockByNumber not supported by this RPC endpoint"
+                echo "0"
+                return 2
+            fi
+            echo "0"
+            return 1
+        fi
+
+        local block_tx_hashes
+        block_tx_hashes=$(echo "$trace_response" | jq -r --arg targets "$target_contracts" "$JQ_FIRST_CALL_TARGET"'
+            ($targets | split(",")) as $t
+            | .result[]?
+            | ((.result? // .) | first_call_target($t)) as $matched
+            | select($matched != null)
+            | "\(.txHash // .transactionHash // .hash // empty) \($matched)"
+        ')
+
+        if [[ -n "$block_tx_hashes" ]]; then
+            tx_hashes+="$block_tx_hashes"$'\n'
+        fi
+    done
+
+    tx_hashes=$(echo "$tx_hashes" | awk 'NF' | awk '!seen[$1]++')
+    local tx_count
+    if ! tx_count=$(emit_transactions_from_hashes "$tx_hashes" "$output_file"); then
+        echo "0"
+        return 1
+    fi
+
+    log debug "  Found $tx_count transactions in blocks $start_block-$end_block"
+    echo "$tx_count"
+}
+
+# Fetch transactions using debug_traceTransaction with callTracer (slow fallback)
+fetch_transactions_debug_trace_tx() {
+    local start_block="$1"
+    local end_block="$2"
+    local target_contracts="$3"
+    local output_file="$4"
+
+    local tx_hashes=""
+
+    for ((block=start_block; block<=end_block; block++)); do
+        local block_hex
+        block_hex=$(printf "0x%x" "$block")
+
+        local block_request
+        block_request=$(jq -n \
+            --arg block_hex "$block_hex" \
+            '{
+                "jsonrpc": "2.0",
+                "method": "eth_getBlockByNumber",
+                "params": [$block_hex, false],
+                "id": 1
+            }')
+
+        echo "1" >> "$RPC_COUNTER_DIR/block_fetch.count"
+        local block_response
+        block_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$block_request" 30)
+
+        if [[ -z "$block_response" ]] || echo "$block_response" | jq -e '.error' > /dev/null 2>&1; then
+            local error_msg
+            error_msg=$(format_rpc_error "$block_response")
+            log error "Error: eth_getBlockByNumber failed: $error_msg"
+            echo "0"
+            return 1
+        fi
+
+        local tx_list
+        tx_list=$(echo "$block_response" | jq -r '.result.transactions[]? // empty')
+
+        while IFS= read -r tx_hash; do
+            [[ -z "$tx_hash" ]] && continue
+
+            local trace_request
+            trace_request=$(jq -n \
+                --arg tx_hash "$tx_hash" \
+                '{
+                    "jsonrpc": "2.0",
+                    "method": "debug_traceTransaction",
+                    "params": [$tx_hash, {"tracer":"callTracer"}],
+                    "id": 1
+                }')
+
+            echo "1" >> "$RPC_COUNTER_DIR/debug_trace_tx.count"
+            local trace_response
+            trace_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$trace_request" 60)
+
+            if [[ -z "$trace_response" ]] || echo "$trace_response" | jq -e '.error' > /dev/null 2>&1; then
+                local error_msg
+                error_msg=$(format_rpc_error "$trace_response")
+                if is_state_unavailable "$trace_response"; then
+                    log error "Error: $(unavailable_state_message "$block" "$trace_response")"
+                    echo "0"
+                    return 1
+                fi
+                log error "Error: debug_traceTransaction failed: $error_msg"
+                if is_method_unsupported "$trace_response"; then
+                    log info "[TRACE] debug_traceTransaction not supported by this RPC endpoint"
+                    echo "0"
+                    return 2
+                fi
+                continue
+            fi
+
+            local matched_contract
+            matched_contract=$(echo "$trace_response" | jq -r --arg targets "$target_contracts" "$JQ_FIRST_CALL_TARGET"'
+                ($targets | split(",")) as $t
+                | (.result? // empty)
+                | first_call_target($t) // empty
+            ')
+            if [[ -n "$matched_contract" ]]; then
+                tx_hashes+="$tx_hash $matched_contract"$'\n'
+            fi
+        done <<< "$tx_list"
+    done
+
+    tx_hashes=$(echo "$tx_hashes" | awk 'NF' | awk '!seen[$1]++')
+    local tx_count
+    if ! tx_count=$(emit_transactions_from_hashes "$tx_hashes" "$output_file"); then
+        echo "0"
+        return 1
+    fi
+
+    log debug "  Found $tx_count transactions in blocks $start_block-$end_block"
+    echo "$tx_count"
+}
+
+# Fetch transactions from a single block (single attempt, no retries)
+# Returns non-zero on any RPC or parse failure so the caller can retry the block
+fetch_block_transactions_once() {
+    local block_number="$1"
+    local target_contracts="$2"
+    local output_file="$3"
+    local rpc_counter_dir="$4"
+
+    local response
+    if response=$(block_cache_read "$block_number"); then
+        process_block_response "$block_number" "$target_contracts" "$output_file" "$rpc_counter_dir" "$response"
+        return
+    fi
+
+    # Convert block number to hex
+    local block_hex
+    block_hex=$(printf "0x%x" "$block_number")
+
+    # Prepare RPC request
+    local rpc_request
+    rpc_request=$(jq -n \
+        --arg method "eth_getBlockByNumber" \
+        --arg block_hex "$block_hex" \
+        '{
+            "jsonrpc": "2.0",
+            "method": $method,
+            "params": [$block_hex, true],
+            "id": 1
+        }')
+
+    # Make the request (retries are handled per block by fetch_block_transactions)
+    echo "1" >> "$rpc_counter_dir/block_fetch.count"
+    response=$(rpc_post "$rpc_request" 30)
+
+    if [[ -z "$response" ]]; then
+        log error "Error: Empty response for block $block_number"
+        return 1
+    fi
+
+    process_block_response "$block_number" "$target_contracts" "$output_file" "$rpc_counter_dir" "$response" || return 1
+    block_cache_write "$block_number" "$response"
+}
+
+# Check if an eth_getLogs error says the request spans too many blocks or results, which
+# providers phrase as "query returned more than 10000 results", "block range too large" and the like
+is_log_range_too_large() {
+    local response="$1"
+    local error_msg
+    error_msg=$(echo "$response" | jq -r '.error.message // empty' 2>/dev/null | tr '[:upper:]' '[:lower:]')
+
+    [[ "$error_msg" == *"more than"* ]] ||
+        [[ "$error_msg" == *"too large"* ]] ||
+        [[ "$error_msg" == *"too many"* ]] ||
+        [[ "$error_msg" == *"block range"* ]] ||
+        [[ "$error_msg" == *"range limit"* ]] ||
+        [[ "$error_msg" == *"exceed"*"range"* ]]
+}
+
+# Fetch the target logs of blocks <from_block>..<to_block> with one eth_getLogs and file them by
+# block for fetch_block_logs (LOGS_DIR/<block>.logs, empty for blocks without any). A window the
+# provider rejects as too large is split in half, recursively, down to single blocks; windows
+# that fail otherwise are left unfiled, so their blocks ask for their own logs during the scan
+# Usage: prefetch_logs <from_block> <to_block> <target_contracts>
+prefetch_logs() {
+    local from_block="$1"
+    local to_block="$2"
+    local target_contracts="$3"
+
+    local logs_request
+    logs_request=$(jq -n \
+        --arg from_hex "$(printf "0x%x" "$from_block")" \
+        --arg to_hex "$(printf "0x%x" "$to_block")" \
+        --arg targets "$target_contracts" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "eth_getLogs",
+            "params": [{
+                "fromBlock": $from_hex,
+                "toBlock": $to_hex,
+                "address": ($targets | split(","))
+            }],
+            "id": 1
+        }')
+
+    echo "1" >> "$RPC_COUNTER_DIR/log_fetch.count"
+    local logs_response
+    logs_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$logs_request" 60 || true)
+
+    if is_log_range_too_large "$logs_response" && [[ $from_block -lt $to_block ]]; then
+        local mid_block=$(((from_block + to_block) / 2))
+        log debug "[LOGS] eth_getLogs for blocks $from_block-$to_block was too large ($(format_rpc_error "$logs_response")); splitting into $from_block-$mid_block and $((mid_block + 1))-$to_block"
+        prefetch_logs "$from_block" "$mid_block" "$target_contracts"
+        prefetch_logs "$((mid_block + 1))" "$to_block" "$target_contracts"
+        return
+    fi
+
+    if ! echo "$logs_response" | jq -e '.result | type == "array"' > /dev/null 2>&1; then
+        log debug "[LOGS] eth_getLogs for blocks $from_block-$to_block failed ($(format_rpc_error "$logs_response")); fetching their logs per block"
+        return
+    fi
+
+    log debug "[LOGS] eth_getLogs for blocks $from_block-$to_block: $(echo "$logs_response" | jq '.result | length') logs"
+    local block
+    for ((block = from_block; block <= to_block; block++)); do
+        : > "$LOGS_DIR/$block.logs.tmp"
+    done
+    echo "$logs_response" | jq -r '.result[] | "\(.blockNumber) \(.transactionHash) \(.address | ascii_downcase)"' |
+        while read -r block_hex tx_hash address; do
+            echo "$tx_hash $address" >> "$LOGS_DIR/$((block_hex)).logs.tmp"
+        done
+    for ((block = from_block; block <= to_block; block++)); do
+        mv "$LOGS_DIR/$block.logs.tmp" "$LOGS_DIR/$block.logs"
+    done
+}
+
+# Collect the transactions of a block that produced logs from any target contract, from the logs
+# prefetch_logs filed or else with an eth_getLogs of its own
+# Usage: fetch_block_logs <block_number> <target_contracts> <rpc_counter_dir>
+# Prints one "<tx_hash> <emitting_contract>" line per transaction (first matching log wins)
+fetch_block_logs() {
+    local block_number="$1"
+    local target_contracts="$2"
+    local rpc_counter_dir="$3"
+
+    if [[ -f "$LOGS_DIR/$block_number.logs" ]]; then
+        awk '!seen[$1]++' "$LOGS_DIR/$block_number.logs"
+        return
+    fi
+
+    local block_hex
+    block_hex=$(printf "0x%x" "$block_number")
+
+    local logs_request
+    logs_request=$(jq -n \
+        --arg block_hex "$block_hex" \
+        --arg targets "$target_contracts" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "eth_getLogs",
+            "params": [{
+                "fromBlock": $block_hex,
+                "toBlock": $block_hex,
+                "address": ($targets | split(","))
+            }],
+            "id": 1
+        }')
+
+    echo "1" >> "$rpc_counter_dir/log_fetch.count"
+    local logs_response
+    logs_response=$(rpc_post "$logs_request" 30)
+
+    if [[ -z "$logs_response" ]] || ! echo "$logs_response" | jq -e 'type == "object"' > /dev/null 2>&1; then
+        log error "Error: Invalid eth_getLogs response for block $block_number"
+        return 1
+    fi
+
+    if echo "$logs_response" | jq -e '.error != null' > /dev/null 2>&1; then
+        log error "Error: eth_getLogs failed for block $block_number: $(format_rpc_error "$logs_response")"
+        return 1
+    fi
+
+    echo "$logs_response" | jq -r '.result[]? | "\(.transactionHash) \(.address | ascii_downcase)"' |
+        awk 'NF' | awk '!seen[$1]++'
+}
+
+# Print the request for the traces of one block with <method> (trace_block or debug_trace_block)
+# Usage: block_trace_request <method> <block_hex>
+block_trace_request() {
+    if [[ "$1" == "trace_block" ]]; then
+        jq -n --arg block_hex "$2" '{"jsonrpc": "2.0", "method": "trace_block", "params": [$block_hex], "id": 1}'
+    else
+        jq -n --arg block_hex "$2" \
+            '{"jsonrpc": "2.0", "method": "debug_traceBlockByNumber", "params": [$block_hex, {"tracer": "callTracer"}], "id": 1}'
+    fi
+}
+
+# Pick the per-block trace method for --match-by traces: parity-style trace_block (OpenEthereum,
+# Nethermind, Erigon) or else debug_traceBlockByNumber with callTracer (Geth), by asking for the
+# traces of <block_number>. Sets BLOCK_TRACE_METHOD; fails when the node offers neither
+# Usage: detect_block_trace_method <block_number>
+detect_block_trace_method() {
+    local block_hex
+    block_hex=$(printf "0x%x" "$1")
+
+    local method
+    for method in trace_block debug_trace_block; do
+        echo "1" >> "$RPC_COUNTER_DIR/$method.count"
+        local response
+        response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$(block_trace_request "$method" "$block_hex")" 60) || true
+        if [[ -n "$response" ]] && echo "$response" | jq -e '.result | type == "array"' > /dev/null 2>&1; then
+            BLOCK_TRACE_METHOD="$method"
+            return 0
+        fi
+        if is_state_unavailable "$response"; then
+            log error "Error: $(unavailable_state_message "$1" "$response")"
+            return 1
+        fi
+        if ! is_method_unsupported "$response"; then
+            log error "Error: Probing $method failed: $(format_rpc_error "$response")"
+            return 1
+        fi
+    done
+
+    log error "Error: --match-by traces needs trace_block (OpenEthereum, Nethermind, Erigon) or debug_traceBlockByNumber with callTracer (Geth), and the RPC endpoint supports neither"
+    return 1
+}
+
+# Collect the transactions of a block whose call tree reaches a target contract (--match-by
+# traces), with BLOCK_TRACE_METHOD. Traces without a transaction hash (old Geth callTracer
+# output) are keyed "#<position>" by their transaction's index in the block instead
+# Usage: fetch_block_traces <block_number> <target_contracts> <rpc_counter_dir>
+# Prints one "<tx_hash> <called_contract>" line per transaction (first call in trace order wins)
+fetch_block_traces() {
+    local block_number="$1"
+    local target_contracts="$2"
+    local rpc_counter_dir="$3"
+
+    local block_hex
+    block_hex=$(printf "0x%x" "$block_number")
+
+    echo "1" >> "$rpc_counter_dir/$BLOCK_TRACE_METHOD.count"
+
+    # Geth call trees of a whole block run to many megabytes; keep them in a file and stream them
+    if [[ "$BLOCK_TRACE_METHOD" == "debug_trace_block" ]]; then
+        local trace_file
+        trace_file=$(mktemp "$TEMP_DIR/block_trace.XXXXXX")
+        rpc_post "$(block_trace_request "$BLOCK_TRACE_METHOD" "$block_hex")" 60 > "$trace_file"
+        local status=0
+        match_call_frames "$block_number" "$target_contracts" "$trace_file" || status=$?
+        rm -f "$trace_file"
+        return $status
+    fi
+
+    local trace_response
+    trace_response=$(rpc_post "$(block_trace_request "$BLOCK_TRACE_METHOD" "$block_hex")" 60)
+
+    if [[ -z "$trace_response" ]] || ! echo "$trace_response" | jq -e 'type == "object"' > /dev/null 2>&1; then
+        log error "Error: Invalid $BLOCK_TRACE_METHOD response for block $block_number"
+        return 1
+    fi
+
+    if is_state_unavailable "$trace_response"; then
+        log error "Error: $(unavailable_state_message "$block_number" "$trace_response")"
+        return 1
+    fi
+    if echo "$trace_response" | jq -e '.error != null' > /dev/null 2>&1; then
+        log error "Error: $BLOCK_TRACE_METHOD failed for block $block_number: $(format_rpc_error "$trace_response")"
+        return 1
+    fi
+
+    # Flat traces in execution order; rewards carry no transaction
+    echo "$trace_response" | jq -r --arg targets "$target_contracts" '
+        ($targets | split(",")) as $t
+        | .result[]?
+        | select(.type == "call" and .transactionHash != null)
+        | ((.action.to // "") | ascii_downcase) as $to
+        | select(any($t[]; . == $to))
+        | "\(.transactionHash) \($to)"' | awk 'NF' | awk '!seen[$1]++'
+}
+
+# Match the callTracer trees of a debug_traceBlockByNumber response saved in <trace_file> against
+# the targets without loading them: jq --stream passes on only each transaction's txHash and the
+# "to" of its frames, in the order the tracer wrote them (a frame before its calls, so depth
+# first). With --with-call-trace the matched transactions' frames are kept as well, as
+# CALL_TRACES_DIR/<block_number>-<position>.json (see process_block_response)
+# Usage: match_call_frames <block_number> <target_contracts> <trace_file>
+# Prints the same "<tx_hash or #position> <called_contract>" lines as fetch_block_traces
+match_call_frames() {
+    local block_number="$1"
+    local target_contracts="$2"
+    local trace_file="$3"
+
+    local events_file="$trace_file.events"
+    if [[ ! -s "$trace_file" ]] || ! jq -rn --stream '
+            inputs | select(length == 2) | .[0] as $path
+            | if ($path | length) == 0 or ($path[0] | type) != "string" then
+                "invalid"
+              elif $path[0] == "error" then
+                "error"
+              elif $path[0] != "result" or ($path[1] | type) != "number" then
+                empty
+              elif ($path | length) == 3 and $path[2] == "txHash" then
+                "\($path[1]) hash \(.[1])"
+              elif $path[-1] == "to" and (.[1] | type) == "string" then
+                "\($path[1]) to \(.[1] | ascii_downcase)"
+              else
+                empty
+              end' "$trace_file" > "$events_file" 2>/dev/null || grep -q '^invalid$' "$events_file"; then
+        log error "Error: Invalid $BLOCK_TRACE_METHOD response for block $block_number"
+        rm -f "$events_file"
+        return 1
+    fi
+
+    if grep -q '^error$' "$events_file"; then
+        if is_state_unavailable "$(cat "$trace_file")"; then
+            log error "Error: $(unavailable_state_message "$block_number" "$(cat "$trace_file")")"
+        else
+            log error "Error: $BLOCK_TRACE_METHOD failed for block $block_number: $(format_rpc_error "$(cat "$trace_file")")"
+        fi
+        rm -f "$events_file"
+        return 1
+    fi
+
+    # "<position> <key> <called_contract>" per matched transaction, in block order
+    local matches
+    matches=$(awk -v targets=",$target_contracts," '
+        $1 + 0 > last { last = $1 + 0 }
+        $2 == "hash" { hash[$1] = $3 }
+        $2 == "to" && !($1 in matched) && index(targets, "," $3 ",") { matched[$1] = $3 }
+        END {
+            for (position = 0; position <= last; position++) {
+                if (position in matched) {
+                    print position, (position in hash ? hash[position] : "#" position), matched[position]
+                }
+            }
+        }' "$events_file")
+    rm -f "$events_file"
+
+    [[ -z "$matches" ]] && return 0
+
+    if [[ "$WITH_CALL_TRACE" == "true" ]]; then
+        local position
+        local frame
+        while read -r position frame; do
+            echo "$frame" > "$CALL_TRACES_DIR/$block_number-$position.json"
+        done < <(jq -r --argjson positions "[$(awk '{ print $1 }' <<< "$matches" | paste -sd, -)]" \
+            '.result as $traces | $positions[] | "\(.) \($traces[.] | (.result? // .) | tojson)"' "$trace_file")
+    fi
+
+    awk '{ print $2, $3 }' <<< "$matches"
+}
+
+# Filter the transactions of a single eth_getBlockByNumber response
+# Returns non-zero on any RPC or parse failure so the caller can retry the block
+process_block_response() {
+    local block_number="$1"
+    local target_contracts="$2"
+    local output_file="$3"
+    local rpc_counter_dir="$4"
+    local response="$5"
+
+    # Check for RPC errors before looking at the result
+    if ! echo "$response" | jq -e 'type == "object"' > /dev/null 2>&1; then
+        log error "Error: Invalid JSON response for block $block_number"
+        return 1
+    fi
+
+    if echo "$response" | jq -e '.error != null' > /dev/null 2>&1; then
+        log error "Error: RPC error for block $block_number: $(format_rpc_error "$response")"
+        return 1
+    fi
+
+    # Extract block data
+    local block_data
+    if ! block_data=$(echo "$response" | jq -r '.result // empty' 2>/dev/null); then
+        log error "Error: Invalid JSON response for block $block_number"
+        return 1
+    fi
+
+    if [[ -z "$block_data" || "$block_data" == "null" || "$block_data" == "empty" ]]; then
+        log warn "Warning: No block data for block $block_number"
+        return 1
+    fi
+
+    # Get block number and transactions
+    local block_num_hex
+    if ! block_num_hex=$(echo "$block_data" | jq -r '.number' 2>/dev/null); then
+        log error "Error: Failed to parse block number for block $block_number"
+        return 1
+    fi
+
+    local transactions
+    if ! transactions=$(echo "$block_data" | jq -c '.transactions[]? // empty' 2>/dev/null); then
+        log error "Error: Failed to parse transactions for block $block_number"
+        return 1
+    fi
+
+    # Convert block number to decimal
+    local block_num_decimal
+    if ! block_num_decimal=$(hex_to_decimal "$block_num_hex"); then
+        log error "Error: Invalid block number '$block_num_hex' in response for block $block_number"
+        return 1
+    fi
+
+    local block_header
+    block_header=$(echo "$block_data" | jq -c 'del(.transactions)')
+
+    if [[ "$VERIFY_CHAIN" == "true" ]]; then
+        echo "$block_num_decimal $(echo "$block_header" | jq -r '"\(.hash) \(.parentHash)"')" >> "$CHAIN_LINKS_FILE"
+    fi
+
+    # With --match-by logs, transactions whose logs came from a target also match
+    local log_matches=""
+    if [[ "$MATCH_BY" == "logs" ]]; then
+        if ! log_matches=$(fetch_block_logs "$block_number" "$target_contracts" "$rpc_counter_dir"); then
+            return 1
+        fi
+    fi
+
+    # With --match-by traces, transactions that call a target anywhere in their trace match
+    local trace_matches=""
+    if [[ "$MATCH_BY" == "traces" ]]; then
+        if ! trace_matches=$(fetch_block_traces "$block_number" "$target_contracts" "$rpc_counter_dir"); then
+            return 1
+        fi
+    fi
+
+    # Process each transaction - direct calls (tx.to is one of the targets) plus log and trace matches
+    local position=-1
+    local found=0
+    local pending=0
+    local no_status=0
+    while IFS= read -r tx; do
+        [[ -z "$tx" ]] && continue
+        position=$((position + 1))
+
+        local tx_hash
+        local tx_to
+        tx_hash=$(echo "$tx" | jq -r '.hash')
+        tx_to=$(echo "$tx" | jq -r '.to // empty' | tr '[:upper:]' '[:lower:]')
+
+        # Contract creations (to == null) are only considered with --include-creations
+        local is_creation=false
+        if [[ -z "$tx_to" ]]; then
+            [[ "$INCLUDE_CREATIONS" != "true" ]] && continue
+            is_creation=true
+        fi
+
+        local matched_contract=""
+        if [[ -n "$tx_to" && ",$target_contracts," == *",$tx_to,"* ]]; then
+            matched_contract="$tx_to"
+        elif [[ -n "$log_matches" ]]; then
+            matched_contract=$(awk -v hash="$tx_hash" '$1 == hash { print $2; exit }' <<< "$log_matches")
+        elif [[ -n "$trace_matches" ]]; then
+            matched_contract=$(awk -v hash="$tx_hash" -v position="#$position" \
+                '$1 == hash || $1 == position { print $2; exit }' <<< "$trace_matches")
+        fi
+        if [[ -f "$CALL_TRACES_DIR/$block_number-$position.json" ]]; then
+            mv "$CALL_TRACES_DIR/$block_number-$position.json" "$CALL_TRACES_DIR/$tx_hash.json"
+        fi
+        # A creation can still match through the contract it deploys, which needs the receipt
+        if [[ -z "$matched_contract" && "$is_creation" != "true" ]]; then
+            continue
+        fi
+
+        # Skip transactions that don't call one of the requested --selector functions (a
+        # creation's input is init code, so it has no selector to match)
+        if [[ "$is_creation" != "true" ]] &&
+            ! selector_matches "$(echo "$tx" | jq -r '.input // ""')" "$matched_contract"; then
+            continue
+        fi
+
+        # Skip transactions outside the --min-value/--max-value range
+        if ! value_in_range "$(echo "$tx" | jq -r '.value // "0x0"')"; then
+            continue
+        fi
+
+        # Skip transactions outside the --min-gas-price/--max-gas-price range
+        if ! gas_price_in_range "$(echo "$tx" | jq -r '.gasPrice // ""')"; then
+            continue
+        fi
+
+        # Skip transactions sent by an --exclude-from address
+        if sender_excluded "$(echo "$tx" | jq -r '.from // ""')"; then
+            continue
+        fi
+
+        # A node answering at the chain tip can list transactions it hasn't indexed yet (no
+        # transactionIndex); they have no receipt and no replay order, so they're skipped unless
+        # --include-pending keeps them, flagged pending
+        local tx_index_hex
+        tx_index_hex=$(echo "$tx" | jq -r '.transactionIndex // empty')
+        if [[ -z "$tx_index_hex" ]]; then
+            if [[ "$INCLUDE_PENDING" != "true" ]]; then
+                pending=$((pending + 1))
+            elif [[ -n "$matched_contract" ]]; then
+                build_transaction_record "$tx" "$block_num_decimal" null "$matched_contract" "$block_header" |
+                    jq -c '. + {pending: true}' >> "$output_file"
+                found=$((found + 1))
+            fi
+            continue
+        fi
+
+        # Matching transaction found - check if transaction succeeded on-chain
+        local receipt_request
+        receipt_request=$(jq -n \
+            --arg tx_hash "$tx_hash" \
+            '{
+                "jsonrpc": "2.0",
+                "method": "eth_getTransactionReceipt",
+                "params": [$tx_hash],
+                "id": 1
+            }')
+
+        echo "1" >> "$rpc_counter_dir/receipt_fetch.count"
+        local receipt_response
+        receipt_response=$(rpc_post "$receipt_request" 30)
+        local outcome
+        outcome=$(receipt_outcome "$receipt_response" "$tx_hash" "$block_number") || return 1
+
+        if [[ -z "$matched_contract" ]]; then
+            local created_contract
+            created_contract=$(echo "$receipt_response" | jq -r '.result.contractAddress // empty' | tr '[:upper:]' '[:lower:]')
+            if [[ -z "$created_contract" || ",$target_contracts," != *",$created_contract,"* ]]; then
+                continue
+            fi
+            matched_contract="$created_contract"
+        fi
+
+        # Only output transaction if it succeeded; reverted ones are only counted
+        if [[ "$outcome" == "unknown" ]]; then
+            no_status=$((no_status + 1))
+        elif [[ "$outcome" == "reverted" ]]; then
+            echo "$tx_hash" >> "$rpc_counter_dir/reverted.hashes"
+        else
+            # Convert transaction index to decimal
+            local tx_index_decimal
+            if ! tx_index_decimal=$(hex_to_decimal "$tx_index_hex"); then
+                log error "Error: Invalid transaction index '$tx_index_hex' for transaction $tx_hash in block $block_number"
+                return 1
+            fi
+
+            if [[ "$WITH_RECEIPTS" == "true" ]]; then
+                echo "$receipt_response" | jq -c '.result' > "$SCAN_RECEIPTS_DIR/$tx_hash.json"
+            fi
+            build_transaction_record "$tx" "$block_num_decimal" "$tx_index_decimal" "$matched_contract" "$block_header" >> "$output_file"
+            found=$((found + 1))
+        fi
+    done <<< "$transactions"
+    if [[ $pending -gt 0 ]]; then
+        log warn "Warning: Skipped $pending pending transactions (no transactionIndex) in block $block_num_decimal; pass --include-pending to keep them"
+    fi
+    warn_unknown_status "$no_status" "in block $block_num_decimal"
+    log debug "  Block $block_num_decimal: found $found transactions"
+}
+
+# Fetch transactions from a single block, retrying the whole block with backoff
+# Transactions are only appended to the output once the block fully succeeds, so a
+# retried block produces exactly the same output as a first-try success
+fetch_block_transactions() {
+    local block_number="$1"
+    local target_contracts="$2"
+    local output_file="$3"
+    local rpc_counter_dir="$4"
+
+    local block_output="$TEMP_DIR/block_$block_number.txt"
+    local attempt=0
+
+    while true; do
+        : > "$block_output"
+        if fetch_block_transactions_once "$block_number" "$target_contracts" "$block_output" "$rpc_counter_dir"; then
+            cat "$block_output" >> "$output_file"
+            rm -f "$block_output"
+            return 0
+        fi
+
+        attempt=$((attempt + 1))
+        if [[ $attempt -gt $MAX_RETRIES ]]; then
+            log error "Error: Failed to fetch block $block_number after $attempt attempts"
+            rm -f "$block_output"
+            return 1
+        fi
+
+        log debug "Retrying block $block_number (retry $attempt/$MAX_RETRIES)"
+        echo "1" >> "$rpc_counter_dir/block_retry.count"
+        backoff_sleep "$attempt"
+    done
+}
+
+# Fetch a range of blocks with a single JSON-RPC batch request
+# Each block gets its own request id, the block number, so results can be mapped back regardless
+# of response order; rpc_post sends fresh ids and puts these back. Blocks whose element is missing
+# or carries an error are re-fetched individually with retries.
+fetch_blocks_batched() {
+    local start_block="$1"
+    local end_block="$2"
+    local target_contracts="$3"
+    local output_file="$4"
+
+    # Cached blocks are left out of the request
+    local uncached_blocks=()
+    for ((block=start_block; block<=end_block; block++)); do
+        if [[ -z "$BLOCK_CACHE_DIR" || ! -s "$BLOCK_CACHE_DIR/$block.json" ]]; then
+            uncached_blocks+=("$block")
+        fi
+    done
+
+    local batch_request
+    batch_request=$(for block in "${uncached_blocks[@]}"; do
+            printf '%d 0x%x\n' "$block" "$block"
+        done | jq -R -s -c '
+            split("\n")
+            | map(select(length > 0) | split(" ") | {
+                "jsonrpc": "2.0",
+                "method": "eth_getBlockByNumber",
+                "params": [.[1], true],
+                "id": (.[0] | tonumber)
+            })')
+
+    local batch_response="[]"
+    if [[ ${#uncached_blocks[@]} -gt 0 ]]; then
+        echo "1" >> "$RPC_COUNTER_DIR/block_batch.count"
+        batch_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$batch_request" 60) || true
+    fi
+
+    if ! echo "$batch_response" | jq -e 'type == "array"' > /dev/null 2>&1; then
+        if echo "$batch_response" | jq -e '.error != null' > /dev/null 2>&1; then
+            log warn "Warning: JSON-RPC batch rejected for blocks $start_block-$end_block: $(format_rpc_error "$batch_response")"
+        else
+            log warn "Warning: Invalid JSON-RPC batch response for blocks $start_block-$end_block"
+        fi
+    fi
+
+    # Split the batch once into one file per block id; the first element for an id wins
+    local responses_dir
+    responses_dir=$(mktemp -d "$TEMP_DIR/batch_responses.XXXXXX")
+    echo "$batch_response" | jq -r '
+        if type == "array" then .[] | select(type == "object" and (.id | type == "number"))
+            | "\(.id)\t\(tojson)"
+        else empty end' 2>/dev/null |
+        while IFS=$'\t' read -r id element; do
+            if [[ "$id" =~ ^[0-9]+$ && ! -e "$responses_dir/$id.json" ]]; then
+                echo "$element" > "$responses_dir/$id.json"
+            fi
+        done
+
+    local failed=0
+    for ((block=start_block; block<=end_block; block++)); do
+        local block_response
+        if ! block_response=$(block_cache_read "$block"); then
+            block_response=$(cat "$responses_dir/$block.json" 2>/dev/null || echo "")
+        fi
+
+        local block_output="$TEMP_DIR/block_$block.txt"
+        : > "$block_output"
+        if [[ -n "$block_response" ]] &&
+            process_block_response "$block" "$target_contracts" "$block_output" "$RPC_COUNTER_DIR" "$block_response"; then
+            block_cache_write "$block" "$block_response"
+            cat "$block_output" >> "$output_file"
+            rm -f "$block_output"
+            continue
+        fi
+        rm -f "$block_output"
+
+        log debug "Re-fetching block $block individually after batch failure"
+        if ! fetch_block_transactions "$block" "$target_contracts" "$output_file" "$RPC_COUNTER_DIR"; then
+            record_failed_blocks "$block" "$block"
+            failed=1
+        fi
+    done
+
+    rm -rf "$responses_dir"
+    return $failed
+}
+
+# Handle a per-transaction trace or call that found the state of its block pruned. With
+# --skip-unavailable-state the transaction is left out with a warning (the caller prints nothing
+# for it); otherwise the block is reported once and counts as failed, which fails the run unless
+# --no-fail-on-error is given
+# Usage: report_unavailable_state <record> <response>
+report_unavailable_state() {
+    local record="$1"
+    local response="$2"
+
+    local block_number
+    block_number=$(echo "$record" | jq -r '.block_number')
+
+    if [[ "$SKIP_UNAVAILABLE_STATE" == "true" ]]; then
+        log warn "Warning: Skipping $(echo "$record" | jq -r '.hash'): $(unavailable_state_message "$block_number" "$response")"
+        return
+    fi
+
+    # mkdir is atomic, so concurrent enrichments of one block report it only once
+    if mkdir "$UNAVAILABLE_STATE_DIR/$block_number" 2> /dev/null; then
+        log error "Error: $(unavailable_state_message "$block_number" "$response"); use an archive endpoint or pass --skip-unavailable-state to leave its transactions out"
+        echo "$block_number" >> "$FAILED_BLOCKS_FILE"
+    fi
+}
+
+# Merge the receipt of one transaction record into it
+# A receipt that can't be found leaves the receipt fields null instead of failing the run
+enrich_record_with_receipt() {
+    local record="$1"
+
+    local tx_hash
+    tx_hash=$(echo "$record" | jq -r '.hash')
+
+    local receipt_request
+    receipt_request=$(jq -n \
+        --arg tx_hash "$tx_hash" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "eth_getTransactionReceipt",
+            "params": [$tx_hash],
+            "id": 1
+        }')
+
+    echo "1" >> "$RPC_COUNTER_DIR/receipt_enrich.count"
+    local receipt_response
+    receipt_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$receipt_request" 30 || true)
+
+    local receipt
+    receipt=$(echo "$receipt_response" | jq -c '.result // empty' 2>/dev/null || true)
+    if [[ -z "$receipt" ]]; then
+        log warn "Warning: No receipt found for $tx_hash, leaving receipt fields empty"
+        receipt="null"
+    fi
+
+    echo "$record" | jq -c --argjson receipt "$receipt" "$JQ_HEX_TO_DEC$JQ_HEX_MUL$JQ_GAS_COST"'. + {
+        status: ($receipt | .status),
+        gas_used: ($receipt | .gasUsed),
+        effective_gas_price: ($receipt | .effectiveGasPrice),
+        logs: ($receipt | .logs)
+    } | with_gas_cost'
+}
+
+# Fetch all receipts of a block in one call with eth_getBlockReceipts
+# Prints the receipts as a JSON array; returns 2 if the node doesn't support the method
+fetch_block_receipts() {
+    local block_number="$1"
+
+    local block_hex
+    block_hex=$(printf "0x%x" "$block_number")
+
+    local receipts_request
+    receipts_request=$(jq -n \
+        --arg block_hex "$block_hex" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "eth_getBlockReceipts",
+            "params": [$block_hex],
+            "id": 1
+        }')
+
+    echo "1" >> "$RPC_COUNTER_DIR/block_receipts.count"
+    local receipts_response
+    receipts_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$receipts_request" 60 || true)
+
+    if is_method_unsupported "$receipts_response"; then
+        return 2
+    fi
+
+    if ! echo "$receipts_response" | jq -e '.result | type == "array"' > /dev/null 2>&1; then
+        log warn "Warning: eth_getBlockReceipts failed for block $block_number: $(format_rpc_error "$receipts_response")"
+        return 1
+    fi
+
+    echo "$receipts_response" | jq -c '.result'
+}
+
+# Fetch the receipts of every block with collected transactions, one eth_getBlockReceipts call
+# per block and up to max_concurrent at a time, and index them by transaction hash into
+# <receipts_by_hash>. Receipts the scan already fetched for the status check are reused, and
+# blocks whose transactions all have one aren't fetched. A node without the method (or a block
+# whose bulk fetch failed) leaves its transactions out, and enrich_record fetches their
+# receipts one by one instead
+# Usage: fetch_receipts_by_hash <transactions_file> <max_concurrent> <receipts_by_hash>
+fetch_receipts_by_hash() {
+    local transactions_file="$1"
+    local max_concurrent="$2"
+    local receipts_by_hash="$3"
+
+    local receipts_dir="$TEMP_DIR/receipts"
+    rm -rf "$receipts_dir"
+    mkdir -p "$receipts_dir"
+
+    local hash
+    jq -r '.hash' "$transactions_file" | while read -r hash; do
+        if [[ -s "$SCAN_RECEIPTS_DIR/$hash.json" ]]; then
+            cat "$SCAN_RECEIPTS_DIR/$hash.json"
+        fi
+    done | jq -s -c '.' > "$receipts_dir/block_scanned.json"
+
+    local blocks=()
+    mapfile -t blocks < <(jq -r --slurpfile scanned "$receipts_dir/block_scanned.json" '
+        ($scanned[0] | map({key: .transactionHash, value: true}) | from_entries) as $have
+        | select($have[.hash] | not) | .block_number' "$transactions_file" | awk '!seen[$0]++')
+    if [[ ${#blocks[@]} -eq 0 ]]; then
+        jq -c 'map({key: (.transactionHash | ascii_downcase), value: .}) | from_entries' \
+            "$receipts_dir/block_scanned.json" > "$receipts_by_hash"
+        return 0
+    fi
+
+    # Probe bulk receipt support with the first block
+    local probe_status=0
+    fetch_block_receipts "${blocks[0]}" > "$receipts_dir/block_${blocks[0]}.json" || probe_status=$?
+
+    local pids=()
+    local block_index=1
+    if [[ $probe_status -eq 2 ]]; then
+        log info "[RECEIPTS] eth_getBlockReceipts not supported, fetching receipts per transaction"
+        : > "$receipts_dir/block_${blocks[0]}.json"
+        block_index=${#blocks[@]}
+    fi
+
+    while [[ $block_index -lt ${#blocks[@]} ]]; do
+        while [[ ${#pids[@]} -lt $max_concurrent && $block_index -lt ${#blocks[@]} ]]; do
+            local block_num="${blocks[$block_index]}"
+            { fetch_block_receipts "$block_num" > "$receipts_dir/block_$block_num.json" || true; } &
+            pids+=($!)
+            block_index=$((block_index + 1))
+        done
+
+        if [[ ${#pids[@]} -gt 0 ]]; then
+            wait "${pids[0]}"
+            pids=("${pids[@]:1}")
+        fi
+    done
+
+    if [[ ${#pids[@]} -gt 0 ]]; then
+        wait "${pids[@]}"
+    fi
+
+    cat "$receipts_dir"/block_*.json | jq -s -c '
+        add // []
+        | map({key: (.transactionHash | ascii_downcase), value: .})
+        | from_entries
+    ' > "$receipts_by_hash"
+}
+
+# Check that the node serves debug_traceTransaction before scanning for --with-state-diff
+# A zero hash is enough: a debug-enabled node answers "not found", others "method not found"
+state_diff_supported() {
+    local probe_request
+    probe_request=$(jq -n '{
+        "jsonrpc": "2.0",
+        "method": "debug_traceTransaction",
+        "params": ["0x0000000000000000000000000000000000000000000000000000000000000000",
+                   {"tracer": "prestateTracer", "tracerConfig": {"diffMode": true}}],
+        "id": 1
+    }')
+
+    local probe_response
+    probe_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$probe_request" 30 || true)
+    ! is_method_unsupported "$probe_response"
+}
+
+# Augment one record with its state diff: the prestateTracer's pre and post account states
+# (balance, nonce, code and storage) for every account the transaction changed
+enrich_record_with_state_diff() {
+    local record="$1"
+
+    local tx_hash
+    tx_hash=$(echo "$record" | jq -r '.hash')
+
+    local trace_request
+    trace_request=$(jq -n \
+        --arg tx_hash "$tx_hash" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "debug_traceTransaction",
+            "params": [$tx_hash, {"tracer": "prestateTracer", "tracerConfig": {"diffMode": true}}],
+            "id": 1
+        }')
+
+    echo "1" >> "$RPC_COUNTER_DIR/state_diff.count"
+    local trace_response
+    trace_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$trace_request" 60 || true)
+
+    local state_diff
+    state_diff=$(echo "$trace_response" | jq -c '.result // empty | {pre: (.pre // {}), post: (.post // {})}' 2>/dev/null || true)
+    if [[ -z "$state_diff" ]] && is_state_unavailable "$trace_response"; then
+        report_unavailable_state "$record" "$trace_response"
+        if [[ "$SKIP_UNAVAILABLE_STATE" == "true" ]]; then
+            return 3
+        fi
+        state_diff="null"
+    elif [[ -z "$state_diff" ]]; then
+        log warn "Warning: No state diff for $tx_hash ($(format_rpc_error "$trace_response")), leaving state_diff empty"
+        state_diff="null"
+    fi
+
+    echo "$record" | jq -c --argjson state_diff "$state_diff" '. + {state_diff: $state_diff}'
+}
+
+# Check that the node serves debug_traceTransaction with the callTracer for --with-call-trace
+# (same zero-hash probe as state_diff_supported)
+call_trace_supported() {
+    local probe_request
+    probe_request=$(jq -n '{
+        "jsonrpc": "2.0",
+        "method": "debug_traceTransaction",
+        "params": ["0x0000000000000000000000000000000000000000000000000000000000000000", {"tracer": "callTracer"}],
+        "id": 1
+    }')
+
+    local probe_response
+    probe_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$probe_request" 30 || true)
+    ! is_method_unsupported "$probe_response"
+}
+
+# Augment one record with its call trace: the callTracer frame of the transaction, taken from the
+# block traces when --match-by traces already kept it, otherwise from debug_traceTransaction
+enrich_record_with_call_trace() {
+    local record="$1"
+
+    local tx_hash
+    tx_hash=$(echo "$record" | jq -r '.hash')
+
+    if [[ -f "$CALL_TRACES_DIR/$tx_hash.json" ]]; then
+        echo "$record" | jq -c --slurpfile call_trace "$CALL_TRACES_DIR/$tx_hash.json" '. + {call_trace: $call_trace[0]}'
+        return
+    fi
+
+    local trace_request
+    trace_request=$(jq -n \
+        --arg tx_hash "$tx_hash" \
+        '{
+            "jsonrpc": "2.0",
+            "method": "debug_traceTransaction",
+            "params": [$tx_hash, {"tracer": "callTracer"}],
+            "id": 1
+        }')
+
+    echo "1" >> "$RPC_COUNTER_DIR/call_trace.count"
+    local trace_response
+    trace_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$trace_request" 60 || true)
+
+    local call_trace
+    call_trace=$(echo "$trace_response" | jq -c '.result // empty | select(type == "object")' 2>/dev/null || true)
+    if [[ -z "$call_trace" ]] && is_state_unavailable "$trace_response"; then
+        report_unavailable_state "$record" "$trace_response"
+        if [[ "$SKIP_UNAVAILABLE_STATE" == "true" ]]; then
+            return 3
+        fi
+        call_trace="null"
+    elif [[ -z "$call_trace" ]]; then
+        log warn "Warning: No call trace for $tx_hash ($(format_rpc_error "$trace_response")), leaving call_trace empty"
+        call_trace="null"
+    fi
+
+    echo "$record" | jq -c --argjson call_trace "$call_trace" '. + {call_trace: $call_trace}'
+}
+
+# Give one record an access list: the EIP-2930 list the transaction declared, or else the addresses
+# and storage slots eth_createAccessList reports for replaying it on top of the parent block
+# (an approximation: earlier transactions of the same block are not applied first)
+enrich_record_with_access_list() {
+    local record="$1"
+
+    if echo "$record" | jq -e '(.access_list // []) | length > 0' > /dev/null; then
+        echo "$record" | jq -c '. + {access_list_source: "declared"}'
+        return
+    fi
+
+    local parent_hex
+    parent_hex=$(printf "0x%x" $(($(echo "$record" | jq -r '.block_number') - 1)))
+
+    local access_list_request
+    access_list_request=$(echo "$record" | jq -c --arg parent_hex "$parent_hex" '
+        def num_to_hex: if . < 16 then "0123456789abcdef"[.:. + 1]
+            else (. / 16 | floor | num_to_hex) + "0123456789abcdef"[. % 16:. % 16 + 1] end;
+        {
+        "jsonrpc": "2.0",
+        "method": "eth_createAccessList",
+        "params": [
+            ({from: .from, data: .data, value: .value}
+             + if (.gas // "") == "" then {} else {gas: ("0x" + (.gas | tonumber | num_to_hex))} end
+             + if .is_creation then {} else {to: .to} end),
+            $parent_hex
+        ],
+        "id": 1
+    }')
+
+    echo "1" >> "$RPC_COUNTER_DIR/access_list.count"
+    local access_list_response
+    access_list_response=$(retry_with_backoff "$MAX_RETRIES" rpc_post "$access_list_request" 30 || true)
+
+    local access_list
+    access_list=$(echo "$access_list_response" | jq -c '.result.accessList // empty' 2>/dev/null || true)
+    if [[ -z "$access_list" ]] && is_state_unavailable "$access_list_response"; then
+        report_unavailable_state "$record" "$access_list_response"
+        if [[ "$SKIP_UNAVAILABLE_STATE" == "true" ]]; then
+            return 3
+        fi
+        echo "$record" | jq -c '. + {access_list: null, access_list_source: null}'
+        return
+    fi
+    if [[ -z "$access_list" ]]; then
+        local tx_hash
+        tx_hash=$(echo "$record" | jq -r '.hash')
+        log warn "Warning: No access list for $tx_hash ($(format_rpc_error "$access_list_response")), leaving access_list empty"
+        echo "$record" | jq -c '. + {access_list: null, access_list_source: null}'
+        return
+    fi
+
+    echo "$record" | jq -c --argjson access_list "$access_list" '. + {access_list: $access_list, access_list_source: "derived"}'
+}
+
+# Check a record without a gas price against --min-gas-price/--max-gas-price on its receipt's
+# effective gas price; records with a gas price (checked during the scan) or without a receipt pass
+# Usage: effective_gas_price_in_range <record>
+effective_gas_price_in_range() {
+    local record="$1"
+    if [[ -z "$MIN_GAS_PRICE" && -z "$MAX_GAS_PRICE" ]]; then
+        return 0
+    fi
+
+    local effective_gas_price
+    effective_gas_price=$(echo "$record" | jq -r 'if .gas_price == null then .effective_gas_price // "" else "" end')
+    gas_price_in_range "$effective_gas_price"
+}
+
+# Whether any --with-* option needs per-transaction RPC calls after the scan
+enrichment_enabled() {
+    [[ "$WITH_RECEIPTS" == "true" || "$WITH_STATE_DIFF" == "true" ||
+       "$WITH_CALL_TRACE" == "true" || "$WITH_ACCESS_LIST" == "true" ]]
+}
+
+# Run the enabled enrichments of one record in turn: receipt (from <receipts_by_hash> when its
+# block's bulk fetch had it), the effective gas price bound, state diff, call trace and access list
+# Prints nothing for a record the gas price bound or --skip-unavailable-state drops, so it costs
+# no further calls
+# Usage: enrich_record <record> <receipts_by_hash>
+enrich_record() {
+    local record="$1"
+    local receipts_by_hash="$2"
+
+    if [[ "$WITH_RECEIPTS" == "true" ]]; then
+        local merged
+        merged=$(echo "$record" | jq -c --slurpfile receipts "$receipts_by_hash" "$JQ_HEX_TO_DEC$JQ_HEX_MUL$JQ_GAS_COST"'
+            $receipts[0][.hash | ascii_downcase] as $receipt
+            | if $receipt == null then
+                empty
+              else
+                . + {
+                    status: $receipt.status,
+                    gas_used: $receipt.gasUsed,
+                    effective_gas_price: $receipt.effectiveGasPrice,
+                    logs: $receipt.logs
+                }
+                | with_gas_cost
+              end')
+        if [[ -n "$merged" ]]; then
+            record="$merged"
+        else
+            record=$(enrich_record_with_receipt "$record")
+        fi
+        if ! effective_gas_price_in_range "$record"; then
+            return 0
+        fi
+    fi
+    if [[ "$WITH_STATE_DIFF" == "true" ]]; then
+        record=$(enrich_record_with_state_diff "$record") || return 0
+    fi
+    if [[ "$WITH_CALL_TRACE" == "true" ]]; then
+        record=$(enrich_record_with_call_trace "$record") || return 0
+    fi
+    if [[ "$WITH_ACCESS_LIST" == "true" ]]; then
+        record=$(enrich_record_with_access_list "$record") || return 0
+    fi
+    echo "$record"
+}
+
+# The enrichment stage: give every collected transaction its --with-receipts, --with-state-diff,
+# --with-call-trace and --with-access-list fields. After the bulk receipt fetch each record runs
+# all of its remaining calls as one job, up to max_concurrent jobs at a time, so the enrichments
+# share one concurrency budget instead of each waiting for the previous one to get through every
+# record. Records keep their order
+# Usage: enrich_records <transactions_file> <max_concurrent>
+enrich_records() {
+    local transactions_file="$1"
+    local max_concurrent="$2"
+
+    local enrichment_dir="$TEMP_DIR/enrichment"
+    rm -rf "$enrichment_dir"
+    mkdir -p "$enrichment_dir"
+
+    local records=()
+    mapfile -t records < "$transactions_file"
+
+    local stages=()
+    if [[ "$WITH_RECEIPTS" == "true" ]]; then
+        stages+=("receipts")
+    fi
+    if [[ "$WITH_STATE_DIFF" == "true" ]]; then
+        stages+=("state diffs")
+    fi
+    if [[ "$WITH_CALL_TRACE" == "true" ]]; then
+        stages+=("call traces")
+    fi
+    if [[ "$WITH_ACCESS_LIST" == "true" ]]; then
+        stages+=("access lists")
+    fi
+    log info "Enriching ${#records[@]} transactions with $(printf '%s, ' "${stages[@]}" | sed 's/, $//')"
+
+    local receipts_by_hash="$enrichment_dir/receipts_by_hash.json"
+    echo '{}' > "$receipts_by_hash"
+    if [[ "$WITH_RECEIPTS" == "true" ]]; then
+        fetch_receipts_by_hash "$transactions_file" "$max_concurrent" "$receipts_by_hash"
+    fi
+
+    local pids=()
+    local record_index=0
+    while [[ $record_index -lt ${#records[@]} ]]; do
+        while [[ ${#pids[@]} -lt $max_concurrent && $record_index -lt ${#records[@]} ]]; do
+            enrich_record "${records[$record_index]}" "$receipts_by_hash" > "$enrichment_dir/$record_index.json" &
+            pids+=($!)
+            record_index=$((record_index + 1))
+        done
+
+        if [[ ${#pids[@]} -gt 0 ]]; then
+            wait "${pids[0]}"
+            pids=("${pids[@]:1}")
+        fi
+    done
+
+    if [[ ${#pids[@]} -gt 0 ]]; then
+        wait "${pids[@]}"
+    fi
+
+    local enriched_file="$enrichment_dir/enriched.txt"
+    : > "$enriched_file"
+    for ((record_index = 0; record_index < ${#records[@]}; record_index++)); do
+        cat "$enrichment_dir/$record_index.json" >> "$enriched_file"
+    done
+    mv "$enriched_file" "$transactions_file"
+}
+
+# Read the functions of a contract ABI (a JSON array, or a Foundry artifact with an "abi" key)
+# into ABI_SELECTORS_FILE, keyed by their 4-byte selectors, for decode_transaction_inputs
+# Usage: abi_load <abi_file>
+abi_load() {
+    local abi_file="$1"
+
+    # Canonical signatures spell tuples out as (type,...) followed by any array suffix
+    local functions
+    if ! functions=$(jq -c '
+        def canonical: if .type | startswith("tuple")
+            then "(" + (.components | map(canonical) | join(",")) + ")" + (.type | ltrimstr("tuple"))
+            else .type end;
+        (if type == "object" then .abi else . end)
+        | if type == "array" then .[] else error("not an ABI") end
+        | select(.type == "function")
+        | {name: .name, inputs: (.inputs // []),
+           signature: (.name + "(" + ((.inputs // []) | map(canonical) | join(",")) + ")")}
+    ' "$abi_file" 2>/dev/null); then
+        log error "Error: --abi $abi_file is not a contract ABI (a JSON array or an artifact with an \"abi\" key)"
+        return 1
+    fi
+
+    if ! keccak256 "" > /dev/null; then
+        log error "Error: --abi needs keccak-256 from OpenSSL 3.2+ or cast (Foundry)"
+        return 1
+    fi
+
+    # The loop runs in a subshell of the pipeline, so its failure is read back from PIPESTATUS
+    ABI_SELECTORS_FILE="$TEMP_DIR/abi_selectors.json"
+    local function_json
+    local signature
+    local hash
+    while IFS= read -r function_json; do
+        [[ -z "$function_json" ]] && continue
+        signature=$(echo "$function_json" | jq -r '.signature')
+        if ! hash=$(keccak256 "$signature"); then
+            log error "Error: Failed to hash the --abi signature $signature"
+            exit 1
+        fi
+        echo "$function_json" | jq -c --arg selector "${hash:0:8}" '{key: $selector, value: .}'
+    done <<< "$functions" | jq -s -c 'from_entries' > "$ABI_SELECTORS_FILE"
+    if [[ ${PIPESTATUS[0]} -ne 0 ]]; then
+        return 1
+    fi
+
+    log info "Loaded $(jq 'length' "$ABI_SELECTORS_FILE") functions from --abi $abi_file"
+}
+
+# jq definitions for arbitrary-size hex (no 0x prefix) to a decimal string, through base-10^7
+# limbs (least significant first), so 256-bit quantities don't lose precision as jq numbers
+JQ_HEX_TO_DEC='
+    def nibbles: ascii_downcase | explode | map(if . >= 97 then . - 87 else . - 48 end);
+    # Numbers past 2^53 are kept exact as little-endian base-10^7 limbs
+    def hex_to_limbs:
+        reduce (nibbles[]) as $nibble ([0];
+            reduce range(0; length) as $i ({limbs: ., carry: $nibble};
+                (.limbs[$i] * 16 + .carry) as $v
+                | .limbs[$i] = $v % 10000000
+                | .carry = ($v - $v % 10000000) / 10000000)
+            | .limbs + (if .carry > 0 then [.carry] else [] end));
+    def limbs_add($other):
+        . as $limbs
+        | reduce range(0; [length, ($other | length)] | max) as $i ({limbs: [], carry: 0};
+            (($limbs[$i] // 0) + ($other[$i] // 0) + .carry) as $v
+            | .limbs += [$v % 10000000]
+            | .carry = ($v - $v % 10000000) / 10000000)
+        | .limbs + (if .carry > 0 then [.carry] else [] end);
+    def limbs_to_dec:
+        reverse
+        | (.[0] | tostring) + (.[1:] | map(tostring | ("0" * (7 - length)) + .) | join(""));
+    def hex_to_dec: hex_to_limbs | limbs_to_dec;
+'
+
+# jq definition of hex_mul, the exact product of two hex quantities as a hex quantity; needs
+# JQ_HEX_TO_DEC. Little-endian words of six hex digits keep every partial product below 2^53
+JQ_HEX_MUL='
+    def hex_words:
+        ltrimstr("0x") | nibbles | reverse
+        | [range(0; length; 6) as $i | .[$i:$i + 6] | reverse | reduce .[] as $nibble (0; . * 16 + $nibble)];
+    def word_hex: if . == 0 then "" else (. / 16 | floor | word_hex) + "0123456789abcdef"[. % 16:. % 16 + 1] end;
+    def words_carry:
+        reduce range(0; length) as $i ({words: ., carry: 0};
+            (.words[$i] + .carry) as $v
+            | .words[$i] = $v % 16777216
+            | .carry = ($v - $v % 16777216) / 16777216)
+        | .words;
+    def hex_mul($a; $b):
+        ($a | hex_words) as $x
+        | ($b | hex_words) as $y
+        | reduce range(0; $x | length) as $i ([range(0; ($x | length) + ($y | length)) | 0];
+            reduce range(0; $y | length) as $j (.; .[$i + $j] += $x[$i] * $y[$j]) | words_carry)
+        | reverse | until(length <= 1 or .[0] != 0; .[1:])
+        | "0x" + ((.[0] // 0 | word_hex | if . == "" then "0" else . end)
+                  + (.[1:] | map(word_hex | ("0" * (6 - length)) + .) | join("")));
+'
+
+# jq definition of with_gas_cost, which sets a record's gas_cost_wei from its receipt fields:
+# gas_used times the effective gas price (the gas price for receipts without one), as a hex
+# quantity; null when either is missing. Needs JQ_HEX_TO_DEC and JQ_HEX_MUL
+JQ_GAS_COST='
+    def with_gas_cost:
+        (.effective_gas_price // .gas_price) as $price
+        | .gas_cost_wei = (if .gas_used != null and $price != null then hex_mul(.gas_used; $price) else null end);
+'
+
+# Decode each record's calldata against the --abi functions into decoded_function:
+# {name, signature, args: [{name, type, value}]}. Integers become decimal strings, bytes and
+# addresses 0x hex, tuples objects (arrays when their components are unnamed). The field is null
+# when the selector isn't in the ABI (fallback/receive, other contracts) or the calldata doesn't decode.
+decode_transaction_inputs() {
+    local transactions_file="$1"
+
+    jq -c --slurpfile selectors "$ABI_SELECTORS_FILE" "$JQ_HEX_TO_DEC"'
+        def dec_increment:
+            explode | reverse
+            | reduce .[] as $digit ({out: [], carry: 1};
+                ($digit - 48 + .carry) as $v | .out += [$v % 10 + 48] | .carry = (if $v >= 10 then 1 else 0 end))
+            | (.out + (if .carry == 1 then [49] else [] end)) | reverse | implode;
+        # Two-complement words: negative values are -(~x + 1)
+        def signed_dec:
+            if (nibbles[0]) >= 8
+            then "-" + (nibbles | map("0123456789abcdef"[15 - .:16 - .]) | join("") | hex_to_dec | dec_increment)
+            else hex_to_dec end;
+        def utf8_decode:
+            reduce .[] as $byte ({codepoints: [], current: 0, pending: 0};
+                if .pending > 0 then
+                    .current = .current * 64 + $byte % 64 | .pending -= 1
+                    | if .pending == 0 then .codepoints += [.current] else . end
+                elif $byte < 128 then .codepoints += [$byte]
+                elif $byte >= 240 then .current = $byte % 8 | .pending = 3
+                elif $byte >= 224 then .current = $byte % 16 | .pending = 2
+                else .current = $byte % 32 | .pending = 1 end)
+            | .codepoints | implode;
+
+        def element: .type |= sub("\\[[0-9]*\\]$"; "");
+        def is_dynamic:
+            if .type == "string" or .type == "bytes" or (.type | test("\\[\\]$")) then true
+            elif .type | test("\\[[0-9]+\\]$") then element | is_dynamic
+            elif .type | startswith("tuple") then any(.components[]; is_dynamic)
+            else false end;
+        def head_size:
+            if is_dynamic then 32
+            elif .type | test("\\[[0-9]+\\]$") then
+                (.type | capture("\\[(?<k>[0-9]+)\\]$").k | tonumber) * (element | head_size)
+            elif .type | startswith("tuple") then [.components[] | head_size] | add
+            else 32 end;
+
+        def decode_params($data; $params; $base):
+            def word($at): $data[$at * 2:$at * 2 + 64]
+                | if length == 64 then . else error("calldata too short") end;
+            # Offsets and lengths must fit comfortably in a double
+            def small($at): word($at)
+                | if .[0:52] | test("[^0]") then error("offset out of range") else .[52:] | hex_to_dec | tonumber end;
+            def decode_value($at):
+                .type as $type
+                | if $type | test("\\[\\]$") then
+                    small($at) as $length
+                    | if $length * 32 > ($data | length) / 2 then error("array too long") else . end
+                    | decode_params($data; [range(0; $length) as $_ | element]; $at + 32)
+                  elif $type | test("\\[[0-9]+\\]$") then
+                    ($type | capture("\\[(?<k>[0-9]+)\\]$").k | tonumber) as $k
+                    | decode_params($data; [range(0; $k) as $_ | element]; $at)
+                  elif $type | startswith("tuple") then
+                    decode_params($data; .components; $at) as $values
+                    | if all(.components[]; (.name // "") != "")
+                      then [.components, $values] | transpose | map({key: .[0].name, value: .[1]}) | from_entries
+                      else $values end
+                  elif $type == "string" or $type == "bytes" then
+                    small($at) as $length
+                    | $data[($at + 32) * 2:($at + 32 + $length) * 2] as $hex
+                    | if ($hex | length) != $length * 2 then error("calldata too short") else . end
+                    | if $type == "string"
+                      then [range(0; $length) as $i | $hex[$i * 2:$i * 2 + 2] | hex_to_dec | tonumber] | utf8_decode
+                      else "0x" + $hex end
+                  elif $type == "address" then "0x" + word($at)[24:]
+                  elif $type == "bool" then word($at) | test("[^0]")
+                  elif $type | test("^bytes[0-9]+$") then "0x" + word($at)[0:($type | ltrimstr("bytes") | tonumber) * 2]
+                  elif $type | test("^uint[0-9]*$") then word($at) | hex_to_dec
+                  elif $type | test("^int[0-9]*$") then word($at) | signed_dec
+                  else error("unsupported type \($type)") end;
+            reduce $params[] as $param ({at: $base, values: []};
+                .at as $head
+                | if $param | is_dynamic then
+                    ($base + small($head)) as $tail
+                    | .values += [$param | decode_value($tail)] | .at += 32
+                  else
+                    .values += [$param | decode_value($head)] | .at += ($param | head_size)
+                  end)
+            | .values;
+
+        (.data // "" | ltrimstr("0x") | ascii_downcase) as $input
+        | $selectors[0][$input[0:8]] as $function
+        | . + {decoded_function: (
+            if ($input | length) < 8 or $function == null then null
+            else try (decode_params($input[8:]; $function.inputs; 0) as $values
+                      | {name: $function.name, signature: $function.signature,
+                         args: ([$function.inputs, $values] | transpose
+                                | map({name: (.[0].name // ""), type: .[0].type, value: .[1]}))})
+                 catch null
+            end)}
+    ' "$transactions_file" > "$transactions_file.decoded"
+    mv "$transactions_file.decoded" "$transactions_file"
+}
+
+# Print the records of a file that --sample-rate keeps
+# A record is kept when the first 32 bits of its hash, read as a fraction, fall below the rate,
+# so the same transactions are picked on every run and the sample needs no seed
+# Usage: sample_records <transactions_file>
+sample_records() {
+    local transactions_file="$1"
+    if [[ "$SAMPLE_RATE" == "1" ]]; then
+        cat "$transactions_file"
+        return
+    fi
+    jq -c --argjson rate "$SAMPLE_RATE" '
+        select((.hash[2:10] | ascii_downcase | explode
+            | reduce .[] as $digit (0; . * 16 + (if $digit >= 97 then $digit - 87 else $digit - 48 end)))
+            < $rate * 4294967296)' "$transactions_file"
+}
+
+# Succeeds once --max-results matches are kept, counting the finished batches plus what the
+# running batch has written so far
+# Usage: max_results_reached <batch_output>
+max_results_reached() {
+    local batch_output="$1"
+    if [[ $MAX_RESULTS -eq 0 ]]; then
+        return 1
+    fi
+    local in_batch
+    in_batch=$(sample_records "$batch_output" 2>/dev/null | wc -l)
+    [[ $((RESULTS_COLLECTED + in_batch)) -ge $MAX_RESULTS ]]
+}
+
+# Kill a background job and everything it started (curl, retry sleeps)
+# Usage: kill_job_tree <pid>
+kill_job_tree() {
+    local pid="$1"
+
+    # Collect the descendants first: once the job is gone they are reparented and can't be found
+    local descendants=()
+    local pending=("$pid")
+    while [[ ${#pending[@]} -gt 0 ]]; do
+        local children=()
+        mapfile -t children < <(pgrep -P "${pending[0]}" 2>/dev/null || true)
+        descendants+=("${children[@]}")
+        pending=("${pending[@]:1}" "${children[@]}")
+    done
+
+    kill "$pid" "${descendants[@]}" 2>/dev/null || true
+    wait "$pid" 2>/dev/null || true
+}
+
+# Wait for a background job, giving up at a deadline (epoch ms; empty waits indefinitely)
+# Usage: wait_for_job <pid> <deadline_ms>
+wait_for_job() {
+    local pid="$1"
+    local deadline_ms="$2"
+
+    if [[ -z "$deadline_ms" ]]; then
+        wait "$pid" || true
+        return 0
+    fi
+
+    local round=0
+    while kill -0 "$pid" 2>/dev/null; do
+        if [[ $(now_ms) -ge $deadline_ms ]]; then
+            return 1
+        fi
+        poll_sleep "$round"
+        round=$((round + 1))
+    done
+    wait "$pid" 2>/dev/null || true
+}
+
+# Fetch blocks first_block..end_block in the background with CONCURRENCY requests in flight
+# throughout, instead of draining at every batch boundary; collect_pooled_batch takes the
+# results batch by batch. Each block leaves its records in <block>.records and a <block>.done
+# marker in BLOCK_POOL_DIR. The pool reads its concurrency and lookahead (the highest block it
+# may start) from files there, so the scan can adjust them between batches, and the last block
+# the scan collected, so no more than MAX_PENDING_BLOCKS results pile up ahead of it
+# Usage: block_pool_start <first_block> <end_block>
+block_pool_start() {
+    local first_block="$1"
+    local end_block="$2"
+
+    BLOCK_POOL_DIR="$TEMP_DIR/block_pool"
+    rm -rf "$BLOCK_POOL_DIR"
+    mkdir -p "$BLOCK_POOL_DIR"
+    echo "$CONCURRENCY" > "$BLOCK_POOL_DIR/concurrency"
+    echo $((first_block + 2 * BATCH_SIZE - 1)) > "$BLOCK_POOL_DIR/window"
+    echo $((first_block - 1)) > "$BLOCK_POOL_DIR/collected"
+
+    block_pool_run "$first_block" "$end_block" "$TARGET_CONTRACTS" > /dev/null &
+    BLOCK_POOL_PID=$!
+}
+
+# The pool itself (see block_pool_start); waits for whichever block finishes first
+# Usage: block_pool_run <first_block> <end_block> <target_contracts>
+block_pool_run() {
+    local block="$1"
+    local end_block="$2"
+    local target_contracts="$3"
+    local running=0
+    local idle_rounds=0
+
+    # Ctrl-C is for the scan to handle (see fetch_transactions); the pool is stopped by the scan
+    trap '' INT
+
+    while [[ $block -le $end_block || $running -gt 0 ]]; do
+        local limit
+        local window
+        local collected
+        limit=$(cat "$BLOCK_POOL_DIR/concurrency")
+        window=$(cat "$BLOCK_POOL_DIR/window")
+        collected=$(cat "$BLOCK_POOL_DIR/collected")
+        while [[ $running -lt $limit && $block -le $end_block && $block -le $window &&
+                 $((block - collected)) -le $MAX_PENDING_BLOCKS ]]; do
+            # Blocks the scan gave up on (--batch-timeout) are not started any more
+            if [[ ! -e "$BLOCK_POOL_DIR/$block.abandoned" ]]; then
+                {
+                    fetch_block_transactions "$block" "$target_contracts" "$BLOCK_POOL_DIR/$block.records" "$RPC_COUNTER_DIR" ||
+                        record_failed_blocks "$block" "$block"
+                    touch "$BLOCK_POOL_DIR/$block.done"
+                } &
+                echo "$!" > "$BLOCK_POOL_DIR/$block.pid"
+                running=$((running + 1))
+            fi
+            block=$((block + 1))
+        done
+
+        if [[ $running -gt 0 ]]; then
+            wait -n || true
+            running=$(jobs -pr | wc -l)
+            idle_rounds=0
+        elif [[ $block -le $end_block ]]; then
+            # Everything allowed so far is done; wait for the scan to move the window or collect
+            poll_sleep "$idle_rounds"
+            idle_rounds=$((idle_rounds + 1))
+        fi
+    done
+}
+
+# Stop the block pool (after the scan, or when it ends early)
+block_pool_stop() {
+    if [[ -n "$BLOCK_POOL_PID" ]]; then
+        kill_job_tree "$BLOCK_POOL_PID"
+        BLOCK_POOL_PID=""
+    fi
+    BLOCK_POOL_DIR=""
+}
+
+# Take one batch's blocks from the block pool into batch_<batch_id>.txt, in block order, once
+# each has finished; prints the batch's transaction count like process_batch. With
+# --batch-timeout, blocks still unfinished at the deadline are abandoned and fail
+# Usage: collect_pooled_batch <start_block> <end_block> <batch_id>
+collect_pooled_batch() {
+    local start_block="$1"
+    local end_block="$2"
+    local batch_id="$3"
+
+    local batch_output="$TEMP_DIR/batch_$batch_id.txt"
+    touch "$batch_output"
+
+    log info "Processing batch $batch_id: blocks $start_block to $end_block" \
+        "batch=$batch_id" "start_block=$start_block" "end_block=$end_block"
+
+    # Let the pool run one batch ahead, at the concurrency chosen for this batch
+    echo "$CONCURRENCY" > "$BLOCK_POOL_DIR/concurrency"
+    echo $((end_block + BATCH_SIZE)) > "$BLOCK_POOL_DIR/window"
+
+    local deadline_ms=""
+    if [[ $BATCH_TIMEOUT -gt 0 ]]; then
+        deadline_ms=$(($(now_ms) + BATCH_TIMEOUT * 1000))
+    fi
+
+    local abandoned=0
+    local block
+    for ((block=start_block; block<=end_block; block++)); do
+        local round=0
+        while [[ ! -e "$BLOCK_POOL_DIR/$block.done" ]]; do
+            if [[ -n "$deadline_ms" && $(now_ms) -ge $deadline_ms ]]; then
+                touch "$BLOCK_POOL_DIR/$block.abandoned"
+                if [[ -s "$BLOCK_POOL_DIR/$block.pid" ]]; then
+                    kill_job_tree "$(cat "$BLOCK_POOL_DIR/$block.pid")"
+                fi
+                record_failed_blocks "$block" "$block"
+                abandoned=$((abandoned + 1))
+                break
+            fi
+            poll_sleep "$round"
+            round=$((round + 1))
+        done
+        if [[ -e "$BLOCK_POOL_DIR/$block.done" && -f "$BLOCK_POOL_DIR/$block.records" ]]; then
+            cat "$BLOCK_POOL_DIR/$block.records" >> "$batch_output"
+        fi
+        rm -f "$BLOCK_POOL_DIR/$block".*
+        echo "$block" > "$BLOCK_POOL_DIR/collected"
+    done
+
+    if [[ $abandoned -gt 0 ]]; then
+        log warn "[TIMEOUT] Batch $batch_id exceeded --batch-timeout ${BATCH_TIMEOUT}s; marking $abandoned unfinished blocks as failed"
+    fi
+
+    local tx_count
+    tx_count=$(wc -l < "$batch_output" | tr -d ' ')
+    if [[ $tx_count -gt 0 ]]; then
+        log info "  Batch $batch_id: found $tx_count transactions" "batch=$batch_id" "transactions=$tx_count"
+    fi
+    echo "$tx_count"
+}
+
+# Process a batch of blocks
+process_batch() {
+    local start_block="$1"
+    local end_block="$2"
+    local target_contracts="$3"
+    local batch_id="$4"
+    local max_concurrent="$5"
+
+    local batch_output="$TEMP_DIR/batch_$batch_id.txt"
+    touch "$batch_output"
+
+    # Create array of block numbers in this batch
+    local blocks=()
+    for ((block=start_block; block<=end_block; block++)); do
+        blocks+=("$block")
+    done
+
+    log info "Processing batch $batch_id: blocks $start_block to $end_block" \
+        "batch=$batch_id" "start_block=$start_block" "end_block=$end_block"
+
+    # Process blocks with concurrency limit; pid_blocks holds the block each job in pids fetches
+    local pids=()
+    local pid_blocks=()
+    local block_index=0
+
+    # With --batch-timeout, blocks still running at the deadline are abandoned
+    local deadline_ms=""
+    if [[ $BATCH_TIMEOUT -gt 0 ]]; then
+        deadline_ms=$(($(now_ms) + BATCH_TIMEOUT * 1000))
+    fi
+    local timed_out=false
+
+    # In jsonrpc-batch mode the whole batch is fetched with a single request
+    if [[ "$BATCH_MODE" == "jsonrpc-batch" ]]; then
+        fetch_blocks_batched "$start_block" "$end_block" "$target_contracts" "$batch_output" &
+        local batch_pid=$!
+        if ! wait_for_job "$batch_pid" "$deadline_ms"; then
+            kill_job_tree "$batch_pid"
+            timed_out=true
+            # The request covers every block, so none of its partial output is kept
+            : > "$batch_output"
+            record_failed_blocks "$start_block" "$end_block"
+        fi
+        block_index=${#blocks[@]}
+    fi
+
+    while [[ $block_index -lt ${#blocks[@]} || ${#pids[@]} -gt 0 ]]; do
+        # Start new jobs up to the concurrency limit; with --max-results none are started once
+        # enough matches are in (blocks start in order, so the ones fetched stay a prefix)
+        while [[ ${#pids[@]} -lt $max_concurrent && $block_index -lt ${#blocks[@]} ]]; do
+            if max_results_reached "$batch_output"; then
+                block_index=${#blocks[@]}
+                break
+            fi
+            local block_num="${blocks[$block_index]}"
+            {
+                fetch_block_transactions "$block_num" "$target_contracts" "$batch_output" "$RPC_COUNTER_DIR" ||
+                    record_failed_blocks "$block_num" "$block_num"
+            } &
+            pids+=($!)
+            pid_blocks+=("$block_num")
+            ((block_index++))
+        done
+
+        # Wait for at least one job to complete
+        if [[ ${#pids[@]} -gt 0 ]]; then
+            if ! wait_for_job "${pids[0]}" "$deadline_ms"; then
+                timed_out=true
+                break
+            fi
+            pids=("${pids[@]:1}")  # Remove first element
+            pid_blocks=("${pid_blocks[@]:1}")
+        fi
+    done
+
+    # A block only reaches the batch output once it is complete, so dropping the unfinished
+    # ones leaves the finished ones intact; the unstarted ones fail along with them
+    if [[ "$timed_out" == "true" && "$BATCH_MODE" != "jsonrpc-batch" ]]; then
+        local unfinished=()
+        local i
+        for i in "${!pids[@]}"; do
+            if kill -0 "${pids[$i]}" 2>/dev/null; then
+                kill_job_tree "${pids[$i]}"
+                unfinished+=("${pid_blocks[$i]}")
+            else
+                wait "${pids[$i]}" 2>/dev/null || true
+            fi
+        done
+        local block_num
+        for block_num in "${unfinished[@]}" "${blocks[@]:$block_index}"; do
+            record_failed_blocks "$block_num" "$block_num"
+        done
+        log warn "[TIMEOUT] Batch $batch_id exceeded --batch-timeout ${BATCH_TIMEOUT}s; marking $((${#unfinished[@]} + ${#blocks[@]} - block_index)) unfinished blocks as failed"
+    elif [[ "$timed_out" == "true" ]]; then
+        log warn "[TIMEOUT] Batch $batch_id exceeded --batch-timeout ${BATCH_TIMEOUT}s; marking its ${#blocks[@]} blocks as failed"
+    fi
+
+    # Count transactions found in this batch
+    local tx_count=0
+    if [[ -f "$batch_output" ]]; then
+        tx_count=$(wc -l < "$batch_output" 2>/dev/null || echo "0")
+    fi
+
+    if [[ $tx_count -gt 0 ]]; then
+        log info "  Batch $batch_id: found $tx_count transactions" "batch=$batch_id" "transactions=$tx_count"
+    fi
+
+    # Always return a number (default to 0 if empty)
+    echo "${tx_count:-0}"
+}
+
+# Drop records whose transaction was already collected, keeping the first occurrence, and remember
+# the hashes of the rest. A transaction must appear once even when it is matched twice, e.g. a node
+# listing it twice in a block or serving it again in a later block, or a future matcher overlapping
+# with the to/logs matches.
+# Usage: drop_duplicate_records <transactions_file>
+drop_duplicate_records() {
+    local transactions_file="$1"
+
+    # Compact JSON never holds a raw tab, so "hash<TAB>record" splits cleanly
+    jq -r '.hash | ascii_downcase' "$transactions_file" | paste - "$transactions_file" |
+        awk -F '\t' -v seen_file="$SEEN_HASHES_FILE" '
+            BEGIN { while ((getline hash < seen_file) > 0) seen[hash] = 1 }
+            !seen[$1]++ { print $2 }' > "$transactions_file.unique"
+
+    local dropped=$(($(wc -l < "$transactions_file") - $(wc -l < "$transactions_file.unique")))
+    if [[ $dropped -gt 0 ]]; then
+        log debug "[DEDUP] Dropped $dropped duplicate transaction(s)"
+    fi
+    mv "$transactions_file.unique" "$transactions_file"
+    jq -r '.hash | ascii_downcase' "$transactions_file" >> "$SEEN_HASHES_FILE"
+}
+
+# Sort a file of transaction records in place, numerically by (block_number, transaction_index);
+# pending records (no transaction_index) come after the rest of their block
+sort_transactions_file() {
+    local transactions_file="$1"
+
+    jq -s -c 'sort_by(.block_number, .transaction_index == null, .transaction_index) | .[]' \
+        "$transactions_file" > "$transactions_file.sorted"
+    mv "$transactions_file.sorted" "$transactions_file"
+}
+
+# jq definition of order_key: a record's (block_number, transaction_index) as two 8-byte
+# big-endian words in one hex string, so sorting the keys as strings gives the replay order
+# (null for a pending record, which has no place in it)
+JQ_ORDER_KEY='
+    def order_key:
+        def num_to_hex: if . < 16 then "0123456789abcdef"[.:. + 1]
+            else (. / 16 | floor | num_to_hex) + "0123456789abcdef"[. % 16:. % 16 + 1] end;
+        if .transaction_index == null then null
+        else "0x" + ([.block_number, .transaction_index]
+            | map(tonumber | num_to_hex | ("0" * (16 - length)) + .) | join("")) end;'
+
+# Version header of the simple format, checked by BacktestingUtils.parseMultipleTransactions
+SIMPLE_PAYLOAD_HEADER="CSTDv1:"
+
+# Record fields that --numeric-format applies to (gas_limit is the block's, in --headers-only
+# records); nonce, gas, block_timestamp and base_fee_per_gas come out of the fetcher as decimal
+# already and stay that way
+NUMERIC_FIELDS='["value","gas_price","gas_limit","max_fee_per_gas","max_priority_fee_per_gas","gas_used","effective_gas_price","gas_cost_wei"]'
+
+# jq definition of apply_numeric_format, which rewrites the NUMERIC_FIELDS of a record for
+# --numeric-format (a no-op for hex); prepend it to a jq program that encodes records
+numeric_format_defs() {
+    if [[ "$NUMERIC_FORMAT" != "decimal" ]]; then
+        echo 'def apply_numeric_format: .;'
+        return
+    fi
+    echo "$JQ_HEX_TO_DEC"'
+        def apply_numeric_format:
+            reduce ('"$NUMERIC_FIELDS"'[]) as $field (.;
+                if (.[$field] | type) == "string" and (.[$field] | startswith("0x"))
+                then .[$field] |= (ltrimstr("0x") | if . == "" then "0" else hex_to_dec end)
+                else . end);'
+}
+
+# Zero in the NUMERIC_FORMAT, for fee fields a record doesn't have
+numeric_zero() {
+    if [[ "$NUMERIC_FORMAT" == "decimal" ]]; then
+        echo "0"
+    else
+        echo "0x0"
+    fi
+}
+
+# Header row of the csv output format; --omit-data puts data_len in place of data
+csv_header() {
+    if [[ "$OMIT_DATA" == "true" ]]; then
+        echo "hash,from,to,value,data_len,block_number,transaction_index,gas_price"
+    else
+        echo "hash,from,to,value,data,block_number,transaction_index,gas_price"
+    fi
+}
+
+# Encode the records of a file as json array elements, ndjson lines or csv rows
+# (json and ndjson carry block_number and transaction_index as decimal strings plus their
+# order_key; quantities follow NUMERIC_FORMAT; --omit-data drops data)
+encode_transaction_rows() {
+    local output_format="$1"
+    local transactions_file="$2"
+    local defs
+    defs=$(numeric_format_defs)"$JQ_ORDER_KEY"'
+        def apply_omit_data: if $omit_data then del(.data) else . end;'
+
+    # --hashes-only: a json array of the hashes, or one bare hash per line
+    if [[ "$HASHES_ONLY" == "true" ]]; then
+        if [[ "$output_format" == "json" ]]; then
+            jq -r -s 'map(.hash | tojson) | join(",\n")' "$transactions_file"
+        else
+            jq -r '.hash' "$transactions_file"
+        fi
+        return
+    fi
+
+    case "$output_format" in
+        "json")
+            jq -r -s --argjson omit_data "$OMIT_DATA" "$defs"'
+                map(apply_numeric_format | apply_omit_data | .order_key = order_key
+                    | .block_number |= tostring | .transaction_index |= (if . == null then . else tostring end)
+                    | tojson)
+                | join(",\n")' "$transactions_file"
+            ;;
+        "ndjson")
+            jq -c --argjson omit_data "$OMIT_DATA" "$defs"'
+                apply_numeric_format | apply_omit_data | .order_key = order_key
+                | .block_number |= tostring | .transaction_index |= (if . == null then . else tostring end)' \
+                "$transactions_file"
+            ;;
+        "csv")
+            # One row per transaction; @csv quotes every string field (notably data)
+            jq -r --argjson omit_data "$OMIT_DATA" "$defs"'apply_numeric_format
+                | [.hash, .from, .to, .value, (if $omit_data then .data_len else .data end),
+                   .block_number, .transaction_index, .gas_price] | @csv' \
+                "$transactions_file"
+            ;;
+    esac
+}
+
+# Format --headers-only records as json, ndjson or csv; block numbers are strings as in the
+# transaction output and --numeric-format applies to gas used and limit
+# Usage: format_block_headers <output_format> <headers_file>
+format_block_headers() {
+    local output_format="$1"
+    local headers_file="$2"
+    local defs
+    defs=$(numeric_format_defs)
+
+    case "$output_format" in
+        "json")
+            echo "["
+            jq -r -s "$defs"'map(apply_numeric_format | .block_number |= tostring | tojson) | join(",\n")' \
+                "$headers_file"
+            echo "]"
+            ;;
+        "ndjson")
+            jq -c "$defs"'apply_numeric_format | .block_number |= tostring' "$headers_file"
+            ;;
+        "csv")
+            echo "block_number,hash,block_timestamp,base_fee_per_gas,gas_used,gas_limit"
+            jq -r "$defs"'apply_numeric_format
+                | [.block_number, .hash, .block_timestamp, .base_fee_per_gas, .gas_used, .gas_limit] | @csv' \
+                "$headers_file"
+            ;;
+    esac
+}
+
+# Tabulate records by the 4-byte selector of their calldata (null when there is less calldata,
+# as for plain transfers): count and total value in decimal wei, most frequent first, with the
+# function signature when --abi knows the selector
+# Usage: selector_breakdown <transactions_file>   (prints a JSON array)
+selector_breakdown() {
+    local transactions_file="$1"
+
+    jq -s -c --slurpfile functions "${ABI_SELECTORS_FILE:-/dev/null}" "$JQ_HEX_TO_DEC"'
+        def selector: (.data // "") | if length >= 10 then .[0:10] | ascii_downcase else null end;
+        ($functions | length > 0) as $abi
+        | group_by(selector)
+        | map({selector: (.[0] | selector),
+               count: length,
+               total_value: (reduce .[] as $tx ([0];
+                   limbs_add($tx.value // "0x0" | ltrimstr("0x") | hex_to_limbs)) | limbs_to_dec)}
+            | . + if $abi then {signature: ($functions[0][.selector // "" | ltrimstr("0x")].signature // null)} else {} end)
+        | sort_by([-.count, .selector])' "$transactions_file"
+}
+
+# Print a selector_breakdown as a table on stderr (--selector-stats)
+# Usage: report_selector_breakdown <breakdown_json>
+report_selector_breakdown() {
+    local breakdown="$1"
+
+    if ! log_enabled info; then
+        return 0
+    fi
+    {
+        echo ""
+        echo "=== SELECTORS ==="
+        if [[ $(echo "$breakdown" | jq 'length') -eq 0 ]]; then
+            echo "  (no matches)"
+        fi
+        echo "$breakdown" | jq -r '.[] | [(.selector // "(none)"), .count, .total_value, (.signature // "")] | @tsv' |
+            awk -F'\t' '{ printf "  %-10s %6d txs  %s wei%s\n", $1, $2, $3, ($4 == "" ? "" : "  " $4) }'
+    } | log_lines info
+}
+
+# Aggregate a run's matches: how many succeeded, were pending or matched but reverted (the
+# status check drops those, recording their hashes in RPC_COUNTER_DIR/reverted.hashes), the value
+# they moved in decimal wei and ether, and with --with-receipts the spread of their gas used.
+# The records are streamed through a projection without their calldata; only the gas used
+# values are held, for the median
+# Usage: transaction_aggregates <transactions_file> <reverted_count|null>   (prints a JSON object)
+transaction_aggregates() {
+    local transactions_file="$1"
+    local reverted="$2"
+
+    jq -c '[(.value // "0x0"), .gas_used, (.pending == true)]' "$transactions_file" |
+        jq -n -c --argjson reverted "$reverted" "$JQ_HEX_TO_DEC"'
+        def wei_to_eth: ("0" * (19 - length)) + . | .[:-18] + "." + .[-18:] | sub("0+$"; "") | sub("\\.$"; "");
+        def bucket: if . < 50000 then "<50k" elif . < 100000 then "50k-100k" elif . < 250000 then "100k-250k"
+            elif . < 500000 then "250k-500k" elif . < 1000000 then "500k-1m" else "1m+" end;
+        reduce inputs as [$value, $gas_used, $pending] ({value: [0], gas: [], successful: 0, pending: 0};
+            .value |= limbs_add($value | ltrimstr("0x") | hex_to_limbs)
+            | if $pending then .pending += 1 else .successful += 1 end
+            | if $gas_used == null then . else .gas += [$gas_used | ltrimstr("0x") | hex_to_dec | tonumber] end)
+        | . as $totals
+        | (.value | limbs_to_dec) as $value
+        | (.gas | sort) as $gas
+        | ($gas | length) as $count
+        | {
+            successful: $totals.successful,
+            pending: $totals.pending,
+            reverted: $reverted,
+            total_value_wei: $value,
+            total_value_eth: ($value | wei_to_eth),
+            gas_used: (if $count == 0 then null else {
+                min: $gas[0],
+                median: (if $count % 2 == 1 then $gas[($count - 1) / 2] else ($gas[$count / 2 - 1] + $gas[$count / 2]) / 2 end),
+                max: $gas[-1],
+                histogram: ([$gas[] | bucket] as $buckets
+                    | ["<50k", "50k-100k", "100k-250k", "250k-500k", "500k-1m", "1m+"]
+                    | map(. as $bucket | {gas_used: $bucket, count: ($buckets | map(select(. == $bucket)) | length)}))
+            } end)
+        }'
+}
+
+# Print transaction_aggregates as a summary on stderr
+# Usage: report_transaction_aggregates <aggregates_json>
+report_transaction_aggregates() {
+    local aggregates="$1"
+
+    if ! log_enabled info; then
+        return 0
+    fi
+    {
+        echo ""
+        echo "=== MATCH TOTALS ==="
+        echo "$aggregates" | jq -r '
+            "Successful: \(.successful)"
+                + (if .reverted == null then "" else ", reverted (dropped): \(.reverted)" end)
+                + (if .pending > 0 then ", pending: \(.pending)" else "" end),
+            "Value moved: \(.total_value_eth) ETH (\(.total_value_wei) wei)",
+            (.gas_used // empty
+                | "Gas used: min \(.min), median \(.median), max \(.max)",
+                  (.histogram[] | select(.count > 0) | "  \(.gas_used | . + " " * (10 - length))\(.count) txs"))'
+    } | log_lines info
+}
+
+# Blocks of the range that the output has nothing for: the failed ones and every block after the
+# last one scanned (after an interrupt or --max-results), one number per line in order
+# Usage: range_gaps <start_block> <end_block> <last_scanned_block>
+range_gaps() {
+    local start_block="$1"
+    local end_block="$2"
+    local last_scanned="$3"
+
+    if [[ $last_scanned -lt $((start_block - 1)) ]]; then
+        last_scanned=$((start_block - 1))
+    fi
+    {
+        awk -v start="$start_block" -v end="$end_block" '$1 >= start && $1 <= end' "$FAILED_BLOCKS_FILE"
+        if [[ -n "$BLOCK_LIST_FILE" ]]; then
+            awk -v after="$last_scanned" -v end="$end_block" '$1 > after && $1 <= end' "$BLOCK_LIST_FILE"
+        else
+            awk -v after="$last_scanned" -v end="$end_block" 'BEGIN { for (block = after + 1; block <= end; block++) print block }'
+        fi
+    } | sort -n -u
+}
+
+# Log the blocks missing from the output, runs of consecutive blocks shortened to "first-last"
+# Usage: report_range_gaps <gaps_file>
+report_range_gaps() {
+    local gaps_file="$1"
+
+    if [[ ! -s "$gaps_file" ]]; then
+        return 0
+    fi
+    local count
+    local ranges
+    count=$(wc -l < "$gaps_file" | tr -d ' ')
+    ranges=$(awk 'function flush() { print (first == last ? first : first "-" last) }
+        NR == 1 { first = last = $1; next }
+        $1 == last + 1 { last = $1; next }
+        { flush(); first = last = $1 }
+        END { if (NR > 0) flush() }' "$gaps_file" | paste -sd, - | sed 's/,/, /g')
+    log warn "WARNING: $count blocks of the range are missing from the output: $ranges"
+}
+
+# Print how matches spread over the blocks of a range (total, histogram, busiest blocks) to stderr
+# Usage: report_match_distribution <transactions_file> <start_block> <end_block>
+report_match_distribution() {
+    local transactions_file="$1"
+    local start_block="$2"
+    local end_block="$3"
+
+    if ! log_enabled info; then
+        return 0
+    fi
+    local per_block
+    per_block=$(jq -r '.block_number' "$transactions_file" | sort -n | uniq -c)
+    local blocks=$((end_block - start_block + 1))
+    if [[ -n "$BLOCK_LIST_FILE" ]]; then
+        blocks=$(awk -v start="$start_block" -v end="$end_block" '$1 >= start && $1 <= end' "$BLOCK_LIST_FILE" | wc -l)
+    fi
+
+    {
+        echo ""
+        echo "=== MATCH DISTRIBUTION ==="
+        echo "$per_block" | awk -v blocks="$blocks" '
+            NF == 2 { total += $1; matched++; bucket[$1 >= 50 ? 5 : $1 >= 10 ? 4 : $1 >= 5 ? 3 : $1 >= 2 ? 2 : 1]++ }
+            END {
+                printf "Matching transactions: %d in %d of %d blocks (%.2f per block)\n", total, matched, blocks, total / blocks
+                print "Matches per block:"
+                bucket[0] = blocks - matched
+                split("0,1,2-4,5-9,10-49,50+", label, ",")
+                for (i = 0; i <= 5; i++) {
+                    if (bucket[i] > 0) {
+                        printf "  %5s: %d blocks\n", label[i + 1], bucket[i]
+                    }
+                }
+            }'
+        if [[ -n "$per_block" ]]; then
+            echo "Busiest blocks:"
+            echo "$per_block" | sort -k1,1nr -k2,2n | head -5 | awk '{ printf "  %s: %d\n", $2, $1 }'
+        fi
+    } | log_lines info
+}
+
+# Name chunk <number> of a --split-every output after OUTPUT_FILE, numbered before the
+# extension: out.ndjson.gz becomes out-00001.ndjson.gz
+# Usage: split_chunk_path <number>
+split_chunk_path() {
+    local number="$1"
+    local dir
+    dir=$(dirname "$OUTPUT_FILE")
+    local name
+    name=$(basename "$OUTPUT_FILE")
+
+    local stem="${name%%.*}"
+    local extension=""
+    if [[ "$name" == *.* ]]; then
+        extension=".${name#*.}"
+    fi
+    printf '%s/%s-%05d%s\n' "$dir" "$stem" "$number" "$extension"
+}
+
+# Write records to numbered files of at most SPLIT_EVERY records each (--split-every), in the
+# file's order; every chunk is a complete payload of its own (a json array, a csv with its
+# header, ...). Lists "path transactions" per chunk in SPLIT_CHUNKS_FILE; no records, no files
+# Usage: write_split_output <transactions_file>
+write_split_output() {
+    local transactions_file="$1"
+    local chunks_dir="$TEMP_DIR/split"
+
+    rm -rf "$chunks_dir"
+    mkdir -p "$chunks_dir"
+    : > "$SPLIT_CHUNKS_FILE"
+    # One record per line, so splitting on lines never cuts a record in two
+    if [[ -s "$transactions_file" ]]; then
+        split -l "$SPLIT_EVERY" -a 5 "$transactions_file" "$chunks_dir/chunk_"
+    fi
+
+    local chunk
+    local number=0
+    for chunk in "$chunks_dir"/chunk_*; do
+        if [[ ! -e "$chunk" ]]; then
+            continue
+        fi
+        number=$((number + 1))
+        local chunk_path
+        chunk_path=$(split_chunk_path "$number")
+        if [[ "$OUTPUT_FORMAT" == "parquet" ]]; then
+            write_parquet "$chunk" "$chunk_path"
+        else
+            format_transactions "$OUTPUT_FORMAT" "$chunk" | compress_output > "$chunk_path"
+        fi
+        echo "$chunk_path $(wc -l < "$chunk" | tr -d ' ')" >> "$SPLIT_CHUNKS_FILE"
+    done
+    rm -rf "$chunks_dir"
+}
+
+# Write records as a Parquet file with the DuckDB CLI (--output-format parquet)
+# Hex quantities are cast to unsigned integers (value to UHUGEINT, the rest to UBIGINT); a value
+# that does not fit becomes NULL, and value_hex always keeps the original text
+# Rows keep the file's (sorted) order
+# Usage: write_parquet <transactions_file> <parquet_file>
+write_parquet() {
+    local transactions_file="$1"
+    local parquet_file="$2"
+
+    # DuckDB string literals escape a quote by doubling it
+    local source="${transactions_file//\'/\'\'}"
+    local target="${parquet_file//\'/\'\'}"
+
+    local data_column="data,"
+    if [[ "$OMIT_DATA" == "true" ]]; then
+        data_column=""
+    fi
+
+    duckdb -c "
+        COPY (
+            SELECT
+                hash,
+                \"from\" AS from_address,
+                \"to\" AS to_address,
+                is_creation,
+                value AS value_hex,
+                TRY_CAST(value AS UHUGEINT) AS value,
+                $data_column
+                CAST(data_len AS UBIGINT) AS data_len,
+                has_data,
+                block_number,
+                CAST(block_timestamp AS UBIGINT) AS block_timestamp,
+                TRY_CAST(NULLIF(base_fee_per_gas, '') AS UBIGINT) AS base_fee_per_gas,
+                transaction_index,
+                CAST(tx_type AS UTINYINT) AS tx_type,
+                CAST(nonce AS UBIGINT) AS nonce,
+                CAST(gas AS UBIGINT) AS gas,
+                TRY_CAST(gas_price AS UBIGINT) AS gas_price,
+                TRY_CAST(max_fee_per_gas AS UBIGINT) AS max_fee_per_gas,
+                TRY_CAST(max_priority_fee_per_gas AS UBIGINT) AS max_priority_fee_per_gas,
+                matched_contract
+            FROM read_json('$source', format = 'newline_delimited', columns = {
+                hash: 'VARCHAR', \"from\": 'VARCHAR', \"to\": 'VARCHAR', is_creation: 'BOOLEAN',
+                value: 'VARCHAR', data: 'VARCHAR', data_len: 'VARCHAR', has_data: 'BOOLEAN',
+                block_number: 'UBIGINT', block_timestamp: 'VARCHAR',
+                base_fee_per_gas: 'VARCHAR', transaction_index: 'UBIGINT', tx_type: 'VARCHAR',
+                nonce: 'VARCHAR', gas: 'VARCHAR', gas_price: 'VARCHAR', max_fee_per_gas: 'VARCHAR',
+                max_priority_fee_per_gas: 'VARCHAR', matched_contract: 'VARCHAR'
+            })
+        ) TO '$target' (FORMAT parquet)" > /dev/null
+}
+
+# How matches were found, for --stats-json and --manifest: the trace method that ran, "logs" for
+# --match-by logs, or "direct" for tx.to alone
+detection_method() {
+    if [[ -n "$TRACE_METHOD" ]]; then
+        echo "$TRACE_METHOD"
+    elif [[ -n "$BLOCK_TRACE_METHOD" ]]; then
+        echo "$BLOCK_TRACE_METHOD"
+    elif [[ "$MATCH_BY" == "logs" ]]; then
+        echo "logs"
+    else
+        echo "direct"
+    fi
+}
+
+# Describe a written dataset as JSON (--manifest): where and how it was fetched (endpoints without
+# credentials or paths, chain, range, targets, filters, tool version) and what came out, with the
+# SHA-256 of the output file so a changed or corrupted copy is detectable. Value and gas price
+# bounds are decimal wei; last_block_scanned is where the scan actually got to
+# Usage: write_manifest <manifest_file> <output_file> <chain_id> <transactions>
+write_manifest() {
+    local manifest_file="$1"
+    local output_file="$2"
+    local chain_id="$3"
+    local transactions="$4"
+
+    local endpoints="[]"
+    local url
+    for url in "${RPC_URLS[@]}"; do
+        endpoints=$(echo "$endpoints" | jq -c --arg endpoint "$(endpoint_label "$url")" '. + [$endpoint]')
+    done
+
+    local git_commit
+    git_commit=$(git -C "$(dirname "${BASH_SOURCE[0]}")" rev-parse HEAD 2>/dev/null) || git_commit=""
+
+    local format="$OUTPUT_FORMAT"
+    if [[ -n "$SQLITE_DB" ]]; then
+        format="sqlite"
+    fi
+
+    # Split output is described chunk by chunk, the size being their total
+    local chunks="null"
+    local bytes
+    local sha256=""
+    if [[ $SPLIT_EVERY -gt 0 && -z "$SQLITE_DB" ]]; then
+        local chunk_path
+        local chunk_transactions
+        chunks=$(while read -r chunk_path chunk_transactions; do
+            jq -cn \
+                --arg path "$chunk_path" \
+                --argjson transactions "$chunk_transactions" \
+                --argjson bytes "$(wc -c < "$chunk_path" | tr -d ' ')" \
+                --arg sha256 "$(openssl dgst -sha256 -r "$chunk_path" | cut -d ' ' -f 1)" \
+                '{path: $path, transactions: $transactions, bytes: $bytes, sha256: $sha256}'
+        done < "$SPLIT_CHUNKS_FILE" | jq -sc '.')
+        bytes=$(echo "$chunks" | jq '[.[].bytes] | add // 0')
+    else
+        bytes=$(wc -c < "$output_file" | tr -d ' ')
+        sha256=$(openssl dgst -sha256 -r "$output_file" | cut -d ' ' -f 1)
+    fi
+
+    jq -n \
+        --arg version "$FETCHER_VERSION" \
+        --arg git_commit "$git_commit" \
+        --arg generated_at "$(date -u +%Y-%m-%dT%H:%M:%SZ)" \
+        --argjson endpoints "$endpoints" \
+        --argjson headers "$(rpc_header_names)" \
+        --arg chain_id "$chain_id" \
+        --argjson start_block "$START_BLOCK" \
+        --argjson end_block "$END_BLOCK" \
+        --argjson last_block_scanned "$SCANNED_TO_BLOCK" \
+        --argjson blocks "$(if [[ -n "$BLOCK_LIST_FILE" ]]; then jq -s -c '.' "$BLOCK_LIST_FILE"; else echo null; fi)" \
+        --argjson interrupted "$INTERRUPTED" \
+        --argjson failed_blocks "$(sort -n -u "$FAILED_BLOCKS_FILE" | jq -s -c '.')" \
+        --arg targets "$TARGET_CONTRACTS" \
+        --arg match_by "$MATCH_BY" \
+        --arg selectors "$SELECTORS" \
+        --arg contract_selectors "$CONTRACT_SELECTORS" \
+        --arg exclude_from "$EXCLUDE_FROM" \
+        --arg min_value "$MIN_VALUE" \
+        --arg max_value "$MAX_VALUE" \
+        --arg min_gas_price "$MIN_GAS_PRICE" \
+        --arg max_gas_price "$MAX_GAS_PRICE" \
+        --argjson include_creations "$INCLUDE_CREATIONS" \
+        --argjson include_pending "$INCLUDE_PENDING" \
+        --arg detection_method "$(detection_method)" \
+        --arg sample_rate "$SAMPLE_RATE" \
+        --argjson max_results "$MAX_RESULTS" \
+        --argjson with_receipts "$WITH_RECEIPTS" \
+        --argjson with_state_diff "$WITH_STATE_DIFF" \
+        --argjson with_call_trace "$WITH_CALL_TRACE" \
+        --argjson with_access_list "$WITH_ACCESS_LIST" \
+        --arg abi "${ABI_SELECTORS_FILE:+true}" \
+        --arg format "$format" \
+        --arg numeric_format "$NUMERIC_FORMAT" \
+        --argjson omit_data "$OMIT_DATA" \
+        --argjson checksum_addresses "$CHECKSUM_ADDRESSES" \
+        --argjson hashes_only "$HASHES_ONLY" \
+        --arg compress "$COMPRESS" \
+        --arg path "$output_file" \
+        --argjson transactions "$transactions" \
+        --argjson bytes "$bytes" \
+        --arg sha256 "$sha256" \
+        --argjson split_every "$SPLIT_EVERY" \
+        --argjson chunks "$chunks" \
+        "$JQ_HEX_TO_DEC"'def or_null: if . == "" then null else . end;
+        def wei: if . == "" then null else hex_to_dec end;
+        {
+            manifest_version: 1,
+            generated_at: $generated_at,
+            tool: {name: "transaction_fetcher.sh", version: $version, git_commit: ($git_commit | or_null)},
+            source: {
+                endpoints: $endpoints,
+                headers: $headers,
+                chain_id: (if $chain_id == "" then null else ($chain_id | tonumber) end)
+            },
+            range: {
+                start_block: $start_block,
+                end_block: $end_block,
+                last_block_scanned: $last_block_scanned,
+                blocks: $blocks,
+                interrupted: $interrupted,
+                failed_blocks: $failed_blocks
+            },
+            targets: ($targets | split(",")),
+            filters: {
+                match_by: $match_by,
+                selectors: (if $selectors == "" then [] else ($selectors | split(",")) end),
+                contract_selectors: (if $contract_selectors == "" then {} else ($contract_selectors | split(",")
+                    | map(split(":")) | group_by(.[0]) | map({key: .[0][0], value: map(.[1])}) | from_entries) end),
+                exclude_from: (if $exclude_from == "" then [] else ($exclude_from | split(",")) end),
+                min_value_wei: ($min_value | wei),
+                max_value_wei: ($max_value | wei),
+                min_gas_price_wei: ($min_gas_price | wei),
+                max_gas_price_wei: ($max_gas_price | wei),
+                include_creations: $include_creations,
+                include_pending: $include_pending,
+                detection_method: $detection_method,
+                sample_rate: ($sample_rate | tonumber),
+                max_results: $max_results
+            },
+            enrichment: {
+                receipts: $with_receipts,
+                state_diff: $with_state_diff,
+                call_trace: $with_call_trace,
+                access_list: $with_access_list,
+                abi_decoded: ($abi == "true")
+            },
+            output: ({
+                path: $path,
+                format: $format,
+                numeric_format: $numeric_format,
+                omit_data: $omit_data,
+                checksum_addresses: $checksum_addresses,
+                hashes_only: $hashes_only,
+                compress: ($compress | or_null),
+                transactions: $transactions,
+                bytes: $bytes,
+                sha256: ($sha256 | or_null)
+            } + if $chunks == null then {} else {split_every: $split_every, chunks: $chunks} end)
+        }' > "$manifest_file"
+}
+
+# ABI-encode the records of a file as one BacktestingTypes.TransactionData[] (--output-format abi)
+# Prints 0x-prefixed hex that Solidity reads back with abi.decode(data, (TransactionData[])):
+# the array offset and length, one offset per element (the struct is dynamic because of
+# `data`), then each struct's 11 head words followed by its calldata. Missing fees encode as 0
+# Usage: encode_abi_transactions <transactions_file>
+encode_abi_transactions() {
+    local transactions_file="$1"
+    jq -s -r '
+        def num_to_hex: if . < 16 then "0123456789abcdef"[.:. + 1]
+            else (. / 16 | floor | num_to_hex) + "0123456789abcdef"[. % 16:. % 16 + 1] end;
+        def word: ltrimstr("0x") | ascii_downcase | ("0" * (64 - length)) + .;
+        def hex_word: (. // "0x0") | if . == "" then "0" else . end | word;
+        def num_word: if . == null or . == "" then "0" else tonumber | num_to_hex end | word;
+        def element:
+            (.data | ltrimstr("0x") | ascii_downcase) as $data
+            | ([.hash, .from, (.to // "")] | map(hex_word) | join(""))
+              + (.value | hex_word)
+              + (11 * 32 | num_word)
+              + ([.block_number, .transaction_index] | map(num_word) | join(""))
+              + (.gas_price | hex_word) + (.gas | num_word)
+              + ([.max_fee_per_gas, .max_priority_fee_per_gas] | map(hex_word) | join(""))
+              + ($data | length / 2 | num_word)
+              + $data + ("0" * ((64 - ($data | length) % 64) % 64));
+        map(element) as $elements
+        | ($elements | length) as $count
+        | (reduce $elements[] as $element ({offsets: [], next: ($count * 32)};
+              .offsets += [.next | num_word] | .next += ($element | length / 2)) | .offsets) as $offsets
+        | "0x" + (32 | num_word) + ($count | num_word) + ($offsets | join("")) + ($elements | join(""))
+    ' "$transactions_file"
+}
+
+# Format transactions for output
+format_transactions() {
+    local output_format="$1"
+    local all_transactions_file="$2"
+
+    # CSV always starts with its header row, even when nothing matched
+    if [[ "$output_format" == "csv" ]]; then
+        csv_header
+    fi
+
+    if [[ ! -f "$all_transactions_file" ]] || [[ ! -s "$all_transactions_file" ]]; then
+        case "$output_format" in
+            "csv") ;;
+            "abi") encode_abi_transactions /dev/null ;;
+            "ndjson") echo "" ;;
+            "json") echo "[]" ;;
+            *) echo "${SIMPLE_PAYLOAD_HEADER}0" ;;
+        esac
+        return
+    fi
+
+    # Count transactions (one per line)
+    local tx_count
+    tx_count=$(wc -l < "$all_transactions_file" | tr -d ' ')
+
+    case "$output_format" in
+        "json")
+            # Convert to JSON format
+            echo "["
+            encode_transaction_rows "$output_format" "$all_transactions_file"
+            echo "]"
+            ;;
+        "ndjson"|"csv")
+            encode_transaction_rows "$output_format" "$all_transactions_file"
+            ;;
+        "abi")
+            encode_abi_transactions "$all_transactions_file"
+            ;;
+        *)
+            # Simple format: CSTDv1:count|hash|from|to|value|data|blockNumber|txIndex|gasPrice|gasLimit|maxFeePerGas|maxPriorityFeePerGas|...
+            # Fields are percent-encoded ("%" -> "%25", "|" -> "%7C") so a stray pipe can't shift fields
+            echo -n "$SIMPLE_PAYLOAD_HEADER$tx_count"
+            jq -j --arg zero "$(numeric_zero)" "$(numeric_format_defs)"'
+                apply_numeric_format
+                | "|" + ([.hash, .from, .to, .value, .data, .block_number, .transaction_index,
+                          (.gas_price // ""), .gas,
+                          (.max_fee_per_gas // $zero), (.max_priority_fee_per_gas // $zero)]
+                         | map(tostring | gsub("%"; "%25") | gsub("\\|"; "%7C")) | join("|"))' \
+                "$all_transactions_file"
+            ;;
+    esac
+}
+
+# Write streamed output (stdin) to --output-file, or to stdout inside the payload markers
+stream_write() {
+    if [[ -n "$OUTPUT_FILE" ]]; then
+        compress_output >> "$OUTPUT_FILE"
+    else
+        cat
+    fi
+}
+
+# Compress a payload on its way to OUTPUT_FILE (--compress); a pass-through without it
+# Streamed batches become separate gzip members, which gzip -d reads back as one file
+compress_output() {
+    if [[ "$COMPRESS" == "gzip" ]]; then
+        gzip -c
+    else
+        cat
+    fi
+}
+
+# Compress a payload for stdout (--compress-stdout): gzip, then base64 so it stays one text line
+# between the TRANSACTION_DATA markers (BSD base64 has no -w, so the line breaks are stripped after)
+compress_stdout() {
+    if [[ "$COMPRESS_STDOUT" == "true" ]]; then
+        gzip -c | base64 | tr -d '\n'
+    else
+        cat
+    fi
+}
+
+# Open the streamed payload (--stream); a resumed OUTPUT_FILE keeps the records it holds
+stream_begin() {
+    if [[ -n "$RESUME_FROM_BLOCK" ]]; then
+        STREAMED_TX_COUNT=$(wc -l < "$OUTPUT_FILE" | tr -d ' ')
+    elif [[ -n "$OUTPUT_FILE" ]]; then
+        : > "$OUTPUT_FILE"
+    else
+        echo "TRANSACTION_DATA:START"
+        echo -n "TRANSACTION_DATA:"
+    fi
+
+    case "$OUTPUT_FORMAT" in
+        "json") echo "[" | stream_write ;;
+        "csv") csv_header | stream_write ;;
+    esac
+}
+
+# Flush one completed batch, ordered by (block_number, transaction_index)
+stream_batch() {
+    local batch_file="$1"
+
+    sort_transactions_file "$batch_file"
+
+    {
+        if [[ "$OUTPUT_FORMAT" == "json" && $STREAMED_TX_COUNT -gt 0 ]]; then
+            echo ","
+        fi
+        encode_transaction_rows "$OUTPUT_FORMAT" "$batch_file"
+    } | stream_write
+
+    STREAMED_TX_COUNT=$((STREAMED_TX_COUNT + $(wc -l < "$batch_file")))
+}
+
+# Create the transactions table of --sqlite (columns follow BacktestingTypes.TransactionData)
+# Quantities that can exceed 64 bits stay text (0x hex or decimal per NUMERIC_FORMAT), as in the json records
+sqlite_init() {
+    sqlite3 "$SQLITE_DB" <<'SQL'
+CREATE TABLE IF NOT EXISTS transactions (
+    hash TEXT PRIMARY KEY,
+    from_address TEXT NOT NULL,
+    to_address TEXT NOT NULL,
+    value TEXT NOT NULL,
+    data TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    transaction_index INTEGER NOT NULL,
+    gas_price TEXT,
+    gas_limit TEXT NOT NULL,
+    max_fee_per_gas TEXT,
+    max_priority_fee_per_gas TEXT
+);
+CREATE INDEX IF NOT EXISTS transactions_block_index ON transactions (block_number, transaction_index);
+SQL
+}
+
+# Insert one completed batch in a single SQL transaction
+# Rows are keyed by hash, so re-running a range (or resuming it) replaces rather than duplicates
+sqlite_write_batch() {
+    local batch_file="$1"
+    {
+        echo "BEGIN;"
+        jq -r --arg q "'" "$(numeric_format_defs)"'
+            def text: if . == null then "NULL" else $q + (tostring | gsub($q; $q + $q)) + $q end;
+            apply_numeric_format
+            | "INSERT OR REPLACE INTO transactions VALUES ("
+            + ([(.hash | text), (.from | text), ((.to // "") | text), (.value | text), (.data | text),
+                (.block_number | tostring), (.transaction_index | tostring),
+                (.gas_price | text), (.gas | text), (.max_fee_per_gas | text),
+                (.max_priority_fee_per_gas | text)] | join(", "))
+            + ");"' "$batch_file"
+        echo "COMMIT;"
+    } | sqlite3 "$SQLITE_DB"
+}
+
+# Close the streamed payload (--stream)
+stream_end() {
+    if [[ "$OUTPUT_FORMAT" == "json" ]]; then
+        echo "]" | stream_write
+    elif [[ "$OUTPUT_FORMAT" == "ndjson" && $STREAMED_TX_COUNT -eq 0 && -z "$OUTPUT_FILE" ]]; then
+        echo ""
+    fi
+
+    if [[ -n "$OUTPUT_FILE" ]]; then
+        log info "Wrote $STREAMED_TX_COUNT transactions ($OUTPUT_FORMAT${COMPRESS:+, $COMPRESS}) to $OUTPUT_FILE"
+    else
+        echo -n "TRANSACTION_DATA:END"
+    fi
+}
+
+# Write completed batches (--stream and --sqlite) from a background writer, so a slow sink such
+# as gzip or SQLite overlaps with fetching the next batch. Batches are queued in order through a
+# FIFO; once OUTPUT_BUFFER of them are waiting, output_writer_submit blocks the scan, which in
+# turn stops the block pool at its lookahead, so memory and disk use stay bounded
+# Usage: output_writer_start
+output_writer_start() {
+    OUTPUT_WRITER_DIR="$TEMP_DIR/output_writer"
+    rm -rf "$OUTPUT_WRITER_DIR"
+    mkdir -p "$OUTPUT_WRITER_DIR"
+    mkfifo "$OUTPUT_WRITER_DIR/queue"
+    : > "$OUTPUT_WRITER_DIR/written"
+    OUTPUT_WRITER_SUBMITTED=0
+
+    # Opened read-write, so submitting never waits for the writer to open the queue
+    output_writer_run <> "$OUTPUT_WRITER_DIR/queue" &
+    OUTPUT_WRITER_PID=$!
+}
+
+# The writer itself (see output_writer_start); reads queued batch files until END and leaves
+# the streamed transaction count behind for output_writer_stop
+# Usage: output_writer_run
+output_writer_run() {
+    local batch_file
+
+    # Ctrl-C is for the scan to handle (see fetch_transactions); what is queued still gets written
+    trap '' INT
+    while IFS= read -r batch_file && [[ "$batch_file" != "END" ]]; do
+        if [[ "$STREAM_OUTPUT" == "true" ]]; then
+            stream_batch "$batch_file"
+        fi
+        if [[ -n "$SQLITE_DB" ]]; then
+            sqlite_write_batch "$batch_file"
+        fi
+        rm -f "$batch_file"
+        echo "$batch_file" >> "$OUTPUT_WRITER_DIR/written"
+    done
+    echo "$STREAMED_TX_COUNT" > "$OUTPUT_WRITER_DIR/streamed"
+}
+
+# Queue a copy of one completed batch for the writer, first waiting while OUTPUT_BUFFER batches
+# are still unwritten; returns 1 if the writer has died
+# Usage: output_writer_submit <batch_file>
+output_writer_submit() {
+    local batch_file="$1"
+
+    local round=0
+    while [[ $((OUTPUT_WRITER_SUBMITTED - $(wc -l < "$OUTPUT_WRITER_DIR/written"))) -ge $OUTPUT_BUFFER ]]; do
+        if ! kill -0 "$OUTPUT_WRITER_PID" 2>/dev/null; then
+            log error "Error: The output writer stopped with batches still queued"
+            return 1
+        fi
+        poll_sleep "$round"
+        round=$((round + 1))
+    done
+
+    OUTPUT_WRITER_SUBMITTED=$((OUTPUT_WRITER_SUBMITTED + 1))
+    local queued="$OUTPUT_WRITER_DIR/batch_$OUTPUT_WRITER_SUBMITTED.txt"
+    cp "$batch_file" "$queued"
+    echo "$queued" > "$OUTPUT_WRITER_DIR/queue"
+}
+
+# Let the writer finish the queued batches and exit; picks up its streamed transaction count
+# Usage: output_writer_stop
+output_writer_stop() {
+    if [[ -z "$OUTPUT_WRITER_PID" ]]; then
+        return 0
+    fi
+
+    local status=0
+    if kill -0 "$OUTPUT_WRITER_PID" 2>/dev/null; then
+        echo "END" > "$OUTPUT_WRITER_DIR/queue"
+    fi
+    wait "$OUTPUT_WRITER_PID" || status=$?
+    OUTPUT_WRITER_PID=""
+    if [[ -s "$OUTPUT_WRITER_DIR/streamed" ]]; then
+        STREAMED_TX_COUNT=$(cat "$OUTPUT_WRITER_DIR/streamed")
+    fi
+
+    if [[ $status -ne 0 ]]; then
+        log error "Error: Writing the output failed"
+        return 1
+    fi
+}
+
+# Erase the progress bar so regular log lines start on a clean line
+progress_clear() {
+    if [[ "$SHOW_PROGRESS" == "true" ]]; then
+        printf '\r\033[K' >&2
+    fi
+}
+
+# Draw the progress bar: blocks done / total, throughput and ETA
+# Usage: progress_draw <blocks_done> <blocks_total> <blocks_this_run> <run_start_ms>
+# Throughput only counts blocks fetched in this run, so a resumed checkpoint doesn't skew the ETA
+progress_draw() {
+    if [[ "$SHOW_PROGRESS" != "true" ]]; then
+        return 0
+    fi
+
+    local blocks_done="$1"
+    local blocks_total="$2"
+    local blocks_this_run="$3"
+    local run_start_ms="$4"
+
+    local width=30
+    local filled=$((blocks_done * width / blocks_total))
+    local bar=""
+    local i
+    for ((i = 0; i < width; i++)); do
+        if [[ $i -lt $filled ]]; then
+            bar+="#"
+        else
+            bar+="-"
+        fi
+    done
+
+    local elapsed_ms=$(($(now_ms) - run_start_ms))
+    local rate="-"
+    local eta="-"
+    if [[ $elapsed_ms -gt 0 && $blocks_this_run -gt 0 ]]; then
+        rate=$(awk "BEGIN {printf \"%.1f\", $blocks_this_run * 1000 / $elapsed_ms}")
+        local eta_secs=$(((blocks_total - blocks_done) * elapsed_ms / blocks_this_run / 1000))
+        eta=$(printf '%d:%02d:%02d' $((eta_secs / 3600)) $((eta_secs % 3600 / 60)) $((eta_secs % 60)))
+    fi
+
+    printf '\r\033[K[%s] %d/%d blocks  %s blocks/s  ETA %s' \
+        "$bar" "$blocks_done" "$blocks_total" "$rate" "$eta" >&2
+}
+
+# Identify the fetch a checkpoint belongs to; resuming under other settings would mix results
+checkpoint_key() {
+    # Incremental runs move their range forward every time, so only targets and filters identify them
+    local start_block="$START_BLOCK"
+    local end_block="$END_BLOCK"
+    if [[ "$SINCE_LAST_RUN" == "true" ]]; then
+        start_block=""
+        end_block=""
+    fi
+
+    jq -cn \
+        --arg targets "$TARGET_CONTRACTS" \
+        --arg start_block "$start_block" \
+        --arg end_block "$end_block" \
+        --arg match_by "$MATCH_BY" \
+        --arg selectors "$SELECTORS" \
+        --arg exclude_from "$EXCLUDE_FROM" \
+        --arg min_value "$MIN_VALUE" \
+        --arg max_value "$MAX_VALUE" \
+        --arg min_gas_price "$MIN_GAS_PRICE" \
+        --arg max_gas_price "$MAX_GAS_PRICE" \
+        --arg include_creations "$INCLUDE_CREATIONS" \
+        --arg use_trace_filter "$USE_TRACE_FILTER" \
+        --arg sample_rate "$SAMPLE_RATE" \
+        --arg contract_selectors "$CONTRACT_SELECTORS" \
+        --arg include_pending "$INCLUDE_PENDING" \
+        --arg skip_unavailable_state "$SKIP_UNAVAILABLE_STATE" \
+        --arg with_receipts "$WITH_RECEIPTS" \
+        '{
+            targets: $targets,
+            start_block: $start_block,
+            end_block: $end_block,
+            match_by: $match_by,
+            selectors: $selectors,
+            exclude_from: $exclude_from,
+            min_value: $min_value,
+            max_value: $max_value,
+            min_gas_price: $min_gas_price,
+            max_gas_price: $max_gas_price,
+            include_creations: $include_creations,
+            use_trace_filter: $use_trace_filter,
+            sample_rate: $sample_rate,
+            include_pending: $include_pending,
+            skip_unavailable_state: $skip_unavailable_state,
+            with_receipts: $with_receipts
+        } + if $contract_selectors == "" then {} else {contract_selectors: $contract_selectors} end'
+}
+
+# Print the last block CHECKPOINT_FILE marks as done, or nothing when it holds no checkpoint yet
+# Usage: checkpoint_last_block
+checkpoint_last_block() {
+    if [[ ! -s "$CHECKPOINT_FILE" ]]; then
+        return 0
+    fi
+
+    local saved_key
+    saved_key=$(jq -c '.key' "$CHECKPOINT_FILE" 2>/dev/null || true)
+    if [[ "$saved_key" != "$(checkpoint_key)" ]]; then
+        log error "Error: Checkpoint $CHECKPOINT_FILE was written for a different range, targets, filters or record options; delete it or pick another --checkpoint-file"
+        return 1
+    fi
+
+    jq -r '.last_block' "$CHECKPOINT_FILE"
+}
+
+# Load the records saved in CHECKPOINT_FILE into <output_file> and print the block to resume from
+# Usage: checkpoint_restore <output_file>
+# The records live next to the checkpoint in "<CHECKPOINT_FILE>.records"
+checkpoint_restore() {
+    local output_file="$1"
+    local records_file="$CHECKPOINT_FILE.records"
+
+    local last_block
+    last_block=$(checkpoint_last_block) || return 1
+    if [[ -z "$last_block" ]]; then
+        # Records without a checkpoint can't be trusted to be contiguous
+        : > "$records_file"
+        echo "$START_BLOCK"
+        return 0
+    fi
+
+    # Drop records appended after the last checkpoint write (a crash in between); they are refetched.
+    # Records saved before block numbers became numeric are converted on the way in
+    if [[ -f "$records_file" ]]; then
+        jq -c --argjson last_block "$last_block" '
+            .block_number |= tonumber | .transaction_index |= (if . == null then . else tonumber end)
+            | select(.block_number <= $last_block)' "$records_file" > "$output_file"
+        cp "$output_file" "$records_file"
+    fi
+
+    echo $((last_block + 1))
+}
+
+# Mark every block up to <last_block> as done, saving the records of <batch_file> with it
+# Usage: checkpoint_advance <batch_file> <last_block>
+checkpoint_advance() {
+    local batch_file="$1"
+    local last_block="$2"
+
+    if [[ -s "$batch_file" ]]; then
+        cat "$batch_file" >> "$CHECKPOINT_FILE.records"
+    fi
+
+    # Write through a temp file so a crash never leaves a truncated checkpoint
+    jq -cn \
+        --argjson key "$(checkpoint_key)" \
+        --arg last_block "$last_block" \
+        '{key: $key, last_block: ($last_block | tonumber)}' > "$CHECKPOINT_FILE.tmp"
+    mv "$CHECKPOINT_FILE.tmp" "$CHECKPOINT_FILE"
+}
+
+# Pick up an ndjson OUTPUT_FILE that an earlier --stream run left partly written (--resume-output).
+# The file must come with the --manifest of that run, which has to describe the same chain,
+# range, targets, filters, enrichments and format: appending under other settings would mix two
+# datasets in one file. Blocks up to the manifest's last_block_scanned (or before its first
+# failed block) are complete. Records past them were written after the manifest by a run that
+# died: a torn last line is dropped, and so are the records of the last block in the file,
+# which may be missing some. Sets RESUME_FROM_BLOCK to where the scan continues (empty when
+# there is no file to resume)
+# Usage: resume_output_prepare <manifest_file> <chain_id>
+resume_output_prepare() {
+    local manifest_file="$1"
+    local chain_id="$2"
+
+    RESUME_FROM_BLOCK=""
+    if [[ ! -s "$OUTPUT_FILE" ]]; then
+        return 0
+    fi
+    if [[ ! -s "$manifest_file" ]]; then
+        log error "Error: --resume-output found $OUTPUT_FILE but no manifest $manifest_file to check it against; delete the file or pass the --manifest it was written with"
+        return 1
+    fi
+
+    local current_manifest="$TEMP_DIR/resume_manifest.json"
+    SCANNED_TO_BLOCK=$((START_BLOCK - 1))
+    write_manifest "$current_manifest" "$OUTPUT_FILE" "$chain_id" 0
+    local mismatch
+    if ! mismatch=$(jq -rn --slurpfile saved "$manifest_file" --slurpfile current "$current_manifest" '
+            def dataset: {
+                chain_id: .source.chain_id,
+                start_block: .range.start_block,
+                end_block: .range.end_block,
+                targets: .targets,
+                filters: .filters,
+                enrichments: .enrichment,
+                format: .output.format,
+                numeric_format: .output.numeric_format,
+                omit_data: .output.omit_data,
+                checksum_addresses: (.output.checksum_addresses // false),
+                hashes_only: (.output.hashes_only // false)
+            };
+            ($saved[0] | dataset) as $saved
+            | [$current[0] | dataset | to_entries[] | select($saved[.key] != .value) | .key]
+            | join(", ")' 2>/dev/null); then
+        log error "Error: Cannot read manifest $manifest_file"
+        return 1
+    fi
+    if [[ -n "$mismatch" ]]; then
+        log error "Error: $OUTPUT_FILE was written with other settings ($mismatch differ, see $manifest_file); delete it or pick another --output-file"
+        return 1
+    fi
+
+    local trusted_block
+    trusted_block=$(jq -r '[.range.last_block_scanned, (.range.failed_blocks[] - 1)] | min' "$manifest_file")
+    local saved_sha256
+    saved_sha256=$(jq -r '.output.sha256 // ""' "$manifest_file")
+
+    # wc -l counts newlines, so this keeps every complete line and drops a torn last one
+    local kept_file="$TEMP_DIR/resume_records.ndjson"
+    head -n "$(wc -l < "$OUTPUT_FILE")" "$OUTPUT_FILE" > "$kept_file"
+
+    RESUME_FROM_BLOCK=$((trusted_block + 1))
+    if [[ "$saved_sha256" != "$(openssl dgst -sha256 -r "$OUTPUT_FILE" | cut -d ' ' -f 1)" && -s "$kept_file" ]]; then
+        local last_block
+        if ! last_block=$(tail -n 1 "$kept_file" | jq -er '.block_number | tonumber' 2>/dev/null); then
+            log error "Error: $OUTPUT_FILE holds lines that are not ndjson transaction records"
+            return 1
+        fi
+        if [[ $last_block -gt $trusted_block ]]; then
+            RESUME_FROM_BLOCK=$last_block
+        fi
+    fi
+
+    local kept_records
+    if ! kept_records=$(jq -r --argjson resume "$RESUME_FROM_BLOCK" \
+            'select((.block_number | tonumber) < $resume) | 1' "$kept_file" 2>/dev/null | wc -l); then
+        log error "Error: $OUTPUT_FILE holds lines that are not ndjson transaction records"
+        return 1
+    fi
+    head -n "$kept_records" "$kept_file" > "$OUTPUT_FILE"
+    rm -f "$kept_file" "$current_manifest"
+}
+
+# Fetch every transaction matching TARGET_CONTRACTS from START_BLOCK to END_BLOCK
+# Usage: fetch_transactions <output_file>
+# Writes sorted JSON records (one per line) to <output_file>, replacing its contents.
+# Runs in the calling shell (not a subshell) so TRACE_METHOD reflects the detection
+# method that was used; failed blocks are collected in FAILED_BLOCKS_FILE.
+# With CHECKPOINT_FILE set, progress is saved after every batch and a matching
+# checkpoint from an earlier run is resumed instead of starting at START_BLOCK.
+fetch_transactions() {
+    local all_transactions_file="$1"
+
+    if [[ ${#RPC_URLS[@]} -eq 0 || -z "$TARGET_CONTRACTS" || -z "$START_BLOCK" || -z "$END_BLOCK" ]]; then
+        log error "Error: fetch_transactions needs RPC_URLS, TARGET_CONTRACTS, START_BLOCK and END_BLOCK"
+        return 1
+    fi
+
+    local target_contracts="$TARGET_CONTRACTS"
+    local start_block="$START_BLOCK"
+    local end_block="$END_BLOCK"
+
+    : > "$all_transactions_file"
+
+    local first_block=$start_block
+    if [[ -n "$CHECKPOINT_FILE" ]]; then
+        first_block=$(checkpoint_restore "$all_transactions_file") || return 1
+        if [[ $first_block -gt $start_block ]]; then
+            log info "Resuming from checkpoint $CHECKPOINT_FILE at block $first_block ($(wc -l < "$all_transactions_file" | tr -d ' ') transactions restored)"
+        fi
+    fi
+    if [[ -n "$RESUME_FROM_BLOCK" ]]; then
+        first_block=$RESUME_FROM_BLOCK
+        log info "Resuming $OUTPUT_FILE at block $first_block ($(wc -l < "$OUTPUT_FILE" | tr -d ' ') transactions kept)"
+    fi
+
+    # Adaptive mode starts at the floor and earns its way up batch by batch
+    CONCURRENCY=$MAX_CONCURRENT
+    if [[ "$ADAPTIVE_CONCURRENCY" == "true" ]]; then
+        CONCURRENCY=$MIN_CONCURRENT
+        ADAPTIVE_PEAK=$MIN_CONCURRENT
+        ADAPTIVE_BASELINE_MS=""
+        : > "$ADAPTIVE_SAMPLES_FILE"
+    fi
+
+    log info ""
+    log info "=== TRANSACTION DISCOVERY ==="
+    log info "Target: ${target_contracts//,/, }"
+    if [[ -n "$BLOCK_LIST_FILE" ]]; then
+        log info "Blocks: $(wc -l < "$BLOCK_LIST_FILE" | tr -d ' ') listed, $start_block to $end_block"
+    else
+        log info "Blocks: $start_block to $end_block"
+    fi
+    if [[ -n "$SELECTORS" ]]; then
+        log info "Selectors: ${SELECTORS//,/, }"
+    fi
+    if [[ -n "$CONTRACT_SELECTORS" ]]; then
+        log info "Selectors by contract: ${CONTRACT_SELECTORS//,/, }"
+    fi
+    if [[ -n "$EXCLUDE_FROM" ]]; then
+        log info "Excluded senders: ${EXCLUDE_FROM//,/, }"
+    fi
+    if [[ ${#RPC_URLS[@]} -gt 1 ]]; then
+        log info "RPC endpoints: ${#RPC_URLS[@]}"
+    fi
+    if [[ "$ADAPTIVE_CONCURRENCY" == "true" ]]; then
+        log info "Concurrency: adaptive, $MIN_CONCURRENT to $MAX_CONCURRENT"
+    fi
+    log info ""
+
+    if [[ "$USE_TRACE_FILTER" == "true" ]]; then
+        TRACE_METHOD="trace_filter"
+        log info "[INFO] Detecting both direct calls AND internal/nested calls to targets"
+        log info "[INFO] Trying trace APIs with automatic fallback..."
+        log info ""
+    elif [[ "$MATCH_BY" == "logs" ]]; then
+        log info "[INFO] Matching direct calls and transactions that emit target logs (eth_getLogs)"
+        log info ""
+    elif [[ "$MATCH_BY" != "traces" ]]; then
+        log info "[INFO] Direct calls only mode (no internal call detection)"
+        log info ""
+    fi
+
+    # --match-by traces settles on a trace method once, asking for the first block to scan
+    if [[ "$MATCH_BY" == "traces" ]]; then
+        if [[ -z "$BLOCK_TRACE_METHOD" ]]; then
+            detect_block_trace_method "$first_block" || return 1
+        fi
+        if [[ "$BLOCK_TRACE_METHOD" == "trace_block" ]]; then
+            log info "[INFO] Detecting internal calls to targets with trace_block on every block"
+        else
+            log info "[INFO] Detecting internal calls to targets with debug_traceBlockByNumber on every block"
+        fi
+        log info ""
+    fi
+
+    # --blocks-file scans each run of consecutive listed blocks as a range of its own
+    local block_runs="$first_block $end_block"
+    if [[ -n "$BLOCK_LIST_FILE" ]]; then
+        block_runs=$(awk -v first="$first_block" '$1 >= first {
+                if (runs && $1 == last + 1) { last = $1; next }
+                if (runs) print start, last
+                start = last = $1; runs++
+            }
+            END { if (runs) print start, last }' "$BLOCK_LIST_FILE")
+    fi
+    local run_start
+    local run_end
+
+    # --match-by logs asks for the logs of the whole range up front, in as few eth_getLogs as
+    # the provider accepts
+    if [[ "$MATCH_BY" == "logs" && $first_block -le $end_block ]]; then
+        while read -r run_start run_end; do
+            if [[ -n "$run_start" ]]; then
+                prefetch_logs "$run_start" "$run_end" "$target_contracts"
+            fi
+        done <<< "$block_runs"
+    fi
+
+    # A checkpoint may hold more matches than a smaller --max-results asks for
+    if [[ $MAX_RESULTS -gt 0 && $(wc -l < "$all_transactions_file") -gt $MAX_RESULTS ]]; then
+        sort_transactions_file "$all_transactions_file"
+        head -n "$MAX_RESULTS" "$all_transactions_file" > "$all_transactions_file.limited"
+        mv "$all_transactions_file.limited" "$all_transactions_file"
+    fi
+    RESULTS_COLLECTED=$(wc -l < "$all_transactions_file" | tr -d ' ')
+    : > "$SEEN_HASHES_FILE"
+    drop_duplicate_records "$all_transactions_file"
+
+    if [[ "$STREAM_OUTPUT" == "true" ]]; then
+        stream_begin
+        if [[ -s "$all_transactions_file" ]]; then
+            stream_batch "$all_transactions_file"
+        fi
+    fi
+
+    if [[ -n "$SQLITE_DB" ]]; then
+        sqlite_init
+        if [[ -s "$all_transactions_file" ]]; then
+            sqlite_write_batch "$all_transactions_file"
+        fi
+    fi
+
+    # Ctrl-C (or SIGTERM) stops the scan after the batches already completed, which are kept,
+    # streamed and checkpointed as usual; the caller reports the run as interrupted
+    SCANNED_TO_BLOCK=$((first_block - 1))
+    INTERRUPTED=false
+    local saved_traps
+    saved_traps=$(trap -p INT TERM)
+    trap 'INTERRUPTED=true' INT TERM
+    local scan_status=0
+    if [[ -n "$BLOCK_LIST_FILE" ]]; then
+        while read -r run_start run_end; do
+            if [[ -z "$run_start" || "$INTERRUPTED" == "true" ]]; then
+                break
+            fi
+            scan_block_range "$run_start" "$run_start" "$run_end" "$all_transactions_file" || scan_status=$?
+            if [[ $scan_status -ne 0 ]]; then
+                break
+            fi
+        done <<< "$block_runs"
+    else
+        scan_block_range "$start_block" "$first_block" "$end_block" "$all_transactions_file" || scan_status=$?
+    fi
+    restore_signal_traps "$saved_traps"
+    if [[ $scan_status -ne 0 ]]; then
+        return 1
+    fi
+
+    # Blocks complete out of order, so sort to make the output reproducible across runs
+    if [[ -s "$all_transactions_file" ]]; then
+        sort_transactions_file "$all_transactions_file"
+    fi
+
+    if enrichment_enabled && [[ "$STREAM_OUTPUT" != "true" && -s "$all_transactions_file" ]]; then
+        enrich_records "$all_transactions_file" "$CONCURRENCY"
+    fi
+
+    if [[ -n "$ABI_SELECTORS_FILE" && "$STREAM_OUTPUT" != "true" && -s "$all_transactions_file" ]]; then
+        decode_transaction_inputs "$all_transactions_file"
+    fi
+
+    if [[ "$CHECKSUM_ADDRESSES" == "true" && "$STREAM_OUTPUT" != "true" && -s "$all_transactions_file" ]]; then
+        checksum_record_addresses "$all_transactions_file" || return 1
+    fi
+}
+
+# Read the records of an earlier json or ndjson dump (gzip-compressed or not) back into the
+# internal form: one object per line with numeric block_number and transaction_index
+# Fails unless every record is a transaction with hex quantities, as the default
+# --numeric-format writes them (the filters and the simple and abi encoders expect hex)
+# Usage: read_dump <dump_file>
+read_dump() {
+    local dump_file="$1"
+
+    local reader="cat"
+    if gzip -t "$dump_file" 2>/dev/null; then
+        reader="gzip -dc"
+    fi
+
+    if ! $reader "$dump_file" | jq -c '
+            if type == "array" then .[] else . end
+            | if type == "object" and has("hash") and has("block_number") and has("transaction_index") then .
+              else error("not a transaction record") end
+            | if (.value | type) == "string" and (.value | startswith("0x")) then .
+              else error("decimal quantities") end
+            | .block_number |= tonumber
+            | .transaction_index |= (if . == null then . else tonumber end)' 2>/dev/null; then
+        log error "Error: --input-file $dump_file is not a json or ndjson dump of transactions with hex quantities (the default --numeric-format)"
+        return 1
+    fi
+}
+
+# Print the first and last block number of a dump ("0 0" if it holds no records)
+# Usage: dump_block_range <dump_file>
+dump_block_range() {
+    local dump_file="$1"
+    local records_file="$TEMP_DIR/dump_range.txt"
+
+    read_dump "$dump_file" > "$records_file" || return 1
+    jq -rs '[.[].block_number] | "\(min // 0) \(max // 0)"' "$records_file"
+    rm -f "$records_file"
+}
+
+# Re-filter the records of an earlier dump (--input-file) instead of fetching: the target,
+# block range, selector, value, gas price, creation, sample and max-results filters apply as
+# they would to a scan, without an RPC call. TARGET_CONTRACTS, START_BLOCK and END_BLOCK may
+# be empty to keep every target and block
+# Usage: refilter_transactions <dump_file> <output_file>
+# Writes sorted records like fetch_transactions, and streams or stores them the same way
+refilter_transactions() {
+    local dump_file="$1"
+    local all_transactions_file="$2"
+
+    local read_file="$TEMP_DIR/dump_records.txt"
+    read_dump "$dump_file" > "$read_file" || return 1
+
+    log info ""
+    log info "=== RE-FILTERING ==="
+    log info "Input: $dump_file ($(wc -l < "$read_file" | tr -d ' ') transactions)"
+    if [[ -n "$TARGET_CONTRACTS" ]]; then
+        log info "Target: ${TARGET_CONTRACTS//,/, }"
+    fi
+    if [[ -n "$START_BLOCK$END_BLOCK" ]]; then
+        log info "Blocks: ${START_BLOCK:-first} to ${END_BLOCK:-last}"
+    fi
+    if [[ -n "$SELECTORS" ]]; then
+        log info "Selectors: ${SELECTORS//,/, }"
+    fi
+    if [[ -n "$CONTRACT_SELECTORS" ]]; then
+        log info "Selectors by contract: ${CONTRACT_SELECTORS//,/, }"
+    fi
+    if [[ -n "$EXCLUDE_FROM" ]]; then
+        log info "Excluded senders: ${EXCLUDE_FROM//,/, }"
+    fi
+    log info ""
+
+    # Calldata left out of the dump (--omit-data) can't be matched against selectors
+    if [[ -n "$SELECTORS$CONTRACT_SELECTORS" ]] && jq -e 'select(has("data") | not)' "$read_file" > /dev/null 2>&1; then
+        log error "Error: --selector needs calldata, which $dump_file does not carry (written with --omit-data?)"
+        return 1
+    fi
+
+    # Amounts compare as 64-digit hex words, like wei_in_range; older dumps wrote a pre-London
+    # base fee as "" rather than null
+    jq -c \
+        --arg targets "$TARGET_CONTRACTS" \
+        --arg start_block "$START_BLOCK" \
+        --arg end_block "$END_BLOCK" \
+        --arg selectors "$SELECTORS" \
+        --arg contract_selectors "$CONTRACT_SELECTORS" \
+        --arg exclude_from "$EXCLUDE_FROM" \
+        --arg min_value "$MIN_VALUE" \
+        --arg max_value "$MAX_VALUE" \
+        --arg min_gas_price "$MIN_GAS_PRICE" \
+        --arg max_gas_price "$MAX_GAS_PRICE" \
+        --argjson include_creations "$INCLUDE_CREATIONS" \
+        --argjson include_pending "$INCLUDE_PENDING" \
+        '
+        def word: ltrimstr("0x") | ascii_downcase | sub("^0+"; "") | ("0" * (64 - length)) + .;
+        def in_range($min; $max): word as $word
+            | ($min == "" or $word >= $min) and ($max == "" or $word <= $max);
+        select($targets == "" or ((.matched_contract // "") | ascii_downcase) as $contract
+            | ($targets | split(",") | index($contract)) != null)
+        | select($start_block == "" or .block_number >= ($start_block | tonumber))
+        | select($end_block == "" or .block_number <= ($end_block | tonumber))
+        | select(.is_creation == true or (((.matched_contract // "") | ascii_downcase) as $contract
+            | ((.data // "")[0:10] | ascii_downcase) as $selector
+            | (($selectors | split(",")) + ($contract_selectors | split(",")
+                | map(select(startswith($contract + ":")) | split(":")[1]))) as $allowed
+            | $allowed == [] or ($allowed | index($selector)) != null))
+        | select($exclude_from == "" or ((.from // "") | ascii_downcase) as $from
+            | ($exclude_from | split(",") | index($from)) == null)
+        | select(.is_creation != true or $include_creations)
+        | select(.pending != true or $include_pending)
+        | select(.value | in_range($min_value; $max_value))
+        | select((.gas_price // .effective_gas_price // null) as $price
+            | $price == null or ($price | in_range($min_gas_price; $max_gas_price)))
+        | if .base_fee_per_gas == "" then .base_fee_per_gas = null else . end
+        ' "$read_file" > "$all_transactions_file.filtered" || return 1
+
+    INTERRUPTED=false
+    SCANNED_TO_BLOCK=$END_BLOCK
+    sample_records "$all_transactions_file.filtered" > "$all_transactions_file"
+    rm -f "$all_transactions_file.filtered" "$read_file"
+
+    : > "$SEEN_HASHES_FILE"
+    drop_duplicate_records "$all_transactions_file"
+    if [[ -s "$all_transactions_file" ]]; then
+        sort_transactions_file "$all_transactions_file"
+    fi
+    if [[ $MAX_RESULTS -gt 0 && $(wc -l < "$all_transactions_file") -gt $MAX_RESULTS ]]; then
+        head -n "$MAX_RESULTS" "$all_transactions_file" > "$all_transactions_file.limited"
+        mv "$all_transactions_file.limited" "$all_transactions_file"
+    fi
+
+    if [[ -n "$ABI_SELECTORS_FILE" && -s "$all_transactions_file" ]]; then
+        decode_transaction_inputs "$all_transactions_file"
+    fi
+    if [[ "$CHECKSUM_ADDRESSES" == "true" && -s "$all_transactions_file" ]]; then
+        checksum_record_addresses "$all_transactions_file" || return 1
+    fi
+
+    if [[ "$STREAM_OUTPUT" == "true" ]]; then
+        stream_begin
+        if [[ -s "$all_transactions_file" ]]; then
+            stream_batch "$all_transactions_file"
+        fi
+    fi
+
+    if [[ -n "$SQLITE_DB" ]]; then
+        sqlite_init
+        if [[ -s "$all_transactions_file" ]]; then
+            sqlite_write_batch "$all_transactions_file"
+        fi
+    fi
+}
+
+# Scan blocks first_block..end_block batch by batch, appending the matches to records_file
+# (streaming, storing and checkpointing each batch as it completes). start_block is where the
+# requested range began, for the progress bar. Runs in the calling shell like fetch_transactions
+# Usage: scan_block_range <start_block> <first_block> <end_block> <records_file>
+scan_block_range() {
+    local start_block="$1"
+    local first_block="$2"
+    local end_block="$3"
+    local all_transactions_file="$4"
+    local target_contracts="$TARGET_CONTRACTS"
+
+    local batch_size=$BATCH_SIZE
+    if [[ "$USE_TRACE_FILTER" == "true" ]]; then
+        batch_size=$TRACE_FILTER_BATCH_SIZE
+    fi
+
+    local batch_id=0
+    local blocks_total=$((end_block - start_block + 1))
+    local resume_output_held=false
+    local run_start_ms
+    run_start_ms=$(now_ms)
+
+    # Block scans fetch through the block pool, so no batch waits for the slowest block of the
+    # one before; --max-results keeps the batch-by-batch fetch so nothing past the limit is fetched
+    if [[ "$USE_TRACE_FILTER" != "true" && "$BATCH_MODE" == "parallel" && $MAX_RESULTS -eq 0 &&
+          $first_block -le $end_block ]]; then
+        block_pool_start "$first_block" "$end_block"
+    fi
+    if [[ "$STREAM_OUTPUT" == "true" || -n "$SQLITE_DB" ]]; then
+        output_writer_start
+    fi
+
+    # Unified batch processing loop
+    for ((batch_start=first_block; batch_start<=end_block; batch_start+=batch_size)); do
+        if [[ "$INTERRUPTED" == "true" ]]; then
+            break
+        fi
+        if [[ $MAX_RESULTS -gt 0 && $RESULTS_COLLECTED -ge $MAX_RESULTS ]]; then
+            progress_clear
+            log info "Reached --max-results $MAX_RESULTS, not fetching blocks $batch_start to $end_block"
+            break
+        fi
+
+        local batch_end=$((batch_start + batch_size - 1))
+        if [[ $batch_end -gt $end_block ]]; then
+            batch_end=$end_block
+        fi
+        progress_clear
+
+        local batch_file="$TEMP_DIR/batch_$batch_id.txt"
+        : > "$batch_file"
+
+        # Call appropriate processing function
+        # Note: We use "|| true" to prevent set -e from exiting on non-zero return codes
+        # The return code is captured in status for fallback handling; the per-batch count
+        # the functions print is only captured here, the records in batch_file are what counts
+        local tx_count
+        if [[ "$USE_TRACE_FILTER" == "true" ]]; then
+            local status=0
+            while true; do
+                if [[ "$TRACE_METHOD" == "trace_filter" ]]; then
+                    tx_count=$(fetch_transactions_trace_filter "$batch_start" "$batch_end" "$target_contracts" "$batch_file") || status=$?
+                    if [[ $status -eq 2 ]]; then
+                        TRACE_METHOD="debug_trace_block"
+                        log info "[TRACE] Falling back to debug_traceBlockByNumber (slower but widely supported)"
+                        status=0
+                        continue
+                    fi
+                elif [[ "$TRACE_METHOD" == "debug_trace_block" ]]; then
+                    tx_count=$(fetch_transactions_debug_trace_block "$batch_start" "$batch_end" "$target_contracts" "$batch_file") || status=$?
+                    if [[ $status -eq 2 ]]; then
+                        TRACE_METHOD="debug_trace_tx"
+                        log info "[TRACE] Falling back to debug_traceTransaction (slowest, per-transaction tracing)"
+                        status=0
+                        continue
+                    fi
+                else
+                    tx_count=$(fetch_transactions_debug_trace_tx "$batch_start" "$batch_end" "$target_contracts" "$batch_file") || status=$?
+                    if [[ $status -eq 2 ]]; then
+                        log warn "[WARN] No trace APIs supported by this RPC - falling back to direct calls only"
+                        log warn "[WARN] Internal/nested calls to target contract will NOT be detected"
+                        log warn "[WARN] Consider using an RPC with debug/trace API support for complete coverage"
+                        TRACE_METHOD=""
+                        tx_count=$(process_batch "$batch_start" "$batch_end" "$target_contracts" "$batch_id" "$CONCURRENCY")
+                    fi
+                fi
+                break
+            done
+            if [[ $status -eq 1 ]]; then
+                record_failed_blocks "$batch_start" "$batch_end"
+            fi
+        else
+            # With --verify-chain a batch that doesn't link up is fetched again from scratch
+            local chain_attempt=0
+            while true; do
+                if [[ $chain_attempt -eq 0 && -n "$BLOCK_POOL_DIR" ]]; then
+                    tx_count=$(collect_pooled_batch "$batch_start" "$batch_end" "$batch_id")
+                else
+                    tx_count=$(process_batch "$batch_start" "$batch_end" "$target_contracts" "$batch_id" "$CONCURRENCY")
+                fi
+                if [[ "$VERIFY_CHAIN" != "true" ]] || verify_chain_links "$batch_start" "$batch_end"; then
+                    break
+                fi
+
+                chain_attempt=$((chain_attempt + 1))
+                if [[ $chain_attempt -gt $MAX_RETRIES ]]; then
+                    progress_clear
+                    log error "Error: Blocks $batch_start to $batch_end still don't link up after $chain_attempt fetches; the chain is reorganizing under the scan (stay further from the tip with --confirmations)"
+                    block_pool_stop
+                    output_writer_stop || true
+                    return 1
+                fi
+                log warn "[REORG] Refetching blocks $batch_start to $batch_end (retry $chain_attempt/$MAX_RETRIES)"
+                : > "$batch_file"
+                awk -v from="$batch_start" -v to="$batch_end" '$1 < from || $1 > to' "$FAILED_BLOCKS_FILE" > "$FAILED_BLOCKS_FILE.kept"
+                mv "$FAILED_BLOCKS_FILE.kept" "$FAILED_BLOCKS_FILE"
+                backoff_sleep "$chain_attempt"
+            done
+        fi
+
+        # Blocks complete out of order, so order the batch before keeping first occurrences
+        if [[ -s "$batch_file" ]]; then
+            sort_transactions_file "$batch_file"
+            drop_duplicate_records "$batch_file"
+        fi
+
+        # Sample and cap the batch before anything is streamed, kept or checkpointed
+        local batch_limited=false
+        if [[ -s "$batch_file" && "$SAMPLE_RATE" != "1" ]]; then
+            sample_records "$batch_file" > "$batch_file.sampled"
+            mv "$batch_file.sampled" "$batch_file"
+        fi
+        if [[ $MAX_RESULTS -gt 0 && -s "$batch_file" ]]; then
+            local remaining=$((MAX_RESULTS - RESULTS_COLLECTED))
+            if [[ $(wc -l < "$batch_file") -ge $remaining ]]; then
+                batch_limited=true
+                head -n "$remaining" "$batch_file" > "$batch_file.limited"
+                mv "$batch_file.limited" "$batch_file"
+            fi
+        fi
+
+        # Ctrl-C also reaches the fetches and jq runs of this batch, so a batch the interrupt cut
+        # into may be missing blocks; it is dropped as a whole before anything of it is kept
+        if [[ "$INTERRUPTED" == "true" ]]; then
+            break
+        fi
+
+        # A resumed file must not run past a gap, so --resume-output writes nothing from the first
+        # batch with a failed block on; the next run picks the file up there
+        if [[ "$RESUME_OUTPUT" == "true" && "$resume_output_held" == "false" && -s "$FAILED_BLOCKS_FILE" ]]; then
+            resume_output_held=true
+            log warn "Warning: A block failed, so $OUTPUT_FILE stops before block $batch_start; rerun with --resume-output to continue it from there"
+        fi
+
+        # Collect results from this batch (flushing it right away when streaming)
+        if [[ -f "$batch_file" && -s "$batch_file" ]]; then
+            if [[ "$STREAM_OUTPUT" == "true" ]]; then
+                if enrichment_enabled; then
+                    enrich_records "$batch_file" "$CONCURRENCY"
+                fi
+                if [[ -n "$ABI_SELECTORS_FILE" && -s "$batch_file" ]]; then
+                    decode_transaction_inputs "$batch_file"
+                fi
+            fi
+            if [[ "$CHECKSUM_ADDRESSES" == "true" && ( "$STREAM_OUTPUT" == "true" || -n "$SQLITE_DB" ) ]]; then
+                checksum_record_addresses "$batch_file" || return 1
+            fi
+            if [[ ( "$STREAM_OUTPUT" == "true" || -n "$SQLITE_DB" ) && "$resume_output_held" == "false" ]]; then
+                if ! output_writer_submit "$batch_file"; then
+                    block_pool_stop
+                    output_writer_stop || true
+                    return 1
+                fi
+            fi
+            cat "$batch_file" >> "$all_transactions_file"
+            RESULTS_COLLECTED=$(wc -l < "$all_transactions_file" | tr -d ' ')
+        fi
+
+        # Batches run one after another, so the checkpoint stays contiguous until the first failed block
+        # (a batch cut short by --max-results may have skipped blocks, so it is not checkpointed)
+        if [[ -n "$CHECKPOINT_FILE" && ! -s "$FAILED_BLOCKS_FILE" && "$batch_limited" == "false" ]]; then
+            checkpoint_advance "$batch_file" "$batch_end"
+        fi
+
+        adaptive_concurrency_update
+
+        SCANNED_TO_BLOCK=$batch_end
+        batch_id=$((batch_id + 1))
+        progress_draw $((batch_end - start_block + 1)) "$blocks_total" $((batch_end - first_block + 1)) "$run_start_ms"
+    done
+    block_pool_stop
+    output_writer_stop || return 1
+    if [[ "$INTERRUPTED" == "true" ]]; then
+        # Blocks of the dropped batch may have failed while being cut off; they were never scanned
+        awk -v last="$SCANNED_TO_BLOCK" '$1 <= last' "$FAILED_BLOCKS_FILE" > "$FAILED_BLOCKS_FILE.kept"
+        mv "$FAILED_BLOCKS_FILE.kept" "$FAILED_BLOCKS_FILE"
+        progress_clear
+        log warn "[INTERRUPT] Stopped after block $SCANNED_TO_BLOCK; keeping the transactions found so far"
+    fi
+    if [[ "$SHOW_PROGRESS" == "true" ]]; then
+        log info ""
+    fi
+}
+
+# Follow the chain tip after fetch_transactions (--follow): subscribe to newHeads on the first
+# ws://, wss:// or ipc:// endpoint and scan every block up to each announced head, streaming the matches.
+# A dropped subscription is reopened with backoff, and the next head also covers the blocks
+# announced while it was down; the seen hashes carry over, so nothing is streamed twice.
+# Returns 0 on SIGINT/SIGTERM (after the range in progress), 1 once reconnecting keeps failing
+# Usage: follow_chain
+follow_chain() {
+    local url=""
+    local candidate
+    for candidate in "${RPC_URLS[@]}"; do
+        if is_connection_url "$candidate"; then
+            url="$candidate"
+            break
+        fi
+    done
+    if [[ -z "$url" ]]; then
+        log error "Error: Following the chain needs a ws://, wss:// or ipc:// endpoint for the newHeads subscription"
+        return 1
+    fi
+
+    local next_block=$((END_BLOCK + 1))
+    SHOW_PROGRESS=false
+    local range_file="$TEMP_DIR/follow_range.txt"
+    local dir="$WS_DIR/follow"
+    mkdir -p "$dir"
+
+    local stop=false
+    local saved_traps
+    saved_traps=$(trap -p INT TERM)
+    trap 'stop=true' INT TERM
+
+    log info "[FOLLOW] Following the chain tip from block $next_block via $(endpoint_label "$url")"
+    local attempt=0
+    while [[ "$stop" != "true" ]]; do
+        rm -f "$dir/in"
+        mkfifo "$dir/in"
+        exec 8<> "$dir/in"
+        echo '{"jsonrpc":"2.0","method":"eth_subscribe","params":["newHeads"],"id":1}' >&8
+
+        # read restarts after a trapped signal, so it waits a second at a time to notice a stop;
+        # a line cut off by the timeout is kept and completed by the next read
+        local message
+        local pending=""
+        while [[ "$stop" != "true" ]]; do
+            local status=0
+            IFS= read -r -t 1 -u 7 message || status=$?
+            if [[ $status -gt 128 ]]; then
+                pending+="$message"
+                continue
+            elif [[ $status -ne 0 ]]; then
+                break
+            fi
+            message="$pending$message"
+            pending=""
+
+            local kind
+            kind=$(echo "$message" | jq -r '
+                if .method == "eth_subscription" then "head \(.params.result.number)"
+                elif .id == 1 and .error then "error \(.error.message // .error)"
+                elif .id == 1 then "subscribed"
+                else "other" end' 2>/dev/null || echo "other")
+
+            case "$kind" in
+                "subscribed")
+                    attempt=0
+                    ;;
+                error*)
+                    log error "Error: eth_subscribe newHeads failed: ${kind#error }"
+                    exec 8>&-
+                    restore_signal_traps "$saved_traps"
+                    return 1
+                    ;;
+                head*)
+                    local head
+                    head=$(hex_to_decimal "${kind#head }") || continue
+                    local tip=$((head - FOLLOW_CONFIRMATIONS))
+                    if [[ $tip -lt $next_block ]]; then
+                        continue
+                    fi
+                    : > "$range_file"
+                    scan_block_range "$next_block" "$next_block" "$tip" "$range_file" || true
+                    if [[ -s "$FAILED_BLOCKS_FILE" ]]; then
+                        log warn "[FOLLOW] Blocks $(sort -n -u "$FAILED_BLOCKS_FILE" | paste -sd, - | sed 's/,/, /g') failed; their transactions are missing from the stream"
+                        : > "$FAILED_BLOCKS_FILE"
+                    fi
+                    log info "[FOLLOW] Scanned blocks $next_block to $tip"
+                    next_block=$((tip + 1))
+                    ;;
+            esac
+        done 7< <(connection_client "$url" <&8 2> /dev/null & echo "$!" > "$dir/pid"; wait)
+        exec 8>&-
+
+        if [[ "$stop" == "true" ]]; then
+            break
+        fi
+        attempt=$((attempt + 1))
+        if [[ $attempt -gt $MAX_RETRIES ]]; then
+            log error "Error: newHeads subscription lost and $MAX_RETRIES reconnects failed"
+            restore_signal_traps "$saved_traps"
+            return 1
+        fi
+        log warn "[FOLLOW] Subscription dropped; reconnecting (retry $attempt/$MAX_RETRIES)"
+        backoff_sleep "$attempt"
+    done
+
+    restore_signal_traps "$saved_traps"
+    log info "[FOLLOW] Stopped; the next block would have been $next_block"
+}
@@ -293,12 +293,9 @@ abstract contract CredibleTestWithBacktesting is CredibleTest, Test {
         string memory output = string(result);
         // Parse transactions
         string memory dataLine = BacktestingUtils.extractDataLine(output);
+        require(bytes(dataLine).length > 0, "Transaction fetcher output contains no payload");
 
-        if (bytes(dataLine).length == 0 || keccak256(bytes(dataLine)) == keccak256(bytes("0"))) {
-            return new BacktestingTypes.TransactionData[](0);
-        }
-
-        // Parse all transactions from the data line
+        // Parse all transactions from the data line ("CSTDv1:0" parses to an empty array)
         transactions = BacktestingUtils.parseMultipleTransactions(dataLine);
     }
 
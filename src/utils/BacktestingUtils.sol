@@ -13,6 +13,9 @@ import {BacktestingTypes} from "./BacktestingTypes.sol";
 library BacktestingUtils {
     using Strings for uint256;
 
+    /// @notice Version header the fetcher prefixes to every simple-format payload
+    string internal constant PAYLOAD_HEADER = "CSTDv1:";
+
     /// @notice Extract transaction data from fetcher output
     function extractDataLine(string memory output) internal pure returns (string memory) {
         bytes memory outputBytes = bytes(output);
@@ -88,9 +91,42 @@ library BacktestingUtils {
         return parts;
     }
 
+    /// @notice Decode a percent-encoded simple-format field ("%7C" -> "|", "%25" -> "%")
+    /// @dev The fetcher encodes these two characters so they can never be mistaken for delimiters
+    function decodeField(string memory field) internal pure returns (string memory) {
+        bytes memory b = bytes(field);
+        bytes memory result = new bytes(b.length);
+        uint256 length = 0;
+
+        for (uint256 i = 0; i < b.length; i++) {
+            if (b[i] == "%" && i + 2 < b.length) {
+                if (b[i + 1] == "7" && (b[i + 2] == "C" || b[i + 2] == "c")) {
+                    result[length++] = "|";
+                    i += 2;
+                    continue;
+                }
+                if (b[i + 1] == "2" && b[i + 2] == "5") {
+                    result[length++] = "%";
+                    i += 2;
+                    continue;
+                }
+            }
+            result[length++] = b[i];
+        }
+
+        assembly {
+            mstore(result, length)
+        }
+        return string(result);
+    }
+
     /// @notice Parse hex or decimal string to uint256
     function stringToUint(string memory str) internal pure returns (uint256) {
         bytes memory b = bytes(str);
+        // Handle empty string (field not reported by the node, e.g. gasPrice) as 0
+        if (b.length == 0) {
+            return 0;
+        }
         if (b.length >= 2 && b[0] == "0" && b[1] == "x") {
             uint256 result = 0;
             for (uint256 i = 2; i < b.length; i++) {
@@ -143,14 +179,27 @@ library BacktestingUtils {
         return data.length >= 4 ? Strings.toHexString(uint32(bytes4(data)), 4) : "N/A";
     }
 
+    /// @notice Split a simple-format payload into its decoded fields (count first)
+    function parsePayloadFields(string memory txDataString) internal pure returns (string[] memory parts) {
+        // A payload without the header is malformed (or from an incompatible fetcher), never "empty"
+        require(startsWith(txDataString, PAYLOAD_HEADER), "Invalid transaction payload: missing CSTDv1 header");
+        uint256 headerLength = bytes(PAYLOAD_HEADER).length;
+        txDataString = substring(txDataString, headerLength, bytes(txDataString).length - headerLength);
+
+        parts = splitString(txDataString, "|");
+        require(parts.length >= 1, "Invalid transaction data format");
+        for (uint256 i = 0; i < parts.length; i++) {
+            parts[i] = decodeField(parts[i]);
+        }
+    }
+
     /// @notice Parse multiple transactions from a single data line
     function parseMultipleTransactions(string memory txDataString)
         internal
         pure
         returns (BacktestingTypes.TransactionData[] memory transactions)
     {
-        string[] memory parts = splitString(txDataString, "|");
-        require(parts.length >= 1, "Invalid transaction data format");
+        string[] memory parts = parsePayloadFields(txDataString);
 
         uint256 count = stringToUint(parts[0]);
 
@@ -202,6 +251,16 @@ library BacktestingUtils {
         }
     }
 
+    /// @notice Decode an abi-format payload (0x-prefixed ABI encoding of TransactionData[])
+    /// @dev Written by `transaction_fetcher.sh --output-format abi`; no delimiters to parse
+    function decodeAbiTransactions(string memory txDataString)
+        internal
+        pure
+        returns (BacktestingTypes.TransactionData[] memory)
+    {
+        return abi.decode(hexStringToBytes(txDataString), (BacktestingTypes.TransactionData[]));
+    }
+
     /// @notice Convert hex character to uint8
     function _hexCharToUint8(bytes1 char) private pure returns (uint8) {
         if (char >= "0" && char <= "9") return uint8(char) - 48;
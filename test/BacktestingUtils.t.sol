@@ -107,9 +107,9 @@ contract BacktestingUtilsTest is Test {
 
     /// @notice Test transaction data parsing from pipe-delimited string
     function testParseMultipleTransactions_Single() public pure {
-        // Format: count|hash|from|to|value|data|blockNumber|txIndex|gasPrice|gasLimit|maxFeePerGas|maxPriorityFeePerGas
+        // Format: CSTDv1:count|hash|from|to|value|data|blockNumber|txIndex|gasPrice|gasLimit|maxFeePerGas|maxPriorityFeePerGas
         string memory txData =
-            "1|0xe5ebeb502ae9ac441fc2912513a7deb9e82bc4d89da91ca41b5fdd51bb96a288|0x6aef8553e34617e65a27bd51ef85c6980c178658|0xcba6a51a78b9b71c8c9db4f0d894e6734afe04f6|0x0|0xa9059cbb|28144849|5|0x3b9aca00|0x5208|0x0|0x0";
+            "CSTDv1:1|0xe5ebeb502ae9ac441fc2912513a7deb9e82bc4d89da91ca41b5fdd51bb96a288|0x6aef8553e34617e65a27bd51ef85c6980c178658|0xcba6a51a78b9b71c8c9db4f0d894e6734afe04f6|0x0|0xa9059cbb|28144849|5|0x3b9aca00|0x5208|0x0|0x0";
 
         BacktestingTypes.TransactionData[] memory txs = BacktestingUtils.parseMultipleTransactions(txData);
 
@@ -123,13 +123,120 @@ contract BacktestingUtilsTest is Test {
         assertEq(txs[0].gasPrice, 1000000000); // 0x3b9aca00 = 1 gwei
     }
 
+    /// @notice Test a gas price the node did not report (empty field) parses as zero
+    function testParseMultipleTransactions_EmptyGasPrice() public pure {
+        string memory txData =
+            "CSTDv1:1|0xe5ebeb502ae9ac441fc2912513a7deb9e82bc4d89da91ca41b5fdd51bb96a288|0x6aef8553e34617e65a27bd51ef85c6980c178658|0xcba6a51a78b9b71c8c9db4f0d894e6734afe04f6|0x0|0xa9059cbb|28144849|5||0x5208|0x6fc23ac00|0x77359400";
+
+        BacktestingTypes.TransactionData[] memory txs = BacktestingUtils.parseMultipleTransactions(txData);
+
+        assertEq(txs.length, 1);
+        assertEq(txs[0].gasPrice, 0);
+        assertEq(txs[0].gasLimit, 0x5208);
+        assertEq(txs[0].maxFeePerGas, 0x6fc23ac00);
+        assertEq(txs[0].maxPriorityFeePerGas, 0x77359400);
+    }
+
+    /// @notice Test decoding an abi-format payload as written by transaction_fetcher.sh
+    function testDecodeAbiTransactions_FetcherPayload() public pure {
+        string memory txData =
+            "0x"
+            "0000000000000000000000000000000000000000000000000000000000000020"
+            "0000000000000000000000000000000000000000000000000000000000000001"
+            "0000000000000000000000000000000000000000000000000000000000000020"
+            "bfe2078a9ebb6373123ddfd0b4ff4c90ddfe6e9df59a7ae5b1c6c79826f75349"
+            "0000000000000000000000001111111111111111111111111111111111111111"
+            "000000000000000000000000aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+            "0000000000000000000000000000000000000000000000000000000000000000"
+            "0000000000000000000000000000000000000000000000000000000000000160"
+            "0000000000000000000000000000000000000000000000000000000000000064"
+            "0000000000000000000000000000000000000000000000000000000000000000"
+            "00000000000000000000000000000000000000000000000000000002cb417800"
+            "000000000000000000000000000000000000000000000000000000000000c350"
+            "00000000000000000000000000000000000000000000000000000006fc23ac00"
+            "0000000000000000000000000000000000000000000000000000000077359400"
+            "0000000000000000000000000000000000000000000000000000000000000024"
+            "a9059cbb00000000000000000000000000000000000000000000000000000000"
+            "0000000000000000000000000000000000000000000000000000000000000000";
+
+        BacktestingTypes.TransactionData[] memory txs = BacktestingUtils.decodeAbiTransactions(txData);
+
+        assertEq(txs.length, 1);
+        assertEq(txs[0].hash, 0xbfe2078a9ebb6373123ddfd0b4ff4c90ddfe6e9df59a7ae5b1c6c79826f75349);
+        assertEq(txs[0].from, 0x1111111111111111111111111111111111111111);
+        assertEq(txs[0].to, 0xaAaAaAaaAaAaAaaAaAAAAAAAAaaaAaAaAaaAaaAa);
+        assertEq(txs[0].value, 0);
+        assertEq(txs[0].data, hex"a9059cbb0000000000000000000000000000000000000000000000000000000000000000");
+        assertEq(txs[0].blockNumber, 100);
+        assertEq(txs[0].transactionIndex, 0);
+        assertEq(txs[0].gasPrice, 0x2cb417800);
+        assertEq(txs[0].gasLimit, 0xc350);
+        assertEq(txs[0].maxFeePerGas, 0x6fc23ac00);
+        assertEq(txs[0].maxPriorityFeePerGas, 0x77359400);
+    }
+
+    /// @notice Test an empty abi-format payload decodes to zero transactions
+    function testDecodeAbiTransactions_Empty() public pure {
+        BacktestingTypes.TransactionData[] memory txs =
+            BacktestingUtils.decodeAbiTransactions(vm.toString(abi.encode(new BacktestingTypes.TransactionData[](0))));
+        assertEq(txs.length, 0);
+    }
+
     /// @notice Test parsing zero transactions
     function testParseMultipleTransactions_Zero() public pure {
-        string memory txData = "0";
+        string memory txData = "CSTDv1:0";
         BacktestingTypes.TransactionData[] memory txs = BacktestingUtils.parseMultipleTransactions(txData);
         assertEq(txs.length, 0);
     }
 
+    /// @notice Test payloads without the version header are rejected instead of parsed as empty
+    function testParseMultipleTransactions_MissingHeader() public {
+        vm.expectRevert("Invalid transaction payload: missing CSTDv1 header");
+        this.parseMultipleTransactions("0");
+
+        vm.expectRevert("Invalid transaction payload: missing CSTDv1 header");
+        this.parseMultipleTransactions("");
+    }
+
+    /// @notice External wrapper so reverts from the internal library call can be expected
+    function parseMultipleTransactions(string memory txData)
+        external
+        pure
+        returns (BacktestingTypes.TransactionData[] memory)
+    {
+        return BacktestingUtils.parseMultipleTransactions(txData);
+    }
+
+    /// @notice Test percent-decoding of simple-format fields
+    function testDecodeField() public pure {
+        assertEq(BacktestingUtils.decodeField("0x1234"), "0x1234");
+        assertEq(BacktestingUtils.decodeField("a%7Cb"), "a|b");
+        assertEq(BacktestingUtils.decodeField("a%7cb"), "a|b");
+        assertEq(BacktestingUtils.decodeField("100%25"), "100%");
+        assertEq(BacktestingUtils.decodeField("%257C"), "%7C");
+        assertEq(BacktestingUtils.decodeField("50%"), "50%");
+        assertEq(BacktestingUtils.decodeField(""), "");
+    }
+
+    /// @notice Test a field containing an encoded pipe round-trips without shifting the other fields
+    function testParseMultipleTransactions_EncodedPipe() public {
+        // Payload as written by the fetcher for calldata containing "|" and "%"
+        string memory txData =
+            "CSTDv1:1|0xe5ebeb502ae9ac441fc2912513a7deb9e82bc4d89da91ca41b5fdd51bb96a288|0x6aef8553e34617e65a27bd51ef85c6980c178658|0xcba6a51a78b9b71c8c9db4f0d894e6734afe04f6|0x0|0x12%7C34%25|28144849|5|0x3b9aca00|0x5208|0x0|0x0";
+
+        // The parser's fields stay aligned and come back decoded
+        string[] memory parts = BacktestingUtils.parsePayloadFields(txData);
+        assertEq(parts.length, 12);
+        assertEq(parts[0], "1");
+        assertEq(parts[5], "0x12|34%");
+        assertEq(BacktestingUtils.stringToUint(parts[6]), 28144849);
+        assertEq(BacktestingUtils.stringToUint(parts[9]), 0x5208);
+
+        // Decoded, that calldata isn't hex, so the transaction itself can't be built from it
+        vm.expectRevert("Invalid hex char");
+        this.parseMultipleTransactions(txData);
+    }
+
     /// @notice Test decoding panic error
     function testDecodeRevertReason_Panic() public pure {
         // Panic(uint256) with code 0x01 (assertion failed)